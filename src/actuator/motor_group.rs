@@ -3,6 +3,8 @@ use alloc::vec::Vec;
 use uom::si::{angular_velocity::*, f64::AngularVelocity};
 use vexide::prelude::Motor;
 
+use crate::hal::DriveMotor;
+
 pub struct MotorGroup {
     motors: Vec<Motor>,
 }
@@ -11,23 +13,50 @@ impl MotorGroup {
     pub fn new(motors: Vec<Motor>) -> Self {
         Self { motors }
     }
+}
 
-    pub fn set_voltage(&mut self, voltage: f64) {
+impl DriveMotor for MotorGroup {
+    fn set_voltage(&mut self, voltage: f64) {
         for motor in self.motors.iter_mut() {
             let _ = motor.set_voltage(voltage);
         }
     }
 
-    pub fn set_velocity(&mut self, velocity: AngularVelocity) {
+    fn set_velocity(&mut self, velocity: AngularVelocity) {
         for motor in self.motors.iter_mut() {
             let _ = motor.set_velocity(velocity.get::<revolution_per_minute>() as i32);
         }
     }
 
-    pub fn position(&self) -> f64 {
-        self.motors
+    /// Average of the motors that can be read, or 0 if none can.
+    fn position(&self) -> f64 {
+        let positions: Vec<f64> = self
+            .motors
             .iter()
             .filter_map(|motor| motor.position().map(|x| x.as_radians()).ok())
-            .sum()
+            .collect();
+
+        if positions.is_empty() {
+            return 0.0;
+        }
+
+        positions.iter().sum::<f64>() / positions.len() as f64
+    }
+
+    /// Average of the motors that can be read.
+    fn velocity(&self) -> Option<AngularVelocity> {
+        let velocities: Vec<f64> = self
+            .motors
+            .iter()
+            .filter_map(|motor| motor.velocity().ok())
+            .collect();
+
+        if velocities.is_empty() {
+            return None;
+        }
+
+        Some(AngularVelocity::new::<revolution_per_minute>(
+            velocities.iter().sum::<f64>() / velocities.len() as f64,
+        ))
     }
 }
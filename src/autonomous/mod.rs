@@ -0,0 +1,166 @@
+//! Autonomous routines the drivers pick from before a match, see [`selector`].
+
+use alloc::vec::Vec;
+
+use crate::localization::{field_map::Alliance, localization::StateRepresentation, mirror::Mirror};
+
+pub mod selector;
+
+/// Tile a routine can start from, described for the red alliance like the field map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StartTile {
+    /// Name from the red driver's point of view, also used to save the selection.
+    pub name: &'static str,
+    /// Name from the blue driver's point of view. Blue is mirrored across the y axis, so left and
+    /// right swap.
+    pub blue_name: &'static str,
+    pub x: f64,
+    pub y: f64,
+    pub heading: f64,
+}
+
+impl StartTile {
+    pub const fn new(name: &'static str, x: f64, y: f64, heading: f64) -> Self {
+        Self {
+            name,
+            blue_name: name,
+            x,
+            y,
+            heading,
+        }
+    }
+
+    pub const fn with_blue_name(mut self, blue_name: &'static str) -> Self {
+        self.blue_name = blue_name;
+        self
+    }
+
+    /// Name of the tile as `alliance`'s driver sees it.
+    pub fn name(&self, alliance: Alliance) -> &'static str {
+        match alliance {
+            Alliance::Red => self.name,
+            Alliance::Blue => self.blue_name,
+        }
+    }
+
+    /// Pose of the robot in the tile on `alliance`'s side of the field.
+    pub fn pose(&self, alliance: Alliance) -> StateRepresentation {
+        Mirror::alliance(alliance).pose(&StateRepresentation::new(self.x, self.y, self.heading))
+    }
+}
+
+/// Autonomous routines by name, in the order the selector lists them.
+pub struct AutonRegistry<R> {
+    routines: Vec<(&'static str, R)>,
+}
+
+impl<R> Default for AutonRegistry<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R> AutonRegistry<R> {
+    pub fn new() -> Self {
+        Self {
+            routines: Vec::new(),
+        }
+    }
+
+    /// Adds a routine to the end of the list.
+    ///
+    /// Panics if the name is taken, the saved selection refers to routines by name.
+    pub fn with_routine(mut self, name: &'static str, routine: R) -> Self {
+        assert!(
+            self.position(name).is_none(),
+            "Autonomous routine {name} is registered twice"
+        );
+
+        self.routines.push((name, routine));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.routines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routines.is_empty()
+    }
+
+    pub fn name(&self, index: usize) -> Option<&'static str> {
+        self.routines.get(index).map(|(name, _)| *name)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&R> {
+        self.routines.get(index).map(|(_, routine)| routine)
+    }
+
+    /// Index of the routine called `name`.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.routines.iter().position(|(other, _)| *other == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f64::consts::PI;
+
+    use super::*;
+    use crate::config::START_TILES;
+
+    #[test]
+    fn mirrors_start_pose_for_blue() {
+        let tile = StartTile::new("Left", -1.5, 0.9, 0.25);
+
+        assert_eq!(
+            tile.pose(Alliance::Red),
+            StateRepresentation::new(-1.5, 0.9, 0.25)
+        );
+
+        let blue = tile.pose(Alliance::Blue);
+        assert_eq!((blue.x, blue.y), (1.5, 0.9));
+        assert!((blue.z - (PI - 0.25)).abs() < 1e-12);
+
+        // Facing the center from either side
+        let tile = StartTile::new("Center", -1.5, 0.0, 0.0);
+        assert!((tile.pose(Alliance::Blue).z - PI).abs() < 1e-12);
+    }
+
+    #[test]
+    fn start_tiles_are_named_from_the_driver() {
+        for alliance in [Alliance::Red, Alliance::Blue] {
+            // Drivers stand behind their alliance wall, facing the center
+            let left = match alliance {
+                Alliance::Red => 1.0,
+                Alliance::Blue => -1.0,
+            };
+
+            for tile in START_TILES {
+                let name = tile.name(alliance).to_lowercase();
+                let side = tile.pose(alliance).y * left;
+
+                assert!(!name.ends_with("left") || side > 0.0, "{alliance:?} {name}");
+                assert!(
+                    !name.ends_with("right") || side < 0.0,
+                    "{alliance:?} {name}"
+                );
+            }
+        }
+
+        let blue_left = START_TILES
+            .iter()
+            .find(|tile| tile.name(Alliance::Blue) == "Left")
+            .unwrap();
+        let pose = blue_left.pose(Alliance::Blue);
+        assert!(pose.x > 0.0 && pose.y < 0.0, "{pose}");
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_duplicate_names() {
+        let _ = AutonRegistry::new()
+            .with_routine("Rush", 0)
+            .with_routine("Rush", 1);
+    }
+}
@@ -0,0 +1,332 @@
+//! Touch-screen menu on the brain for picking the routine, alliance and starting tile.
+//!
+//! The screen is split into one row per choice. Tapping the left half of a row steps back through
+//! its options and tapping the right half steps forward.
+
+use alloc::string::String;
+
+use serde::{Deserialize, Serialize};
+
+use super::{AutonRegistry, StartTile};
+#[cfg(feature = "vexide")]
+use crate::config::{AUTON_SELECTION_PATH, SELECTOR_PERIOD};
+use crate::localization::{field_map::Alliance, localization::StateRepresentation};
+
+/// Brain screen resolution in pixels.
+const SCREEN_WIDTH: i16 = 480;
+const SCREEN_HEIGHT: i16 = 240;
+
+const ROWS: [Row; 3] = [Row::Routine, Row::Alliance, Row::Tile];
+const ROW_HEIGHT: i16 = SCREEN_HEIGHT / ROWS.len() as i16;
+
+/// What the drivers picked, as indices into the registry and the start tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AutonSelection {
+    pub routine: usize,
+    pub alliance: Alliance,
+    pub tile: usize,
+}
+
+/// Selection as saved between program restarts, by name so it survives reordering the registry.
+#[derive(Serialize, Deserialize)]
+struct SavedSelection {
+    routine: String,
+    alliance: Alliance,
+    tile: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Row {
+    Routine,
+    Alliance,
+    Tile,
+}
+
+pub struct AutonSelector<R> {
+    registry: AutonRegistry<R>,
+    tiles: &'static [StartTile],
+    selection: AutonSelection,
+}
+
+impl<R> AutonSelector<R> {
+    /// Starts on the first routine and tile for red.
+    ///
+    /// Panics if there are no routines or tiles to pick from.
+    pub fn new(registry: AutonRegistry<R>, tiles: &'static [StartTile]) -> Self {
+        assert!(!registry.is_empty(), "No autonomous routines to select");
+        assert!(!tiles.is_empty(), "No starting tiles to select");
+
+        Self {
+            registry,
+            tiles,
+            selection: AutonSelection {
+                routine: 0,
+                alliance: Alliance::Red,
+                tile: 0,
+            },
+        }
+    }
+
+    pub fn selection(&self) -> AutonSelection {
+        self.selection
+    }
+
+    pub fn routine(&self) -> &R {
+        self.registry.get(self.selection.routine).unwrap()
+    }
+
+    /// Pose the selected routine starts from, for initializing the localization.
+    pub fn start_pose(&self) -> StateRepresentation {
+        self.tiles[self.selection.tile].pose(self.selection.alliance)
+    }
+
+    /// Handles a tap on the screen. Returns whether it changed the selection.
+    pub fn touch(&mut self, x: i16, y: i16) -> bool {
+        if !(0..SCREEN_WIDTH).contains(&x) || !(0..SCREEN_HEIGHT).contains(&y) {
+            return false;
+        }
+
+        let previous = self.selection;
+        let forward = x >= SCREEN_WIDTH / 2;
+        let step = |index: usize, len: usize| {
+            if forward {
+                (index + 1) % len
+            } else {
+                (index + len - 1) % len
+            }
+        };
+
+        match ROWS[(y / ROW_HEIGHT) as usize] {
+            Row::Routine => {
+                self.selection.routine = step(self.selection.routine, self.registry.len());
+            }
+            Row::Alliance => {
+                self.selection.alliance = match self.selection.alliance {
+                    Alliance::Red => Alliance::Blue,
+                    Alliance::Blue => Alliance::Red,
+                };
+            }
+            Row::Tile => {
+                self.selection.tile = step(self.selection.tile, self.tiles.len());
+            }
+        }
+
+        self.selection != previous
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&SavedSelection {
+            routine: self.registry.name(self.selection.routine).unwrap().into(),
+            alliance: self.selection.alliance,
+            tile: self.tiles[self.selection.tile].name.into(),
+        })
+        .unwrap()
+    }
+
+    /// Restores a selection saved with [`Self::to_json`]. Returns whether it was restored, a
+    /// selection naming a routine or tile that no longer exists is ignored.
+    pub fn restore(&mut self, json: &str) -> bool {
+        let Ok(saved) = serde_json::from_str::<SavedSelection>(json) else {
+            return false;
+        };
+        let Some(routine) = self.registry.position(&saved.routine) else {
+            return false;
+        };
+        let Some(tile) = self.tiles.iter().position(|tile| tile.name == saved.tile) else {
+            return false;
+        };
+
+        self.selection = AutonSelection {
+            routine,
+            alliance: saved.alliance,
+            tile,
+        };
+        true
+    }
+}
+
+#[cfg(feature = "vexide")]
+impl<R> AutonSelector<R> {
+    /// Restores the selection saved on the SD card by the last run, if there is one.
+    pub fn load(&mut self) -> bool {
+        vexide::core::fs::read_to_string(AUTON_SELECTION_PATH).is_ok_and(|json| self.restore(&json))
+    }
+
+    pub fn save(&self) {
+        if vexide::core::fs::write(AUTON_SELECTION_PATH, self.to_json()).is_err() {
+            crate::println!("WARNING: Can't save the autonomous selection");
+        }
+    }
+
+    pub fn draw(&self, screen: &mut vexide::devices::screen::Screen) {
+        use vexide::{
+            devices::screen::{Rect, Text, TextSize},
+            prelude::Rgb,
+        };
+
+        for (index, row) in ROWS.into_iter().enumerate() {
+            let top = index as i16 * ROW_HEIGHT;
+            let background = match (row, self.selection.alliance) {
+                (Row::Alliance, Alliance::Red) => Rgb::new(160, 0, 0),
+                (Row::Alliance, Alliance::Blue) => Rgb::new(0, 0, 160),
+                _ => Rgb::new(40, 40, 40),
+            };
+
+            screen.fill(
+                &Rect::new((0, top), (SCREEN_WIDTH - 1, top + ROW_HEIGHT - 2)),
+                background,
+            );
+
+            let (label, value) = self.label(row);
+            let label = alloc::format!("{label}: {value}");
+            let text_top = top + ROW_HEIGHT / 2 - 10;
+            for (text, x) in [("<", 16), (label.as_str(), 60), (">", SCREEN_WIDTH - 32)] {
+                screen.fill(
+                    &Text::new(text, TextSize::Medium, (x, text_top)),
+                    Rgb::WHITE,
+                );
+            }
+        }
+    }
+
+    fn label(&self, row: Row) -> (&'static str, &'static str) {
+        match row {
+            Row::Routine => (
+                "Routine",
+                self.registry.name(self.selection.routine).unwrap(),
+            ),
+            Row::Alliance => (
+                "Alliance",
+                match self.selection.alliance {
+                    Alliance::Red => "Red",
+                    Alliance::Blue => "Blue",
+                },
+            ),
+            Row::Tile => (
+                "Start",
+                self.tiles[self.selection.tile].name(self.selection.alliance),
+            ),
+        }
+    }
+}
+
+/// Draws the selector and handles taps, saving every change so the selection survives restarting
+/// the program.
+///
+/// Stops once a connected competition leaves disabled, so a stray tap can't change the routine
+/// during the match.
+#[cfg(feature = "vexide")]
+pub async fn run_selector<R>(
+    selector: alloc::rc::Rc<core::cell::RefCell<AutonSelector<R>>>,
+    mut screen: vexide::devices::screen::Screen,
+) {
+    use vexide::core::competition::{self, CompetitionMode};
+
+    let mut press_count = screen.touch_status().press_count;
+    selector.borrow().draw(&mut screen);
+
+    loop {
+        if competition::is_connected() && competition::mode() != CompetitionMode::Disabled {
+            return;
+        }
+
+        let touch = screen.touch_status();
+
+        if touch.press_count != press_count {
+            press_count = touch.press_count;
+
+            let mut selector = selector.borrow_mut();
+            if selector.touch(touch.x, touch.y) {
+                selector.save();
+                selector.draw(&mut screen);
+            }
+        }
+
+        vexide::prelude::sleep(SELECTOR_PERIOD).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TILES: [StartTile; 2] = [
+        StartTile::new("Left", -1.5, 0.9, 0.0),
+        StartTile::new("Right", -1.5, -0.9, 0.0),
+    ];
+
+    fn selector() -> AutonSelector<u8> {
+        AutonSelector::new(
+            AutonRegistry::new()
+                .with_routine("Rush", 1)
+                .with_routine("Safe", 2)
+                .with_routine("Skills", 3),
+            &TILES,
+        )
+    }
+
+    #[test]
+    fn steps_through_options() {
+        let mut selector = selector();
+
+        // Right half of the routine row steps forward, left half back and around
+        assert!(selector.touch(400, 10));
+        assert_eq!(*selector.routine(), 2);
+        assert!(selector.touch(50, 10));
+        assert!(selector.touch(50, 10));
+        assert_eq!(*selector.routine(), 3);
+
+        assert!(selector.touch(400, 100));
+        assert!(selector.touch(400, 200));
+        assert_eq!(
+            selector.selection(),
+            AutonSelection {
+                routine: 2,
+                alliance: Alliance::Blue,
+                tile: 1,
+            }
+        );
+        assert_eq!(selector.start_pose(), TILES[1].pose(Alliance::Blue));
+
+        // Off the screen
+        assert!(!selector.touch(-1, 10));
+        assert!(!selector.touch(10, SCREEN_HEIGHT));
+    }
+
+    #[test]
+    fn single_option_rows_do_not_change() {
+        static TILE: [StartTile; 1] = [StartTile::new("Only", -1.5, 0.0, 0.0)];
+        let mut selector = AutonSelector::new(AutonRegistry::new().with_routine("Only", ()), &TILE);
+
+        assert!(!selector.touch(400, 10));
+        assert!(!selector.touch(400, 200));
+        assert!(selector.touch(400, 100));
+    }
+
+    #[test]
+    fn restores_saved_selection_by_name() {
+        let mut saved = selector();
+        saved.touch(400, 10);
+        saved.touch(400, 100);
+        saved.touch(400, 200);
+        let json = saved.to_json();
+
+        // Reordered and extended since it was saved
+        let mut selector = AutonSelector::new(
+            AutonRegistry::new()
+                .with_routine("Elims", 4)
+                .with_routine("Safe", 2)
+                .with_routine("Rush", 1),
+            &TILES,
+        );
+        assert!(selector.restore(&json));
+        assert_eq!(*selector.routine(), 2);
+        assert_eq!(selector.start_pose(), TILES[1].pose(Alliance::Blue));
+
+        // The saved routine was removed
+        let mut selector = AutonSelector::new(AutonRegistry::new().with_routine("Rush", 1), &TILES);
+        assert!(!selector.restore(&json));
+        assert!(!selector.restore("not json"));
+        assert_eq!(selector.selection().alliance, Alliance::Red);
+    }
+}
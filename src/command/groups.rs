@@ -0,0 +1,428 @@
+//! Commands made of other commands. A group requires everything its members require.
+
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use super::{Command, Requirements};
+
+/// Members of a group that run at the same time.
+struct Members<'a> {
+    commands: Vec<Box<dyn Command + 'a>>,
+    running: Vec<bool>,
+}
+
+impl<'a> Members<'a> {
+    fn new(commands: Vec<Box<dyn Command + 'a>>) -> Self {
+        let mut requirements = Requirements::NONE;
+        for command in &commands {
+            assert!(
+                !requirements.intersects(command.requirements()),
+                "Commands running together can't require the same subsystem"
+            );
+            requirements = requirements.union(command.requirements());
+        }
+
+        Self {
+            running: vec![false; commands.len()],
+            commands,
+        }
+    }
+
+    fn requirements(&self) -> Requirements {
+        self.commands
+            .iter()
+            .fold(Requirements::NONE, |requirements, command| {
+                requirements.union(command.requirements())
+            })
+    }
+
+    fn initialize(&mut self) {
+        for (command, running) in self.commands.iter_mut().zip(self.running.iter_mut()) {
+            command.initialize();
+            *running = true;
+        }
+    }
+
+    /// Executes every running member, returns whether any of them finished.
+    fn execute(&mut self) -> bool {
+        let mut any_finished = false;
+
+        for (command, running) in self.commands.iter_mut().zip(self.running.iter_mut()) {
+            if !*running {
+                continue;
+            }
+
+            command.execute();
+            if command.is_finished() {
+                command.end(false);
+                *running = false;
+                any_finished = true;
+            }
+        }
+
+        any_finished
+    }
+
+    fn all_finished(&self) -> bool {
+        !self.running.contains(&true)
+    }
+
+    fn interrupt(&mut self) {
+        for (command, running) in self.commands.iter_mut().zip(self.running.iter_mut()) {
+            if *running {
+                command.end(true);
+                *running = false;
+            }
+        }
+    }
+}
+
+/// Runs commands one after the other.
+pub struct SequentialGroup<'a> {
+    commands: Vec<Box<dyn Command + 'a>>,
+    current: usize,
+}
+
+impl<'a> SequentialGroup<'a> {
+    pub fn new(commands: Vec<Box<dyn Command + 'a>>) -> Self {
+        let current = commands.len();
+        Self { commands, current }
+    }
+}
+
+impl Command for SequentialGroup<'_> {
+    fn requirements(&self) -> Requirements {
+        self.commands
+            .iter()
+            .fold(Requirements::NONE, |requirements, command| {
+                requirements.union(command.requirements())
+            })
+    }
+
+    fn initialize(&mut self) {
+        self.current = 0;
+
+        if let Some(command) = self.commands.first_mut() {
+            command.initialize();
+        }
+    }
+
+    fn execute(&mut self) {
+        let Some(command) = self.commands.get_mut(self.current) else {
+            return;
+        };
+
+        command.execute();
+        if command.is_finished() {
+            command.end(false);
+
+            self.current += 1;
+            if let Some(next) = self.commands.get_mut(self.current) {
+                next.initialize();
+            }
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.current >= self.commands.len()
+    }
+
+    fn end(&mut self, interrupted: bool) {
+        if interrupted && let Some(command) = self.commands.get_mut(self.current) {
+            command.end(true);
+        }
+        self.current = self.commands.len();
+    }
+
+    fn name(&self) -> &'static str {
+        self.commands
+            .get(self.current)
+            .map_or("SequentialGroup", |command| command.name())
+    }
+}
+
+/// Runs commands together until all of them finish.
+pub struct ParallelGroup<'a> {
+    members: Members<'a>,
+}
+
+impl<'a> ParallelGroup<'a> {
+    pub fn new(commands: Vec<Box<dyn Command + 'a>>) -> Self {
+        Self {
+            members: Members::new(commands),
+        }
+    }
+}
+
+impl Command for ParallelGroup<'_> {
+    fn requirements(&self) -> Requirements {
+        self.members.requirements()
+    }
+
+    fn initialize(&mut self) {
+        self.members.initialize();
+    }
+
+    fn execute(&mut self) {
+        self.members.execute();
+    }
+
+    fn is_finished(&self) -> bool {
+        self.members.all_finished()
+    }
+
+    fn end(&mut self, _interrupted: bool) {
+        self.members.interrupt();
+    }
+}
+
+/// Runs commands together until one of them finishes, interrupting the rest.
+pub struct RaceGroup<'a> {
+    members: Members<'a>,
+    finished: bool,
+}
+
+impl<'a> RaceGroup<'a> {
+    pub fn new(commands: Vec<Box<dyn Command + 'a>>) -> Self {
+        Self {
+            members: Members::new(commands),
+            finished: false,
+        }
+    }
+}
+
+impl Command for RaceGroup<'_> {
+    fn requirements(&self) -> Requirements {
+        self.members.requirements()
+    }
+
+    fn initialize(&mut self) {
+        self.finished = false;
+        self.members.initialize();
+    }
+
+    fn execute(&mut self) {
+        self.finished |= self.members.execute();
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished || self.members.all_finished()
+    }
+
+    fn end(&mut self, _interrupted: bool) {
+        self.members.interrupt();
+    }
+}
+
+/// Runs commands alongside a deadline command until the deadline finishes, interrupting the
+/// rest.
+pub struct DeadlineGroup<'a> {
+    deadline: Box<dyn Command + 'a>,
+    deadline_running: bool,
+    members: Members<'a>,
+}
+
+impl<'a> DeadlineGroup<'a> {
+    pub fn new(deadline: Box<dyn Command + 'a>, others: Vec<Box<dyn Command + 'a>>) -> Self {
+        let members = Members::new(others);
+        assert!(
+            !deadline.requirements().intersects(members.requirements()),
+            "Commands running together can't require the same subsystem"
+        );
+
+        Self {
+            deadline,
+            deadline_running: false,
+            members,
+        }
+    }
+}
+
+impl Command for DeadlineGroup<'_> {
+    fn requirements(&self) -> Requirements {
+        self.deadline
+            .requirements()
+            .union(self.members.requirements())
+    }
+
+    fn initialize(&mut self) {
+        self.deadline.initialize();
+        self.deadline_running = true;
+        self.members.initialize();
+    }
+
+    fn execute(&mut self) {
+        self.members.execute();
+
+        if self.deadline_running {
+            self.deadline.execute();
+            if self.deadline.is_finished() {
+                self.deadline.end(false);
+                self.deadline_running = false;
+            }
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        !self.deadline_running
+    }
+
+    fn end(&mut self, interrupted: bool) {
+        if interrupted && self.deadline_running {
+            self.deadline.end(true);
+            self.deadline_running = false;
+        }
+        self.members.interrupt();
+    }
+
+    fn name(&self) -> &'static str {
+        self.deadline.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{
+        tests::{recorded, Log},
+        SubsystemId,
+    };
+
+    fn run(command: &mut impl Command, ticks: usize) {
+        command.initialize();
+        for _ in 0..ticks {
+            if command.is_finished() {
+                break;
+            }
+            command.execute();
+        }
+    }
+
+    #[test]
+    fn runs_in_sequence() {
+        let log = Log::default();
+        let mut group = recorded("a", Requirements::NONE, 1, &log).and_then(recorded(
+            "b",
+            Requirements::NONE,
+            2,
+            &log,
+        ));
+
+        run(&mut group, 10);
+        assert!(group.is_finished());
+        assert_eq!(
+            *log.borrow(),
+            [
+                "a init",
+                "a execute",
+                "a end",
+                "b init",
+                "b execute",
+                "b execute",
+                "b end"
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_in_parallel() {
+        let log = Log::default();
+        let mut group = recorded("a", Requirements::NONE, 1, &log).along_with(recorded(
+            "b",
+            Requirements::NONE,
+            2,
+            &log,
+        ));
+
+        run(&mut group, 10);
+        assert_eq!(
+            *log.borrow(),
+            [
+                "a init",
+                "b init",
+                "a execute",
+                "a end",
+                "b execute",
+                "b execute",
+                "b end"
+            ]
+        );
+    }
+
+    #[test]
+    fn race_interrupts_the_rest() {
+        let log = Log::default();
+        let mut group = recorded("a", Requirements::NONE, 1, &log).race_with(recorded(
+            "b",
+            Requirements::NONE,
+            5,
+            &log,
+        ));
+
+        run(&mut group, 10);
+        assert!(group.is_finished());
+        group.end(false);
+        assert_eq!(
+            *log.borrow(),
+            [
+                "a init",
+                "b init",
+                "a execute",
+                "a end",
+                "b execute",
+                "b interrupted"
+            ]
+        );
+    }
+
+    #[test]
+    fn deadline_interrupts_the_rest() {
+        let log = Log::default();
+        let mut group = recorded("a", Requirements::NONE, 2, &log).deadline_for(recorded(
+            "b",
+            Requirements::NONE,
+            5,
+            &log,
+        ));
+
+        run(&mut group, 10);
+        group.end(false);
+        assert_eq!(
+            *log.borrow(),
+            [
+                "a init",
+                "b init",
+                "b execute",
+                "a execute",
+                "b execute",
+                "a execute",
+                "a end",
+                "b interrupted"
+            ]
+        );
+    }
+
+    #[test]
+    fn requires_what_members_require() {
+        let log = Log::default();
+        let group = recorded("a", Requirements::of(SubsystemId(0)), 1, &log).and_then(recorded(
+            "b",
+            Requirements::of(SubsystemId(2)),
+            1,
+            &log,
+        ));
+
+        assert_eq!(
+            group.requirements(),
+            Requirements::of(SubsystemId(0)).with(SubsystemId(2))
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn parallel_members_cannot_share_subsystems() {
+        let log = Log::default();
+        let drive = Requirements::of(SubsystemId(0));
+
+        let _ = recorded("a", drive, 1, &log).along_with(recorded("b", drive, 1, &log));
+    }
+}
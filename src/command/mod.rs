@@ -0,0 +1,338 @@
+//! Command-based control in the spirit of WPILib.
+//!
+//! A [`Command`] declares the subsystems it requires, and the [`scheduler::Scheduler`] makes sure
+//! only one command holds a subsystem at a time: scheduling a command interrupts whatever held
+//! its subsystems, and each subsystem's default command takes over again once nothing else
+//! holds it. Commands reach the hardware through
+//! [`SubsystemHandle`](crate::state_machine::runner::SubsystemHandle)s, so they never need
+//! mutable access to the robot.
+
+use alloc::{boxed::Box, vec};
+use core::time::Duration;
+
+use groups::{DeadlineGroup, ParallelGroup, RaceGroup, SequentialGroup};
+
+use crate::{
+    hal::time::Instant,
+    state_machine::{
+        runner::{RequestId, SubsystemHandle},
+        State,
+    },
+};
+
+pub mod groups;
+pub mod scheduler;
+
+/// Identifies one of the robot's subsystems, below 32.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubsystemId(pub u8);
+
+/// Set of subsystems a command needs exclusive use of.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Requirements(u32);
+
+impl Requirements {
+    pub const NONE: Self = Self(0);
+
+    pub fn of(subsystem: SubsystemId) -> Self {
+        Self::NONE.with(subsystem)
+    }
+
+    pub fn with(self, subsystem: SubsystemId) -> Self {
+        assert!(subsystem.0 < 32, "Subsystem ids must be below 32");
+
+        Self(self.0 | 1 << subsystem.0)
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn contains(self, subsystem: SubsystemId) -> bool {
+        self.intersects(Self::of(subsystem))
+    }
+}
+
+pub trait Command {
+    fn requirements(&self) -> Requirements;
+
+    /// Called once when the command is scheduled.
+    fn initialize(&mut self) {}
+
+    /// Called every scheduler tick while the command runs.
+    fn execute(&mut self) {}
+
+    /// Checked after every [`Command::execute`].
+    fn is_finished(&self) -> bool;
+
+    /// Called once when the command finishes or another command interrupts it.
+    fn end(&mut self, _interrupted: bool) {}
+
+    /// Name of the command for logging and status, the type's name by default.
+    fn name(&self) -> &'static str {
+        let name = core::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    /// Runs `next` after this command.
+    fn and_then<'a>(self, next: impl Command + 'a) -> SequentialGroup<'a>
+    where
+        Self: Sized + 'a,
+    {
+        SequentialGroup::new(vec![Box::new(self), Box::new(next)])
+    }
+
+    /// Runs both commands together until both finish.
+    fn along_with<'a>(self, other: impl Command + 'a) -> ParallelGroup<'a>
+    where
+        Self: Sized + 'a,
+    {
+        ParallelGroup::new(vec![Box::new(self), Box::new(other)])
+    }
+
+    /// Runs both commands together until either finishes.
+    fn race_with<'a>(self, other: impl Command + 'a) -> RaceGroup<'a>
+    where
+        Self: Sized + 'a,
+    {
+        RaceGroup::new(vec![Box::new(self), Box::new(other)])
+    }
+
+    /// Runs `other` alongside this command, interrupting it once this command finishes.
+    fn deadline_for<'a>(self, other: impl Command + 'a) -> DeadlineGroup<'a>
+    where
+        Self: Sized + 'a,
+    {
+        DeadlineGroup::new(Box::new(self), vec![Box::new(other)])
+    }
+}
+
+impl<C: Command + ?Sized> Command for Box<C> {
+    fn requirements(&self) -> Requirements {
+        (**self).requirements()
+    }
+
+    fn initialize(&mut self) {
+        (**self).initialize();
+    }
+
+    fn execute(&mut self) {
+        (**self).execute();
+    }
+
+    fn is_finished(&self) -> bool {
+        (**self).is_finished()
+    }
+
+    fn end(&mut self, interrupted: bool) {
+        (**self).end(interrupted);
+    }
+
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+}
+
+/// Runs a state on a subsystem's runner, finishing when the state ends.
+///
+/// `make_state` builds a fresh state every time the command starts, so default commands can
+/// resume after being interrupted.
+pub struct StateCommand<I, O, F> {
+    subsystem: SubsystemId,
+    handle: SubsystemHandle<I, O>,
+    make_state: F,
+    request: Option<RequestId>,
+    name: Option<&'static str>,
+}
+
+impl<I, O, F> StateCommand<I, O, F> {
+    pub fn new(subsystem: SubsystemId, handle: SubsystemHandle<I, O>, make_state: F) -> Self {
+        Self {
+            subsystem,
+            handle,
+            make_state,
+            request: None,
+            name: None,
+        }
+    }
+}
+
+impl<I, O, S: State<I, O> + 'static, F: FnMut() -> S> Command for StateCommand<I, O, F> {
+    fn requirements(&self) -> Requirements {
+        Requirements::of(self.subsystem)
+    }
+
+    fn initialize(&mut self) {
+        let state = (self.make_state)();
+        self.name = Some(state.name());
+        self.request = Some(self.handle.run(state));
+    }
+
+    fn is_finished(&self) -> bool {
+        self.request
+            .is_none_or(|request| self.handle.is_done(request))
+    }
+
+    fn end(&mut self, interrupted: bool) {
+        if interrupted {
+            self.handle.cancel();
+        }
+        self.request = None;
+    }
+
+    fn name(&self) -> &'static str {
+        self.name.unwrap_or("StateCommand")
+    }
+}
+
+/// Calls a function once and finishes.
+pub struct InstantCommand<F> {
+    requirements: Requirements,
+    f: F,
+}
+
+impl<F: FnMut()> InstantCommand<F> {
+    pub fn new(requirements: Requirements, f: F) -> Self {
+        Self { requirements, f }
+    }
+}
+
+impl<F: FnMut()> Command for InstantCommand<F> {
+    fn requirements(&self) -> Requirements {
+        self.requirements
+    }
+
+    fn initialize(&mut self) {
+        (self.f)();
+    }
+
+    fn is_finished(&self) -> bool {
+        true
+    }
+}
+
+/// Does nothing for `duration`, for timing command groups.
+pub struct WaitCommand {
+    duration: Duration,
+    start: Instant,
+}
+
+impl WaitCommand {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Command for WaitCommand {
+    fn requirements(&self) -> Requirements {
+        Requirements::NONE
+    }
+
+    fn initialize(&mut self) {
+        self.start = Instant::now();
+    }
+
+    fn is_finished(&self) -> bool {
+        self.start.elapsed() >= self.duration
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use alloc::{format, rc::Rc, string::String, vec::Vec};
+    use core::cell::{Cell, RefCell};
+
+    use super::*;
+
+    pub type Log = Rc<RefCell<Vec<String>>>;
+
+    /// Logs its lifecycle and finishes after `ticks` executes.
+    pub struct Recorded {
+        pub label: &'static str,
+        pub requirements: Requirements,
+        pub ticks: usize,
+        pub executed: usize,
+        pub log: Log,
+    }
+
+    pub fn recorded(
+        label: &'static str,
+        requirements: Requirements,
+        ticks: usize,
+        log: &Log,
+    ) -> Recorded {
+        Recorded {
+            label,
+            requirements,
+            ticks,
+            executed: 0,
+            log: log.clone(),
+        }
+    }
+
+    impl Command for Recorded {
+        fn requirements(&self) -> Requirements {
+            self.requirements
+        }
+
+        fn initialize(&mut self) {
+            self.executed = 0;
+            self.log.borrow_mut().push(format!("{} init", self.label));
+        }
+
+        fn execute(&mut self) {
+            self.executed += 1;
+            self.log
+                .borrow_mut()
+                .push(format!("{} execute", self.label));
+        }
+
+        fn is_finished(&self) -> bool {
+            self.executed >= self.ticks
+        }
+
+        fn end(&mut self, interrupted: bool) {
+            let event = if interrupted { "interrupted" } else { "end" };
+            self.log
+                .borrow_mut()
+                .push(format!("{} {event}", self.label));
+        }
+
+        fn name(&self) -> &'static str {
+            self.label
+        }
+    }
+
+    #[test]
+    fn combines_requirements() {
+        let drive = Requirements::of(SubsystemId(0));
+        let both = drive.with(SubsystemId(3));
+
+        assert!(both.contains(SubsystemId(3)));
+        assert!(!drive.contains(SubsystemId(3)));
+        assert!(both.intersects(drive));
+        assert!(!Requirements::of(SubsystemId(1)).intersects(both));
+        assert_eq!(drive.union(Requirements::of(SubsystemId(3))), both);
+    }
+
+    #[test]
+    fn instant_command_finishes_immediately() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let mut command =
+            InstantCommand::new(Requirements::NONE, move || counter.set(counter.get() + 1));
+
+        command.initialize();
+        assert!(command.is_finished());
+        assert_eq!(calls.get(), 1);
+    }
+}
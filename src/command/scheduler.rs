@@ -0,0 +1,300 @@
+use alloc::{boxed::Box, vec::Vec};
+
+use super::{Command, Requirements, SubsystemId};
+use crate::{
+    hal::time::{sleep_until, Instant},
+    state_machine::runner::PERIOD,
+};
+
+struct DefaultCommand<'a> {
+    subsystem: SubsystemId,
+    command: Box<dyn Command + 'a>,
+    running: bool,
+}
+
+/// Runs commands, giving each subsystem to at most one of them at a time.
+///
+/// Call [`Scheduler::tick`] every loop, it executes the scheduled commands and starts the default
+/// command of every subsystem no scheduled command holds.
+#[derive(Default)]
+pub struct Scheduler<'a> {
+    scheduled: Vec<Box<dyn Command + 'a>>,
+    defaults: Vec<DefaultCommand<'a>>,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Command to run on `subsystem` whenever nothing else holds it. It may only require that
+    /// subsystem, and replaces the previous default.
+    pub fn set_default_command(&mut self, subsystem: SubsystemId, command: impl Command + 'a) {
+        assert_eq!(
+            command.requirements(),
+            Requirements::of(subsystem),
+            "Default commands must require exactly their subsystem"
+        );
+
+        if let Some(index) = self
+            .defaults
+            .iter()
+            .position(|default| default.subsystem == subsystem)
+        {
+            let mut previous = self.defaults.remove(index);
+            if previous.running {
+                previous.command.end(true);
+            }
+        }
+
+        self.defaults.push(DefaultCommand {
+            subsystem,
+            command: Box::new(command),
+            running: false,
+        });
+    }
+
+    /// Starts `command`, interrupting every command that holds one of its subsystems.
+    pub fn schedule(&mut self, command: impl Command + 'a) {
+        let mut command: Box<dyn Command + 'a> = Box::new(command);
+        let requirements = command.requirements();
+
+        self.scheduled.retain_mut(|scheduled| {
+            if scheduled.requirements().intersects(requirements) {
+                scheduled.end(true);
+                false
+            } else {
+                true
+            }
+        });
+
+        for default in &mut self.defaults {
+            if default.running && default.command.requirements().intersects(requirements) {
+                default.command.end(true);
+                default.running = false;
+            }
+        }
+
+        command.initialize();
+        self.scheduled.push(command);
+    }
+
+    /// Interrupts every scheduled command, the default commands take over on the next tick.
+    pub fn cancel_all(&mut self) {
+        for mut command in self.scheduled.drain(..) {
+            command.end(true);
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.scheduled.retain_mut(|command| {
+            command.execute();
+
+            if command.is_finished() {
+                command.end(false);
+                false
+            } else {
+                true
+            }
+        });
+
+        let held = self.held();
+        for default in &mut self.defaults {
+            if !default.running && !held.intersects(default.command.requirements()) {
+                default.command.initialize();
+                default.running = true;
+            }
+
+            if default.running {
+                default.command.execute();
+
+                // Restarts on the next tick
+                if default.command.is_finished() {
+                    default.command.end(false);
+                    default.running = false;
+                }
+            }
+        }
+    }
+
+    /// Subsystems held by scheduled commands, not counting default commands.
+    fn held(&self) -> Requirements {
+        self.scheduled
+            .iter()
+            .fold(Requirements::NONE, |held, command| {
+                held.union(command.requirements())
+            })
+    }
+
+    /// Name of the command holding `subsystem`, `None` if nothing does.
+    pub fn holder(&self, subsystem: SubsystemId) -> Option<&'static str> {
+        self.scheduled
+            .iter()
+            .find(|command| command.requirements().contains(subsystem))
+            .or_else(|| {
+                self.defaults
+                    .iter()
+                    .find(|default| default.running && default.subsystem == subsystem)
+                    .map(|default| &default.command)
+            })
+            .map(|command| command.name())
+    }
+
+    /// Ticks every [`PERIOD`], forever.
+    pub async fn run(&mut self) {
+        loop {
+            let start = Instant::now();
+            self.tick();
+            sleep_until(start + PERIOD).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{rc::Rc, vec, vec::Vec};
+    use core::cell::RefCell;
+
+    use super::*;
+    use crate::{
+        command::{
+            tests::{recorded, Log},
+            StateCommand,
+        },
+        sim::block_on,
+        state_machine::{
+            runner::{Subsystem, SubsystemRunner},
+            State,
+        },
+    };
+
+    const DRIVE: SubsystemId = SubsystemId(0);
+    const INTAKE: SubsystemId = SubsystemId(1);
+
+    #[test]
+    fn interrupts_conflicting_commands() {
+        let log = Log::default();
+        let mut scheduler = Scheduler::new();
+
+        scheduler.schedule(recorded("a", Requirements::of(DRIVE), 10, &log));
+        scheduler.schedule(recorded("b", Requirements::of(INTAKE), 10, &log));
+        scheduler.tick();
+        assert_eq!(scheduler.holder(DRIVE), Some("a"));
+
+        scheduler.schedule(recorded(
+            "c",
+            Requirements::of(DRIVE).with(SubsystemId(2)),
+            1,
+            &log,
+        ));
+        assert_eq!(scheduler.holder(DRIVE), Some("c"));
+        assert_eq!(scheduler.holder(INTAKE), Some("b"));
+
+        scheduler.tick();
+        assert_eq!(scheduler.holder(DRIVE), None);
+        assert_eq!(
+            *log.borrow(),
+            [
+                "a init",
+                "b init",
+                "a execute",
+                "b execute",
+                "a interrupted",
+                "c init",
+                "b execute",
+                "c execute",
+                "c end"
+            ]
+        );
+    }
+
+    #[test]
+    fn default_commands_resume() {
+        let log = Log::default();
+        let mut scheduler = Scheduler::new();
+        scheduler.set_default_command(
+            DRIVE,
+            recorded("default", Requirements::of(DRIVE), usize::MAX, &log),
+        );
+
+        scheduler.tick();
+        assert_eq!(scheduler.holder(DRIVE), Some("default"));
+
+        scheduler.schedule(recorded("a", Requirements::of(DRIVE), 1, &log));
+        scheduler.tick();
+        scheduler.tick();
+        assert_eq!(
+            *log.borrow(),
+            [
+                "default init",
+                "default execute",
+                "default interrupted",
+                "a init",
+                "a execute",
+                "a end",
+                "default init",
+                "default execute",
+                "default execute"
+            ]
+        );
+
+        scheduler.cancel_all();
+        assert_eq!(scheduler.holder(DRIVE), Some("default"));
+    }
+
+    /// Records each output, `None` where it went idle.
+    struct Recorder(Rc<RefCell<Vec<Option<i32>>>>);
+
+    impl Subsystem for Recorder {
+        type Input = ();
+        type Output = i32;
+
+        async fn read(&mut self) {}
+
+        async fn write(&mut self, output: i32) {
+            self.0.borrow_mut().push(Some(output));
+        }
+
+        async fn idle(&mut self) {
+            self.0.borrow_mut().push(None);
+        }
+    }
+
+    /// Outputs `value` for `ticks` updates.
+    struct Hold(i32, usize);
+
+    impl State<(), i32> for Hold {
+        fn update(&mut self, _: &()) -> Option<i32> {
+            self.1 = self.1.checked_sub(1)?;
+            Some(self.0)
+        }
+    }
+
+    #[test]
+    fn drives_subsystem_runners() {
+        let outputs = Rc::new(RefCell::new(Vec::new()));
+        let mut runner = SubsystemRunner::new(Recorder(outputs.clone()));
+        let handle = runner.handle();
+
+        let mut scheduler = Scheduler::new();
+        scheduler.set_default_command(
+            DRIVE,
+            StateCommand::new(DRIVE, handle.clone(), || Hold(0, usize::MAX)),
+        );
+
+        scheduler.tick();
+        block_on(runner.tick());
+
+        scheduler.schedule(StateCommand::new(DRIVE, handle.clone(), || Hold(1, 2)));
+        assert_eq!(scheduler.holder(DRIVE), Some("Hold"));
+        for _ in 0..4 {
+            block_on(runner.tick());
+            scheduler.tick();
+        }
+
+        assert_eq!(
+            *outputs.borrow(),
+            vec![Some(0), Some(1), Some(1), None, Some(0)]
+        );
+    }
+}
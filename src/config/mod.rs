@@ -1,13 +1,45 @@
 use core::{f64::consts::PI, time::Duration};
 
 use nalgebra::Vector2;
-use uom::si::{f64::Length, length::inch};
+use uom::si::{
+    acceleration::meter_per_second_squared,
+    f64::{Acceleration, Length, Velocity},
+    length::inch,
+    velocity::meter_per_second,
+};
+#[cfg(feature = "vexide")]
 use vexide::devices::geometry::Point2;
 
-use crate::localization::localization::StateRepresentation;
+use crate::{
+    autonomous::StartTile,
+    localization::{
+        field_map::{Alliance, FieldMap},
+        localization::StateRepresentation,
+        predict::tracking_wheel_odometry::TrackingWheelOffsets,
+        sensor::line_tracker::LineCalibration,
+    },
+    motion_control::{feedforward::FeedforwardGains, path::PathConstraints, pid::PidGains},
+};
 
 pub const TELEMETRY_ENABLED: bool = false;
-pub const NUM_PARTICLES: usize = 100;
+
+/// Bounds on the particle filter's particle count, which adapts between them with KLD-sampling.
+pub const MIN_PARTICLES: usize = 50;
+pub const MAX_PARTICLES: usize = 2000;
+
+/// Time a single particle filter update may take, caps the particle count on slower ticks.
+pub static LOCALIZATION_UPDATE_BUDGET: Duration = Duration::from_millis(5);
+
+/// Bound on the KL divergence between the particles and the true belief for KLD-sampling, and the
+/// upper standard normal quantile of the probability the bound holds (0.99).
+pub const KLD_EPSILON: f64 = 0.05;
+pub const KLD_Z: f64 = 2.326;
+
+/// Histogram bin sizes KLD-sampling counts the spread of the particles with.
+pub fn kld_bin_size() -> Length {
+    Length::new::<inch>(4.0)
+}
+pub const KLD_HEADING_BIN_SIZE: f64 = PI / 18.0;
 
 pub fn wheel_diameter() -> Length {
     Length::new::<inch>(2.75)
@@ -15,32 +47,174 @@ pub fn wheel_diameter() -> Length {
 
 pub const DRIVE_RATIO: f64 = 4.0;
 
+/// Whether localization uses the dead wheels in [`tracking_wheel_offsets`] instead of the drive
+/// motor encoders.
+pub const TRACKING_WHEELS: bool = false;
+
+pub fn tracking_wheel_diameter() -> Length {
+    Length::new::<inch>(2.0)
+}
+
+/// Positions of the dead wheels for [`TrackingWheelOdometry`], measured from the center of
+/// rotation.
+///
+/// [`TrackingWheelOdometry`]: crate::localization::predict::tracking_wheel_odometry::TrackingWheelOdometry
+pub fn tracking_wheel_offsets() -> TrackingWheelOffsets {
+    TrackingWheelOffsets {
+        left: Length::new::<inch>(4.5),
+        right: Length::new::<inch>(-4.5),
+        perpendicular: Length::new::<inch>(-2.0),
+    }
+}
+
 pub static LOCALIZATION_MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(5000);
 
 pub fn localization_min_update_distance() -> Length {
     Length::new::<inch>(2.0)
 }
 
+/// Most a single sensor can lower a particle's log-likelihood below the best particle's, so a
+/// reading that's zero or missing for some particles can't collapse every weight at once.
+pub const SENSOR_LOG_LIKELIHOOD_RANGE: f64 = 20.0;
+
+/// Resample once the effective sample size drops below this fraction of the particle count.
+pub const RESAMPLE_ESS_RATIO: f64 = 0.5;
+
+/// Smoothing rates of the long and short term average likelihood used to decide how many random
+/// particles to inject (augmented MCL).
+pub const AUGMENTED_MCL_SLOW_RATE: f64 = 0.05;
+pub const AUGMENTED_MCL_FAST_RATE: f64 = 0.5;
+
+/// Largest position standard deviation at which the localization is considered converged.
+pub fn converged_position_std() -> Length {
+    Length::new::<inch>(2.0)
+}
+
+/// Largest heading standard deviation at which the localization is considered converged.
+pub const CONVERGED_HEADING_STD: f64 = PI / 36.0;
+
+/// Spread of the belief around the selected start tile at the start of autonomous. Tighter than
+/// [`converged_position_std`], since the robot is placed by hand against the tile's marks.
+pub fn start_position_std() -> Length {
+    Length::new::<inch>(1.0)
+}
+
+/// Spread of the heading around the selected start tile at the start of autonomous.
+pub const START_HEADING_STD: f64 = PI / 90.0;
+
 pub const FIELD_SIZE: f64 = 3.566414;
 pub const FIELD_MAX: f64 = FIELD_SIZE / 2.0;
+pub const TILE_SIZE: f64 = FIELD_SIZE / 6.0;
+
+/// Tiles the autonomous routines can start from, against the red alliance wall and facing the
+/// center of the field. Named from each driver's point of view.
+pub const START_TILES: [StartTile; 4] = [
+    StartTile::new("Far left", START_TILE_X, 1.5 * TILE_SIZE, 0.0).with_blue_name("Far right"),
+    StartTile::new("Left", START_TILE_X, 0.5 * TILE_SIZE, 0.0).with_blue_name("Right"),
+    StartTile::new("Right", START_TILE_X, -0.5 * TILE_SIZE, 0.0).with_blue_name("Left"),
+    StartTile::new("Far right", START_TILE_X, -1.5 * TILE_SIZE, 0.0).with_blue_name("Far left"),
+];
+const START_TILE_X: f64 = -FIELD_MAX + TILE_SIZE / 2.0;
+
+/// File on the SD card the autonomous selection is kept in between program restarts.
+pub const AUTON_SELECTION_PATH: &str = "auton.json";
+/// How often the selector checks the screen for taps.
+pub static SELECTOR_PERIOD: Duration = Duration::from_millis(20);
 
 pub const ANGLE_NOISE: f64 = PI / 20.0;
 pub const DRIVE_NOISE: f64 = 0.1;
 
-pub const LINE_SENSOR_THRESHOLD: f64 = 0.2;
+/// Spread the particle filter adds to each particle's heading per update, relative to the turn
+/// the IMU saw plus a constant drift, so heading hypotheses stay apart after resampling.
+pub const HEADING_NOISE: f64 = 0.02;
+pub const HEADING_DRIFT_NOISE: f64 = 0.002;
 
-pub const FIELD_TAPES: [(Vector2<f64>, Vector2<f64>); 2] = [
-    (Vector2::new(0.0, 0.0), Vector2::new(1.0, 0.0)),
-    (Vector2::new(0.0, 0.0), Vector2::new(0.0, 1.0)),
-];
+/// Largest difference in the heading change two IMUs, or an IMU and the wheels, can see in one
+/// update before one of them is rejected as an outlier, in radians.
+pub const IMU_OUTLIER_THRESHOLD: f64 = 0.05;
 
-pub const WALLS: [(Vector2<f64>, Vector2<f64>); 2] = [
-    (Vector2::new(0.0, 0.0), Vector2::new(1.0, 0.0)),
-    (Vector2::new(0.0, 0.0), Vector2::new(0.0, 1.0)),
-];
+/// Starting line tracker calibration, the tile reading is recalibrated at startup.
+pub const LINE_TRACKER_CALIBRATION: LineCalibration = LineCalibration {
+    tile: 0.1,
+    tape: 0.3,
+};
+
+/// How precisely crossing the edge of a tape locates the line tracker.
+pub fn line_edge_std() -> Length {
+    Length::new::<inch>(0.5)
+}
+
+/// Field geometry the sensor models measure against, mirrored for the blue alliance.
+pub fn field_map(alliance: Alliance) -> FieldMap {
+    FieldMap::from_json(include_str!("../../bins/field/field.json"))
+        .expect("Bundled field description is invalid")
+        .for_alliance(alliance)
+}
 
 pub const GPS_ANGLE_DIFF_MAX: f64 = PI / 8.0;
 
+/// Default exit conditions of the closed loop moves: how long they have to stay within tolerance,
+/// and how long they may take at most.
+pub static MOTION_SETTLE_TIME: Duration = Duration::from_millis(250);
+pub static MOTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn motion_settle_tolerance() -> Length {
+    Length::new::<inch>(1.0)
+}
+pub const MOTION_SETTLE_HEADING_TOLERANCE: f64 = PI / 90.0;
+
+/// Boomerang gains, forward speed per meter to the carrot and turn rate per radian of heading
+/// error.
+pub const BOOMERANG_LINEAR_GAIN: f64 = 2.0;
+pub const BOOMERANG_ANGULAR_GAIN: f64 = 6.0;
+
+/// Distance from the target at which boomerang stops chasing the carrot and turns to the target
+/// heading.
+pub fn boomerang_close_distance() -> Length {
+    Length::new::<inch>(6.0)
+}
+
+/// Gains of the voltage PID moves, volts per radian of heading error and per meter of distance.
+pub const TURN_PID: PidGains = PidGains {
+    kp: 10.0,
+    ki: 2.0,
+    kd: 0.8,
+};
+pub const DRIVE_PID: PidGains = PidGains {
+    kp: 30.0,
+    ki: 4.0,
+    kd: 3.0,
+};
+
+/// Most voltage the integral term of the PID moves can add, and how strongly their derivative is
+/// smoothed.
+pub const PID_INTEGRAL_LIMIT: f64 = 3.0;
+pub const PID_DERIVATIVE_FILTER: f64 = 0.5;
+
+/// Velocity feedforward and feedback of each side of the drivetrain, per motor shaft rad/s.
+pub const LEFT_DRIVE_FEEDFORWARD: FeedforwardGains = FeedforwardGains {
+    ks: 0.6,
+    kv: 0.19,
+    ka: 0.02,
+    kp: 0.05,
+};
+pub const RIGHT_DRIVE_FEEDFORWARD: FeedforwardGains = FeedforwardGains {
+    ks: 0.6,
+    kv: 0.19,
+    ka: 0.02,
+    kp: 0.05,
+};
+
+/// Limits paths generated on the robot are timed with.
+pub fn path_constraints() -> PathConstraints {
+    PathConstraints {
+        max_velocity: Velocity::new::<meter_per_second>(1.2),
+        max_acceleration: Acceleration::new::<meter_per_second_squared>(2.0),
+        max_lateral_acceleration: Acceleration::new::<meter_per_second_squared>(1.5),
+        track_width: track_width(),
+    }
+}
+
 pub const LIFT_RATIO: f64 = 8.0;
 pub const INTAKE_RATIO: f64 = 16.5 / 6.0;
 
@@ -48,14 +222,16 @@ pub fn track_width() -> Length {
     Length::new::<inch>(10.0)
 }
 
-pub fn distance_threshold() -> Length {
-    Length::new::<inch>(1.0)
-}
-
+#[cfg(feature = "vexide")]
 pub fn get_gps_offset() -> Point2<f64> {
     Point2::new(0.2, 0.2)
 }
 
+/// Readings past this are treated as the sensor seeing nothing.
+pub fn distance_sensor_max_range() -> Length {
+    Length::new::<inch>(78.0)
+}
+
 pub fn get_distance_1_offset() -> StateRepresentation {
     StateRepresentation::new(0.0, 0.0, 0.0)
 }
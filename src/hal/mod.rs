@@ -0,0 +1,61 @@
+//! Hardware abstraction layer for the drivetrain and localization devices.
+//!
+//! Localization, motion control and the drivetrain only talk to hardware through these traits,
+//! so they run the same against vexide devices ([`v5`]) and the simulated backend
+//! ([`crate::sim`]).
+
+use nalgebra::Vector2;
+use uom::si::f64::{Angle, AngularVelocity, Length};
+
+pub mod sync;
+pub mod time;
+#[cfg(feature = "vexide")]
+pub mod v5;
+
+/// One side of a drivetrain, usually a [`crate::actuator::motor_group::MotorGroup`].
+pub trait DriveMotor {
+    fn set_voltage(&mut self, voltage: f64);
+    fn set_velocity(&mut self, velocity: AngularVelocity);
+
+    /// Motor shaft position in radians.
+    fn position(&self) -> f64;
+
+    /// Motor shaft velocity, `None` if it can't be read.
+    fn velocity(&self) -> Option<AngularVelocity>;
+}
+
+/// Source of the absolute heading of the robot.
+pub trait Imu {
+    async fn calibrate(&mut self);
+
+    /// Heading of the robot, counter-clockwise positive. `None` if the sensor is unavailable.
+    fn heading(&self) -> Option<Angle>;
+}
+
+/// Time-of-flight distance sensor.
+pub trait RangeFinder {
+    /// Distance to the detected object, `None` if nothing is in range.
+    fn distance(&self) -> Option<Length>;
+
+    /// Confidence of the current reading from 0.0 to 1.0.
+    fn confidence(&self) -> Option<f64>;
+
+    /// Relative size of the detected object, used to reject readings off small objects.
+    fn object_size(&self) -> Option<u32>;
+}
+
+/// Reflectivity sensor pointed at the field tiles.
+pub trait Reflectance {
+    /// Reflectivity of the surface below the sensor from 0.0 to 1.0.
+    fn reflectivity(&self) -> Option<f64>;
+}
+
+/// Absolute position sensor, e.g. the VEX GPS.
+pub trait Gps {
+    /// Position of the robot in meters, and heading in counter-clockwise radians from the +x axis
+    /// wrapped into [0, TAU). `None` if the reading is unusable.
+    fn pose(&self) -> Option<(Vector2<f64>, f64)>;
+
+    /// Estimated error of the position reading in meters.
+    fn error(&self) -> Option<f64>;
+}
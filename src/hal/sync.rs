@@ -0,0 +1,9 @@
+//! Async mutex for devices shared between tasks, like the drive motors read by localization.
+//!
+//! On the brain this is the vexide mutex, otherwise it's [`crate::sim::sync::Mutex`].
+
+#[cfg(feature = "vexide")]
+pub use vexide::core::sync::Mutex;
+
+#[cfg(not(feature = "vexide"))]
+pub use crate::sim::sync::Mutex;
@@ -0,0 +1,13 @@
+//! Monotonic clock and sleeps for timing control loops and localization updates.
+//!
+//! On the brain this is the vexide clock, otherwise it's the simulated clock driven by
+//! [`crate::sim::clock::advance`].
+
+#[cfg(feature = "vexide")]
+pub use vexide::{
+    core::time::Instant,
+    prelude::{sleep, sleep_until},
+};
+
+#[cfg(not(feature = "vexide"))]
+pub use crate::sim::clock::{sleep, sleep_until, Instant};
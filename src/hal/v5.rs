@@ -0,0 +1,66 @@
+use nalgebra::Vector2;
+use uom::si::{
+    angle::{degree, radian},
+    f64::{Angle, Length},
+    length::millimeter,
+};
+use vexide::prelude::{AdiLineTracker, DistanceSensor, GpsSensor, InertialSensor};
+
+use super::{Gps, Imu, RangeFinder, Reflectance};
+use crate::utils::wrap_angle;
+
+const GPS_BAD_BITFLAG: u32 = 0b0000_0100_0000_0000;
+
+impl Imu for InertialSensor {
+    async fn calibrate(&mut self) {
+        let _ = InertialSensor::calibrate(self).await;
+    }
+
+    fn heading(&self) -> Option<Angle> {
+        // The IMU reports clockwise degrees
+        InertialSensor::heading(self)
+            .ok()
+            .map(|heading| Angle::new::<degree>(-heading))
+    }
+}
+
+impl RangeFinder for DistanceSensor {
+    fn distance(&self) -> Option<Length> {
+        DistanceSensor::distance(self)
+            .ok()?
+            .map(|distance| Length::new::<millimeter>(distance as f64))
+    }
+
+    fn confidence(&self) -> Option<f64> {
+        self.distance_confidence().ok()
+    }
+
+    fn object_size(&self) -> Option<u32> {
+        self.relative_size().ok()?
+    }
+}
+
+impl Reflectance for AdiLineTracker {
+    fn reflectivity(&self) -> Option<f64> {
+        AdiLineTracker::reflectivity(self).ok()
+    }
+}
+
+impl Gps for GpsSensor {
+    fn pose(&self) -> Option<(Vector2<f64>, f64)> {
+        if self.status().ok()? & GPS_BAD_BITFLAG != 0 {
+            return None;
+        }
+
+        let pose = GpsSensor::pose(self).ok()?;
+
+        // The GPS reports clockwise degrees from the +y axis
+        let heading = Angle::new::<degree>(90.0 - pose.1).get::<radian>();
+
+        Some((Vector2::new(pose.0.x, pose.0.y), wrap_angle(heading)))
+    }
+
+    fn error(&self) -> Option<f64> {
+        GpsSensor::error(self).ok()
+    }
+}
@@ -0,0 +1,35 @@
+#![no_std]
+#![feature(let_chains)]
+#![allow(async_fn_in_trait)]
+// Float methods resolve to std's inherent ones when tests link std
+#![cfg_attr(test, allow(unused_imports))]
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+#[cfg(feature = "vexide")]
+pub mod actuator;
+pub mod autonomous;
+pub mod command;
+pub mod config;
+pub mod hal;
+pub mod localization;
+pub mod motion_control;
+pub mod sensor;
+pub mod sim;
+pub mod state_machine;
+pub mod subsystems;
+pub mod utils;
+
+#[cfg(feature = "vexide")]
+pub(crate) use vexide::core::println;
+
+/// Stands in for the brain's terminal when built without vexide.
+#[cfg(not(feature = "vexide"))]
+macro_rules! println {
+    ($($arg:tt)*) => {{
+        let _ = format_args!($($arg)*);
+    }};
+}
+#[cfg(not(feature = "vexide"))]
+pub(crate) use println;
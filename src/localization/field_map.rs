@@ -0,0 +1,276 @@
+//! Static geometry of the field that the sensor models and the simulator measure against.
+
+use alloc::{string::String, vec::Vec};
+
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+use uom::si::{f64::Length, length::meter};
+
+use super::mirror::Mirror;
+use crate::utils::{point_segment_distance, ray_segment_intersection};
+
+/// Side of the field a match is played from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Alliance {
+    Red,
+    Blue,
+}
+
+/// Line segment with the bounding circle used to skip it in queries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub start: Vector2<f64>,
+    pub end: Vector2<f64>,
+    center: Vector2<f64>,
+    radius: f64,
+}
+
+impl Segment {
+    pub fn new(start: Vector2<f64>, end: Vector2<f64>) -> Self {
+        Self {
+            start,
+            end,
+            center: (start + end) / 2.0,
+            radius: (end - start).magnitude() / 2.0,
+        }
+    }
+
+    /// Lower bound on the distance from `point` to anywhere on the segment.
+    fn min_distance(&self, point: &Vector2<f64>) -> f64 {
+        ((point - self.center).magnitude() - self.radius).max(0.0)
+    }
+
+    fn mirrored(&self, mirror: Mirror) -> Self {
+        Self::new(mirror.point(self.start), mirror.point(self.end))
+    }
+}
+
+/// Perimeter, tape lines and static obstacles of the field, in field coordinates.
+///
+/// The description is written for the red alliance, [`FieldMap::for_alliance`] mirrors it for
+/// blue.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldMap {
+    /// Everything that blocks a distance sensor: the perimeter and the obstacle outlines.
+    walls: Vec<Segment>,
+    tapes: Vec<Segment>,
+    tape_width: Length,
+}
+
+#[derive(Deserialize)]
+struct PointDescription {
+    x: f64,
+    y: f64,
+}
+
+impl From<&PointDescription> for Vector2<f64> {
+    fn from(point: &PointDescription) -> Self {
+        Vector2::new(point.x, point.y)
+    }
+}
+
+#[derive(Deserialize)]
+struct TapeDescription {
+    start: PointDescription,
+    end: PointDescription,
+}
+
+#[derive(Deserialize)]
+struct ObstacleDescription {
+    #[allow(dead_code)]
+    name: String,
+    outline: Vec<PointDescription>,
+}
+
+#[derive(Deserialize)]
+struct FieldDescription {
+    tape_width: f64,
+    perimeter: Vec<PointDescription>,
+    tapes: Vec<TapeDescription>,
+    obstacles: Vec<ObstacleDescription>,
+}
+
+/// Edges of a closed polygon.
+fn outline_segments(outline: &[PointDescription]) -> impl Iterator<Item = Segment> + '_ {
+    outline
+        .iter()
+        .zip(outline.iter().cycle().skip(1))
+        .map(|(start, end)| Segment::new(start.into(), end.into()))
+}
+
+impl FieldMap {
+    /// Parses a field description, see `bins/field/field.json` for the format.
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        let description: FieldDescription = serde_json::from_str(json)?;
+
+        let mut walls: Vec<Segment> = outline_segments(&description.perimeter).collect();
+        for obstacle in description.obstacles.iter() {
+            walls.extend(outline_segments(&obstacle.outline));
+        }
+
+        Ok(Self {
+            walls,
+            tapes: description
+                .tapes
+                .iter()
+                .map(|tape| Segment::new((&tape.start).into(), (&tape.end).into()))
+                .collect(),
+            tape_width: Length::new::<meter>(description.tape_width),
+        })
+    }
+
+    /// The same field with every segment mirrored.
+    pub fn mirrored(&self, mirror: Mirror) -> Self {
+        Self {
+            walls: self
+                .walls
+                .iter()
+                .map(|wall| wall.mirrored(mirror))
+                .collect(),
+            tapes: self
+                .tapes
+                .iter()
+                .map(|tape| tape.mirrored(mirror))
+                .collect(),
+            tape_width: self.tape_width,
+        }
+    }
+
+    pub fn for_alliance(self, alliance: Alliance) -> Self {
+        match Mirror::alliance(alliance) {
+            Mirror::Identity => self,
+            mirror => self.mirrored(mirror),
+        }
+    }
+
+    pub fn walls(&self) -> &[Segment] {
+        &self.walls
+    }
+
+    pub fn tapes(&self) -> &[Segment] {
+        &self.tapes
+    }
+
+    pub fn tape_width(&self) -> Length {
+        self.tape_width
+    }
+
+    /// Distance along the ray from `origin` towards `direction` to the closest wall or obstacle.
+    pub fn ray_cast(&self, origin: &Vector2<f64>, direction: &Vector2<f64>) -> Option<f64> {
+        let direction = direction.normalize();
+        let mut closest: Option<f64> = None;
+
+        for wall in self.walls.iter() {
+            // Can't beat the closest hit so far
+            if let Some(closest) = closest
+                && wall.min_distance(origin) >= closest
+            {
+                continue;
+            }
+
+            if let Some(distance) =
+                ray_segment_intersection(origin, &direction, &wall.start, &wall.end)
+                && closest.is_none_or(|closest| distance < closest)
+            {
+                closest = Some(distance);
+            }
+        }
+
+        closest
+    }
+
+    /// Distance from `point` to the closest tape line, `None` if the field has no tape.
+    pub fn nearest_tape(&self, point: &Vector2<f64>) -> Option<f64> {
+        let mut closest: Option<f64> = None;
+
+        for tape in self.tapes.iter() {
+            if let Some(closest) = closest
+                && tape.min_distance(point) >= closest
+            {
+                continue;
+            }
+
+            let distance = point_segment_distance(point, &tape.start, &tape.end);
+            if closest.is_none_or(|closest| distance < closest) {
+                closest = Some(distance);
+            }
+        }
+
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{field_map, FIELD_MAX};
+
+    #[test]
+    fn ray_cast_hits_closest_wall() {
+        let field = field_map(Alliance::Red);
+
+        // Straight at the perimeter from the corner of the field, missing the ladder
+        let distance = field
+            .ray_cast(&Vector2::new(-1.5, -1.5), &Vector2::new(1.0, 0.0))
+            .unwrap();
+        assert!((distance - (FIELD_MAX + 1.5)).abs() < 1e-9);
+
+        // Through the center, stopping at the ladder
+        let distance = field
+            .ray_cast(&Vector2::new(-1.5, 0.0), &Vector2::new(2.0, 0.0))
+            .unwrap();
+        assert!((distance - 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn nearest_tape_distance() {
+        let field = field_map(Alliance::Red);
+
+        let distance = field.nearest_tape(&Vector2::new(0.3, 0.0)).unwrap();
+        assert!((distance - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn blue_is_mirrored_red() {
+        // Only on red's side, so mirroring it can't go unnoticed like on the symmetric field
+        let red = FieldMap::from_json(
+            r#"{
+                "tape_width": 0.05,
+                "perimeter": [
+                    { "x": -1.0, "y": -1.0 },
+                    { "x": 1.0, "y": -1.0 },
+                    { "x": 1.0, "y": 1.0 },
+                    { "x": -1.0, "y": 1.0 }
+                ],
+                "tapes": [{ "start": { "x": -0.8, "y": 0.5 }, "end": { "x": -0.2, "y": 0.5 } }],
+                "obstacles": [{
+                    "name": "Red goal",
+                    "outline": [
+                        { "x": -0.6, "y": -0.6 },
+                        { "x": -0.4, "y": -0.6 },
+                        { "x": -0.5, "y": -0.4 }
+                    ]
+                }]
+            }"#,
+        )
+        .unwrap();
+        let blue = red.clone().for_alliance(Alliance::Blue);
+
+        assert_eq!(red.clone().for_alliance(Alliance::Red), red);
+        assert_eq!(
+            blue.tapes(),
+            [Segment::new(Vector2::new(0.8, 0.5), Vector2::new(0.2, 0.5))]
+        );
+
+        // The goal is hit from the other side of the field
+        let from_center =
+            |field: &FieldMap| field.ray_cast(&Vector2::new(0.0, -0.5), &Vector2::new(-1.0, 0.0));
+        assert!((from_center(&red).unwrap() - 0.45).abs() < 1e-9);
+        assert!((from_center(&blue).unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(
+            red.nearest_tape(&Vector2::new(-0.5, 0.5)),
+            blue.nearest_tape(&Vector2::new(0.5, 0.5))
+        );
+        assert!(blue.nearest_tape(&Vector2::new(-0.5, 0.5)).unwrap() > 0.5);
+    }
+}
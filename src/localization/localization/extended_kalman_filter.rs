@@ -0,0 +1,366 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use nalgebra::{DMatrix, DVector, Matrix2, Matrix3, Matrix3x2, Rotation2};
+use uom::num_traits::Float;
+
+use super::{Localization, MeasurementModel, StateRepresentation};
+use crate::{
+    config::FIELD_MAX, localization::predict::MotionModel, sensor::imu::HeadingHealth,
+    utils::wrap_angle,
+};
+
+/// Step used for the numerical jacobian of the measurement models.
+const JACOBIAN_STEP: f64 = 1e-4;
+
+/// Squared Mahalanobis distance above which a measurement is treated as an outlier.
+const INNOVATION_GATE: f64 = 16.0;
+
+/// Extended Kalman filter over the same predictor and measurement models as
+/// [`super::particle_filter::ParticleFilter`].
+///
+/// Much cheaper than the particle filter, but it keeps a single gaussian hypothesis, so it needs a
+/// reasonable initial pose and can't recover from being badly wrong.
+pub struct ExtendedKalmanFilter<M: MotionModel> {
+    state: StateRepresentation,
+    covariance: Matrix3<f64>,
+    sensors: Vec<Box<dyn MeasurementModel>>,
+    predictor: M,
+}
+
+impl<M: MotionModel> ExtendedKalmanFilter<M> {
+    pub fn new(predictor: M) -> Self {
+        Self {
+            state: StateRepresentation::new(0.0, 0.0, 0.0),
+            covariance: Matrix3::from_diagonal_element(FIELD_MAX * FIELD_MAX),
+            sensors: Vec::new(),
+            predictor,
+        }
+    }
+
+    pub fn add_sensor(&mut self, sensor: impl MeasurementModel + 'static) {
+        self.sensors.push(Box::new(sensor));
+    }
+
+    /// Starts from the center of the box with the variance of a uniform distribution over it.
+    pub fn init_uniform(&mut self, min: &StateRepresentation, max: &StateRepresentation) {
+        let range = max - min;
+
+        self.state = (min + max) / 2.0;
+        self.covariance = Matrix3::from_diagonal(&range.component_mul(&range).scale(1.0 / 12.0));
+    }
+
+    fn predict(&mut self) {
+        let heading_delta = self.predictor.heading_delta();
+        // The predictor's heading is relative to wherever the IMU was zeroed, so only its change
+        // is used and the step is turned from the predictor's heading onto our own
+        let predictor_heading = self.predictor.heading().angle() - heading_delta;
+        let displacement =
+            Rotation2::new(self.state.z - predictor_heading) * self.predictor.mean_displacement();
+
+        self.state.x += displacement.x;
+        self.state.y += displacement.y;
+        self.state.z = wrap_angle(self.state.z + heading_delta);
+
+        // Error in the heading swings the displacement around
+        let mut motion_jacobian = Matrix3::identity();
+        motion_jacobian[(0, 2)] = -displacement.y;
+        motion_jacobian[(1, 2)] = displacement.x;
+
+        // Drive noise stretches the step and angle noise swings it around, along with the heading
+        let noise_jacobian = Matrix3x2::new(
+            displacement.x,
+            -displacement.y,
+            displacement.y,
+            displacement.x,
+            0.0,
+            1.0,
+        );
+        let noise = Matrix2::new(
+            self.predictor.drive_noise().powi(2),
+            0.0,
+            0.0,
+            self.predictor.angle_noise().powi(2),
+        );
+
+        self.covariance = motion_jacobian * self.covariance * motion_jacobian.transpose()
+            + noise_jacobian * noise * noise_jacobian.transpose();
+    }
+
+    fn correct(&mut self, sensor_index: usize) -> Option<()> {
+        let sensor = &self.sensors[sensor_index];
+
+        let (measurement, measurement_covariance) = sensor.measure()?;
+        let predicted = sensor.predict(&self.state)?;
+
+        if measurement.len() != predicted.len() {
+            return None;
+        }
+
+        // Central difference jacobian of the measurement model
+        let mut jacobian = DMatrix::zeros(predicted.len(), 3);
+        for i in 0..3 {
+            let mut step = StateRepresentation::zeros();
+            step[i] = JACOBIAN_STEP;
+
+            let forward = sensor.predict(&(self.state + step))?;
+            let backward = sensor.predict(&(self.state - step))?;
+
+            jacobian.set_column(i, &((forward - backward) / (2.0 * JACOBIAN_STEP)));
+        }
+
+        let covariance = DMatrix::from_column_slice(3, 3, self.covariance.as_slice());
+        let innovation: DVector<f64> = measurement - predicted;
+        let innovation_covariance =
+            &jacobian * &covariance * jacobian.transpose() + &measurement_covariance;
+        let innovation_covariance_inverse = innovation_covariance.try_inverse()?;
+
+        // Reject readings that are far outside what we expect, e.g. off another robot
+        if (innovation.transpose() * &innovation_covariance_inverse * &innovation)[(0, 0)]
+            > INNOVATION_GATE
+        {
+            return None;
+        }
+
+        let gain = &covariance * jacobian.transpose() * innovation_covariance_inverse;
+        let correction = &gain * innovation;
+
+        // Joseph form keeps the covariance symmetric and positive definite
+        let residual = DMatrix::identity(3, 3) - &gain * &jacobian;
+        let covariance = &residual * covariance * residual.transpose()
+            + &gain * measurement_covariance * gain.transpose();
+
+        self.state += StateRepresentation::from_column_slice(correction.as_slice());
+        self.covariance = Matrix3::from_column_slice(covariance.as_slice());
+
+        Some(())
+    }
+}
+
+impl<M: MotionModel> Localization for ExtendedKalmanFilter<M> {
+    fn pose_estimate(&self) -> StateRepresentation {
+        self.state
+    }
+
+    fn covariance(&self) -> Matrix3<f64> {
+        self.covariance
+    }
+
+    fn heading_health(&self) -> HeadingHealth {
+        self.predictor.heading_health()
+    }
+
+    fn init_norm(&mut self, mean: &StateRepresentation, std_dev: &Matrix3<f64>) {
+        self.state = *mean;
+        self.covariance = std_dev * std_dev.transpose();
+    }
+
+    fn get_estimates(&self) -> Vec<StateRepresentation> {
+        vec![self.state]
+    }
+
+    async fn update(&mut self) {
+        self.predictor.update().await;
+
+        self.predict();
+
+        for i in 0..self.sensors.len() {
+            self.correct(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use core::{cell::RefCell, time::Duration};
+
+    use nalgebra::Vector2;
+    use uom::si::{
+        angular_velocity::radian_per_second,
+        f64::{AngularVelocity, Length, Velocity},
+        length::meter,
+        velocity::meter_per_second,
+    };
+
+    use super::*;
+    use crate::{
+        config::{track_width, wheel_diameter, DRIVE_RATIO},
+        localization::{
+            predict::{
+                tank_pose_tracking::TankPoseTracking,
+                tracking_wheel_odometry::{TrackingWheelOdometry, TrackingWheelOffsets},
+            },
+            sensor::gps::GpsPoseSensor,
+        },
+        sensor::rotary::TrackingWheel,
+        sim::{
+            block_on, clock,
+            devices::{SimGps, SimImu, SimMotor, SimTrackingWheel},
+            drive_plant,
+            plant::Side,
+        },
+        utils::angle_difference,
+    };
+
+    #[test]
+    fn converges_to_gps() {
+        let plant = drive_plant();
+        plant
+            .borrow_mut()
+            .set_pose(StateRepresentation::new(0.5, -0.3, 1.0));
+
+        let wheel = |side| {
+            TrackingWheel::new(
+                SimMotor::new(plant.clone(), side),
+                wheel_diameter(),
+                Some(DRIVE_RATIO),
+            )
+        };
+        let predictor = block_on(TankPoseTracking::new(
+            wheel(Side::Left),
+            wheel(Side::Right),
+            vec![SimImu::new(plant.clone(), 0.0)],
+            track_width(),
+            0.1,
+            0.01,
+        ));
+
+        let mut ekf = ExtendedKalmanFilter::new(predictor);
+        ekf.add_sensor(GpsPoseSensor::new(SimGps::new(plant.clone(), 0.0)));
+        ekf.init_uniform(
+            &StateRepresentation::new(-1.0, -1.0, 0.5),
+            &StateRepresentation::new(1.0, 1.0, 1.5),
+        );
+
+        for _ in 0..20 {
+            block_on(ekf.update());
+        }
+
+        let estimate = ekf.pose_estimate();
+        assert!((estimate.x - 0.5).abs() < 0.01, "{estimate}");
+        assert!((estimate.y + 0.3).abs() < 0.01, "{estimate}");
+        assert!((estimate.z - 1.0).abs() < 1e-9, "{estimate}");
+        assert!(ekf.covariance()[(0, 0)] < 0.01);
+    }
+
+    #[test]
+    fn integrates_heading_from_imu_zero() {
+        let plant = drive_plant();
+        let start = StateRepresentation::new(0.5, -0.3, 1.0);
+        plant.borrow_mut().set_pose(start);
+
+        let wheel = |side| {
+            TrackingWheel::new(
+                SimMotor::new(plant.clone(), side),
+                wheel_diameter(),
+                Some(DRIVE_RATIO),
+            )
+        };
+        // Calibrated facing the start heading, so the IMU reads zero there
+        let predictor = block_on(TankPoseTracking::new(
+            wheel(Side::Left),
+            wheel(Side::Right),
+            vec![SimImu::new(plant.clone(), 0.0).with_zero(start.z)],
+            track_width(),
+            0.1,
+            0.01,
+        ));
+
+        let mut ekf = ExtendedKalmanFilter::new(predictor);
+        ekf.init_norm(&start, &Matrix3::from_diagonal_element(0.01));
+
+        // Drive an arc to the left
+        {
+            let mut plant = plant.borrow_mut();
+            plant.set_velocity(Side::Left, AngularVelocity::new::<radian_per_second>(10.0));
+            plant.set_velocity(Side::Right, AngularVelocity::new::<radian_per_second>(15.0));
+        }
+        for _ in 0..100 {
+            plant.borrow_mut().step(Duration::from_millis(10));
+            clock::advance(Duration::from_millis(10));
+            block_on(ekf.update());
+        }
+
+        let pose = plant.borrow().pose();
+        let estimate = ekf.pose_estimate();
+        assert!(
+            (estimate.xy() - pose.xy()).magnitude() < 0.01,
+            "{estimate} {pose}"
+        );
+        assert!(
+            angle_difference(estimate.z, pose.z).abs() < 1e-6,
+            "{estimate} {pose}"
+        );
+        assert!(ekf.covariance()[(2, 2)] > 0.01 * 0.01);
+    }
+
+    #[test]
+    fn reverses_on_tracking_wheels() {
+        let plant = drive_plant();
+        let start = StateRepresentation::new(0.5, -0.3, 1.0);
+        plant.borrow_mut().set_pose(start);
+
+        let diameter = Length::new::<meter>(0.05);
+        let wheel = |x, y, direction| {
+            TrackingWheel::new(
+                SimTrackingWheel::new(plant.clone(), Vector2::new(x, y), direction, diameter),
+                diameter,
+                None,
+            )
+        };
+        let offsets = TrackingWheelOffsets {
+            left: Length::new::<meter>(0.1),
+            right: Length::new::<meter>(-0.1),
+            perpendicular: Length::new::<meter>(-0.05),
+        };
+        let predictor = block_on(async {
+            TrackingWheelOdometry::new(
+                wheel(0.0, 0.1, Vector2::x()),
+                wheel(-0.05, 0.0, Vector2::y()),
+                offsets,
+                vec![SimImu::new(plant.clone(), 0.0)],
+                0.1,
+                0.01,
+            )
+            .await
+            .with_right_wheel(wheel(0.0, -0.1, Vector2::x()))
+            .await
+        });
+
+        let mut ekf = ExtendedKalmanFilter::new(predictor);
+        ekf.init_norm(&start, &Matrix3::from_diagonal_element(0.01));
+
+        // Reverse while being pushed to the robot's left
+        {
+            let mut plant = plant.borrow_mut();
+            plant.set_velocity(Side::Left, AngularVelocity::new::<radian_per_second>(-10.0));
+            plant.set_velocity(
+                Side::Right,
+                AngularVelocity::new::<radian_per_second>(-10.0),
+            );
+        }
+        for _ in 0..100 {
+            {
+                let mut plant = plant.borrow_mut();
+                plant.step(Duration::from_millis(10));
+
+                let mut pose = plant.pose();
+                pose.x -= 0.002 * pose.z.sin();
+                pose.y += 0.002 * pose.z.cos();
+                plant.set_pose(pose);
+            }
+            clock::advance(Duration::from_millis(10));
+            block_on(ekf.update());
+        }
+
+        let pose = plant.borrow().pose();
+        let travel = Rotation2::new(-start.z) * (pose.xy() - start.xy());
+        assert!(travel.x < -0.5 && travel.y > 0.15, "{travel}");
+
+        let estimate = ekf.pose_estimate();
+        assert!(
+            (estimate.xy() - pose.xy()).magnitude() < 0.01,
+            "{estimate} {pose}"
+        );
+    }
+}
@@ -1,12 +1,46 @@
-use nalgebra::Vector3;
+use alloc::vec::Vec;
+
+use nalgebra::{Matrix3, Vector3};
+use uom::{num_traits::Float, si::length::meter};
 
 use super::sensor::*;
+use crate::{
+    config::{converged_position_std, CONVERGED_HEADING_STD},
+    sensor::imu::HeadingHealth,
+};
 
 pub type StateRepresentation = Vector3<f64>;
 
 pub trait Localization {
     fn pose_estimate(&self) -> StateRepresentation;
+
+    /// Covariance of the belief around [`Localization::pose_estimate`].
+    fn covariance(&self) -> Matrix3<f64>;
+
+    /// Whether the belief is tight enough to start driving paths off of it.
+    fn converged(&self) -> bool {
+        let covariance = self.covariance();
+        let max_position_std = converged_position_std().get::<meter>();
+
+        covariance[(0, 0)].sqrt() < max_position_std
+            && covariance[(1, 1)].sqrt() < max_position_std
+            && covariance[(2, 2)].sqrt() < CONVERGED_HEADING_STD
+    }
+
+    /// Whether the motion model is still measuring the heading properly.
+    fn heading_health(&self) -> HeadingHealth;
+
+    /// Resets the belief to a normal distribution around `mean`.
+    ///
+    /// `std_dev` is a square root of the covariance, so a diagonal of standard deviations gives
+    /// an uncorrelated spread in x, y and heading.
+    fn init_norm(&mut self, mean: &StateRepresentation, std_dev: &Matrix3<f64>);
+
+    /// Samples of the current belief for telemetry.
+    fn get_estimates(&self) -> Vec<StateRepresentation>;
+
     async fn update(&mut self);
 }
 
+pub mod extended_kalman_filter;
 pub mod particle_filter;
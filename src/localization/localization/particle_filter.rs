@@ -1,26 +1,51 @@
-use alloc::{boxed::Box, sync::Arc, vec::Vec};
-use core::time::Duration;
+use alloc::{boxed::Box, collections::BTreeSet, vec, vec::Vec};
+use core::{f64::consts::TAU, time::Duration};
 
-use nalgebra::{clamp, Matrix3};
+use nalgebra::{clamp, Matrix3, Rotation2, Vector2};
 use rand::{
     distributions::{Distribution, Uniform},
     rngs::SmallRng,
+    seq::index::sample,
     Rng, SeedableRng,
 };
-use rand_distr::Normal;
-use uom::si::{f64::Length, length::meter};
-use vexide::core::{println, sync::Mutex, time::Instant};
+use rand_distr::{Binomial, Normal};
+use uom::{
+    num_traits::Float,
+    si::{f64::Length, length::meter},
+};
 
 use super::{Localization, Sensor, StateRepresentation};
 use crate::{
-    actuator::motor_group::MotorGroup, config::FIELD_MAX,
-    localization::predict::tank_pose_tracking::TankPoseTracking, uom::num_traits::Float,
+    config::{
+        kld_bin_size, AUGMENTED_MCL_FAST_RATE, AUGMENTED_MCL_SLOW_RATE, FIELD_MAX,
+        HEADING_DRIFT_NOISE, HEADING_NOISE, KLD_EPSILON, KLD_HEADING_BIN_SIZE, KLD_Z,
+        LOCALIZATION_UPDATE_BUDGET, MAX_PARTICLES, MIN_PARTICLES, RESAMPLE_ESS_RATIO,
+        SENSOR_LOG_LIKELIHOOD_RANGE,
+    },
+    hal::time::Instant,
+    localization::predict::MotionModel,
+    println,
+    sensor::imu::HeadingHealth,
+    utils::{angle_difference, log_sum_exp, wrap_angle},
 };
 
-pub struct ParticleFilter<const D: usize> {
-    particles: [StateRepresentation; D],
-    sensors: Vec<Box<dyn Sensor>>,
-    predictor: TankPoseTracking<Arc<Mutex<MotorGroup>>>,
+/// Monte Carlo localization with a particle count that adapts at runtime.
+///
+/// The count grows towards `max_particles` while the belief is spread out and shrinks towards
+/// `min_particles` once it has converged (KLD-sampling), and is capped so an update fits in the
+/// update budget.
+pub struct ParticleFilter<M: MotionModel> {
+    particles: Vec<StateRepresentation>,
+    weights: Vec<f64>,
+    min_particles: usize,
+    max_particles: usize,
+    update_budget: Duration,
+    particle_cost: Duration,
+    /// Logs of the long and short term average likelihood, `NEG_INFINITY` until the first update.
+    log_slow_likelihood: f64,
+    log_fast_likelihood: f64,
+    sensors: Vec<(Box<dyn Sensor>, f64)>,
+    predictor: M,
     rng: SmallRng,
     last_update_time: Instant,
     dist_since_update: f64,
@@ -28,16 +53,19 @@ pub struct ParticleFilter<const D: usize> {
     min_update_distance: Length,
 }
 
-impl<const D: usize> ParticleFilter<D> {
-    pub fn new(
-        predictor: TankPoseTracking<Arc<Mutex<MotorGroup>>>,
-        min_update_interval: Duration,
-        min_update_distance: Length,
-    ) -> Self {
+impl<M: MotionModel> ParticleFilter<M> {
+    pub fn new(predictor: M, min_update_interval: Duration, min_update_distance: Length) -> Self {
         let rng = SmallRng::seed_from_u64(0);
 
         Self {
-            particles: [StateRepresentation::new(0.0, 0.0, 0.0); D],
+            particles: vec![StateRepresentation::new(0.0, 0.0, 0.0); MIN_PARTICLES],
+            weights: vec![1.0 / MIN_PARTICLES as f64; MIN_PARTICLES],
+            min_particles: MIN_PARTICLES,
+            max_particles: MAX_PARTICLES,
+            update_budget: LOCALIZATION_UPDATE_BUDGET,
+            particle_cost: Duration::ZERO,
+            log_slow_likelihood: f64::NEG_INFINITY,
+            log_fast_likelihood: f64::NEG_INFINITY,
             sensors: Vec::new(),
             predictor,
             rng,
@@ -48,32 +76,38 @@ impl<const D: usize> ParticleFilter<D> {
         }
     }
 
-    pub fn add_sensor(&mut self, sensor: impl Sensor + 'static) {
-        self.sensors.push(Box::new(sensor));
+    /// Overrides the configured bounds on the particle count.
+    pub fn with_particle_limits(mut self, min_particles: usize, max_particles: usize) -> Self {
+        assert!(min_particles > 0, "Need at least one particle");
+        assert!(min_particles <= max_particles, "Min must be less than max");
+
+        self.min_particles = min_particles;
+        self.max_particles = max_particles;
+        self
     }
 
-    pub fn get_estimates(&self) -> [StateRepresentation; D] {
-        self.particles.clone()
+    /// Overrides the configured time budget for a single update.
+    pub fn with_update_budget(mut self, update_budget: Duration) -> Self {
+        self.update_budget = update_budget;
+        self
     }
 
-    pub fn init_norm(&mut self, mean: &StateRepresentation, covariance: &Matrix3<f64>) {
-        let normal_dist = Normal::new(0.0, 1.0).expect("Can't create normal dist");
+    pub fn particle_count(&self) -> usize {
+        self.particles.len()
+    }
 
-        for particle in self.particles.iter_mut() {
-            let new_particle = mean
-                + covariance
-                    * StateRepresentation::new(
-                        normal_dist.sample(&mut self.rng),
-                        normal_dist.sample(&mut self.rng),
-                        normal_dist.sample(&mut self.rng),
-                    );
-            particle.x = clamp(new_particle.x, -FIELD_MAX, FIELD_MAX);
-            particle.y = clamp(new_particle.y, -FIELD_MAX, FIELD_MAX);
-            particle.z = new_particle.z;
-        }
+    pub fn add_sensor(&mut self, sensor: impl Sensor + 'static) {
+        self.add_weighted_sensor(sensor, 1.0);
+    }
+
+    /// Adds a sensor whose log-likelihood is scaled by `weight`, so sensors that aren't really
+    /// independent or are less trusted can be down weighted.
+    pub fn add_weighted_sensor(&mut self, sensor: impl Sensor + 'static, weight: f64) {
+        assert!(weight >= 0.0, "Sensor weight must be non negative");
+
+        self.sensors.push((Box::new(sensor), weight));
     }
 
-    #[allow(dead_code)]
     pub fn init_uniform(&mut self, min: &StateRepresentation, max: &StateRepresentation) {
         assert!(min.x <= max.x, "Min must be less than max");
         assert!(min.y <= max.y, "Min must be less than max");
@@ -83,37 +117,237 @@ impl<const D: usize> ParticleFilter<D> {
         let normal_dist_y = Uniform::new(min.y, max.y);
         let normal_dist_z = Uniform::new(min.z, max.z);
 
-        for particle in self.particles.iter_mut() {
-            particle.x = normal_dist_x.sample(&mut self.rng);
-            particle.y = normal_dist_y.sample(&mut self.rng);
-            particle.z = normal_dist_z.sample(&mut self.rng);
+        // Nothing is known yet, so start with as many particles as allowed
+        self.particles = (0..self.max_particles)
+            .map(|_| {
+                StateRepresentation::new(
+                    normal_dist_x.sample(&mut self.rng),
+                    normal_dist_y.sample(&mut self.rng),
+                    normal_dist_z.sample(&mut self.rng),
+                )
+            })
+            .collect();
+
+        self.reset_weights();
+    }
+
+    /// Effective number of particles carrying the weight, the particle count when they're all
+    /// equal and 1 when a single particle has all of it.
+    pub fn effective_sample_size(&self) -> f64 {
+        1.0 / self
+            .weights
+            .iter()
+            .map(|weight| weight * weight)
+            .sum::<f64>()
+    }
+
+    fn reset_weights(&mut self) {
+        self.weights = vec![1.0 / self.particles.len() as f64; self.particles.len()];
+        self.log_slow_likelihood = f64::NEG_INFINITY;
+        self.log_fast_likelihood = f64::NEG_INFINITY;
+    }
+
+    /// Most particles an update can afford within the update budget, going by how long the last
+    /// one took per particle.
+    fn budget_particles(&self) -> usize {
+        if self.particle_cost.is_zero() {
+            self.max_particles
+        } else {
+            (self.update_budget.as_secs_f64() / self.particle_cost.as_secs_f64()) as usize
         }
     }
+
+    /// Particle count for the next resample.
+    ///
+    /// The KLD bound is taken over the histogram bins a systematic resample at the current count
+    /// would land in, so the count can grow by at most the spread the current particles cover
+    /// each resample.
+    fn kld_particle_count(&self) -> usize {
+        let step = 1.0 / self.particles.len() as f64;
+        let position_bin = kld_bin_size().get::<meter>();
+
+        let mut bins = BTreeSet::new();
+        let mut pointer = step / 2.0;
+        let mut cumulative_weight = 0.0;
+
+        for (particle, weight) in self.particles.iter().zip(self.weights.iter().copied()) {
+            cumulative_weight += weight;
+
+            if cumulative_weight >= pointer {
+                bins.insert((
+                    (particle.x / position_bin).floor() as i32,
+                    (particle.y / position_bin).floor() as i32,
+                    (particle.z / KLD_HEADING_BIN_SIZE).floor() as i32,
+                ));
+
+                while pointer <= cumulative_weight {
+                    pointer += step;
+                }
+            }
+        }
+
+        // Wilson-Hilferty approximation of the chi-square quantile (Fox, 2003)
+        let required = if bins.len() > 1 {
+            let k = (bins.len() - 1) as f64;
+            let a = 2.0 / (9.0 * k);
+
+            (k / (2.0 * KLD_EPSILON) * (1.0 - a + a.sqrt() * KLD_Z).powi(3)).ceil() as usize
+        } else {
+            0
+        };
+
+        let max_particles = self.max_particles.min(self.budget_particles());
+
+        required.clamp(self.min_particles, max_particles.max(self.min_particles))
+    }
+
+    /// Systematic (low variance) resampling to `count` particles, then replaces `injected` of them
+    /// with uniformly random ones.
+    fn resample(&mut self, count: usize, injected: usize) {
+        let old_particles = core::mem::take(&mut self.particles);
+        let step = 1.0 / count as f64;
+
+        // One random offset, then evenly spaced pointers through the cumulative weights
+        let mut pointer = self.rng.sample(Uniform::new(0.0, step));
+        let mut cumulative_weight = self.weights[0];
+        let mut index = 0;
+
+        for _ in 0..count {
+            // Rounding can leave the total weight just short of the last pointer
+            while cumulative_weight < pointer && index < old_particles.len() - 1 {
+                index += 1;
+                cumulative_weight += self.weights[index];
+            }
+
+            self.particles.push(old_particles[index]);
+            pointer += step;
+        }
+
+        let position_dist = Uniform::new(-FIELD_MAX, FIELD_MAX);
+        let heading_dist = Uniform::new(0.0, TAU);
+
+        for index in sample(&mut self.rng, count, injected.min(count)) {
+            self.particles[index] = StateRepresentation::new(
+                position_dist.sample(&mut self.rng),
+                position_dist.sample(&mut self.rng),
+                heading_dist.sample(&mut self.rng),
+            );
+        }
+
+        self.weights = vec![step; count];
+    }
 }
 
-impl<const D: usize> Localization for ParticleFilter<D> {
+impl<M: MotionModel> Localization for ParticleFilter<M> {
+    /// Weighted mean of the particles, using a circular mean for the heading so it doesn't break
+    /// where headings wrap around.
     fn pose_estimate(&self) -> StateRepresentation {
-        self.particles.iter().sum::<StateRepresentation>() / D as f64
+        let mut position = Vector2::zeros();
+        let mut heading = Vector2::zeros();
+
+        for (particle, weight) in self.particles.iter().zip(self.weights.iter().copied()) {
+            position += particle.xy() * weight;
+            heading += Vector2::new(particle.z.cos(), particle.z.sin()) * weight;
+        }
+
+        let heading = heading.y.atan2(heading.x);
+
+        StateRepresentation::new(position.x, position.y, wrap_angle(heading))
+    }
+
+    /// Weighted covariance of the particles, heading errors are taken the short way around.
+    fn covariance(&self) -> Matrix3<f64> {
+        let mean = self.pose_estimate();
+
+        self.particles
+            .iter()
+            .zip(self.weights.iter().copied())
+            .map(|(particle, weight)| {
+                let error = StateRepresentation::new(
+                    particle.x - mean.x,
+                    particle.y - mean.y,
+                    angle_difference(particle.z, mean.z),
+                );
+
+                error * error.transpose() * weight
+            })
+            .sum()
+    }
+
+    fn heading_health(&self) -> HeadingHealth {
+        self.predictor.heading_health()
+    }
+
+    fn init_norm(&mut self, mean: &StateRepresentation, std_dev: &Matrix3<f64>) {
+        let normal_dist = Normal::new(0.0, 1.0).expect("Can't create normal dist");
+
+        // KLD-sampling shrinks the set on the next resample if the pose really is this certain
+        self.particles = (0..self.max_particles)
+            .map(|_| {
+                let new_particle = mean
+                    + std_dev
+                        * StateRepresentation::new(
+                            normal_dist.sample(&mut self.rng),
+                            normal_dist.sample(&mut self.rng),
+                            normal_dist.sample(&mut self.rng),
+                        );
+
+                StateRepresentation::new(
+                    clamp(new_particle.x, -FIELD_MAX, FIELD_MAX),
+                    clamp(new_particle.y, -FIELD_MAX, FIELD_MAX),
+                    new_particle.z,
+                )
+            })
+            .collect();
+
+        self.reset_weights();
+    }
+
+    fn get_estimates(&self) -> Vec<StateRepresentation> {
+        self.particles.clone()
     }
 
     async fn update(&mut self) {
         self.predictor.update().await;
 
-        let orientation = self.predictor.orientation().angle();
+        let start = Instant::now();
+        let count = self.particles.len();
+
+        let start_heading = self.predictor.heading().angle() - self.predictor.heading_delta();
 
         // Predict step
         for particle in self.particles.iter_mut() {
-            let prediction = self.predictor.predict();
-            particle.x += prediction.x;
-            particle.y += prediction.y;
-            particle.z = orientation;
+            let prediction = self.predictor.sample();
+
+            // The motion model moves along its own heading, so turn the displacement by how far
+            // this particle's heading is off from it
+            let displacement =
+                Rotation2::new(angle_difference(particle.z, start_heading)) * prediction.xy();
+            let heading_noise = self.rng.sample(
+                Normal::new(
+                    0.0,
+                    HEADING_NOISE * prediction.z.abs() + HEADING_DRIFT_NOISE,
+                )
+                .unwrap(),
+            );
+
+            particle.x += displacement.x;
+            particle.y += displacement.y;
+            particle.z = wrap_angle(particle.z + prediction.z + heading_noise);
         }
 
-        self.dist_since_update += self.predictor.predict().magnitude();
+        self.dist_since_update += self.predictor.displacement().abs();
 
-        // Only run if it's been longer than the minimum time or moved more than 2 inches
+        let mut sensor_event = false;
+        for (sensor, _) in self.sensors.iter_mut() {
+            sensor_event |= sensor.tick();
+        }
+
+        // Only run if it's been longer than the minimum time or moved more than 2 inches, unless
+        // a sensor has something to apply right away
         if (Instant::now() - self.last_update_time) < self.min_update_interval
             && self.dist_since_update < self.min_update_distance.get::<meter>()
+            && !sensor_event
             || self.sensors.is_empty()
         {
             return;
@@ -122,51 +356,398 @@ impl<const D: usize> Localization for ParticleFilter<D> {
         // println!("Update");
 
         // Update step
-        let mut weights = [0.0; D];
+        let mut log_likelihoods = vec![0.0; count];
+
+        // Sensors are independent, so their likelihoods multiply, i.e. their logs add
+        for (sensor, weight) in self.sensors.iter() {
+            let mut sensor_log_weights = vec![f64::NEG_INFINITY; count];
+
+            for (log_weight, particle) in sensor_log_weights.iter_mut().zip(self.particles.iter()) {
+                if let Some(log_p) = sensor.log_p(particle)
+                    && !log_p.is_nan()
+                {
+                    *log_weight = log_p;
+                }
+            }
 
-        // weight particles
-        for (i, particle) in self.particles.iter().enumerate() {
-            weights[i] = self
-                .sensors
+            // No usable reading at all from this sensor carries no information
+            let best = sensor_log_weights
                 .iter()
-                .filter_map(|sensor| sensor.p(&particle))
-                .sum::<f64>()
-                .abs();
+                .copied()
+                .fold(f64::NEG_INFINITY, f64::max);
+            if !best.is_finite() {
+                continue;
+            }
+
+            for (log_likelihood, sensor_log_weight) in
+                log_likelihoods.iter_mut().zip(sensor_log_weights.iter())
+            {
+                *log_likelihood +=
+                    weight * sensor_log_weight.max(best - SENSOR_LOG_LIKELIHOOD_RANGE);
+            }
+        }
+
+        // Long and short term average likelihood, the short term one dropping below the long term
+        // one means the particles are all in the wrong place. Kept in log space, the likelihoods
+        // underflow exactly when the filter is lost.
+        let log_avg_likelihood = log_sum_exp(&log_likelihoods) - (count as f64).ln();
+        if log_avg_likelihood.is_finite() && self.log_slow_likelihood == f64::NEG_INFINITY {
+            self.log_slow_likelihood = log_avg_likelihood;
+            self.log_fast_likelihood = log_avg_likelihood;
+        } else if log_avg_likelihood.is_finite() {
+            self.log_slow_likelihood = log_moving_average(
+                self.log_slow_likelihood,
+                log_avg_likelihood,
+                AUGMENTED_MCL_SLOW_RATE,
+            );
+            self.log_fast_likelihood = log_moving_average(
+                self.log_fast_likelihood,
+                log_avg_likelihood,
+                AUGMENTED_MCL_FAST_RATE,
+            );
         }
 
-        // Calculate average weight and random variable for resample
-        let avg_weight = weights.iter().sum::<f64>() / weights.len() as f64;
-        if avg_weight <= 0.0 {
-            println!("WARNING: Avg weight too low: {}", avg_weight);
+        // Weights carry over until the next resample
+        let mut log_weights = vec![0.0; count];
+        for ((log_weight, weight), log_likelihood) in log_weights
+            .iter_mut()
+            .zip(self.weights.iter())
+            .zip(log_likelihoods)
+        {
+            *log_weight = weight.ln() + log_likelihood;
+        }
+
+        // Normalize in log space so tiny likelihoods don't underflow to zero
+        let normalizer = log_sum_exp(&log_weights);
+        if !normalizer.is_finite() {
+            println!("WARNING: Can't normalize weights: {}", normalizer);
             return;
         }
-        let sample_rand = self.rng.sample(Uniform::new(0.0, avg_weight));
 
-        // Clone the particles to be memory safe with resample
-        let old_particles = self.particles.clone();
+        for (weight, log_weight) in self.weights.iter_mut().zip(log_weights) {
+            *weight = (log_weight - normalizer).exp();
+        }
 
-        let mut sum = sample_rand;
+        let inject_probability = if self.log_slow_likelihood.is_finite() {
+            (1.0 - (self.log_fast_likelihood - self.log_slow_likelihood).exp()).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
 
-        // Sample an adequate number of points
-        for i in 0..D {
-            // Start with a sum and index for the while loop
-            let mut weight_sum = weights[0];
-            let mut particle_index = 0;
+        self.particle_cost = start.elapsed() / count as u32;
 
-            // Increase until it gets to the right index
-            while weight_sum < sum && particle_index < weights.len() {
-                particle_index += 1;
-                weight_sum += weights[particle_index];
-            }
+        // Only resample once the weights have degenerated, there are particles to inject or there
+        // are more particles than fit in the budget, resampling too often throws away diversity
+        // for nothing
+        let degenerate = self.effective_sample_size() < RESAMPLE_ESS_RATIO * count as f64
+            || count > self.budget_particles().max(self.min_particles);
 
-            // Resample the particle
-            self.particles[i] = old_particles[particle_index];
+        if degenerate || inject_probability > 0.0 {
+            let resample_count = self.kld_particle_count();
+            let injected = Binomial::new(resample_count as u64, inject_probability)
+                .unwrap()
+                .sample(&mut self.rng) as usize;
 
-            // Increase the sum
-            sum += avg_weight;
+            if degenerate || injected > 0 {
+                self.resample(resample_count, injected);
+            }
         }
 
         self.last_update_time = Instant::now();
         self.dist_since_update = 0.0;
     }
 }
+
+/// Exponential moving average of likelihoods given and kept as logs, moving `rate` of the way
+/// towards `log_sample`.
+fn log_moving_average(log_average: f64, log_sample: f64, rate: f64) -> f64 {
+    log_sum_exp(&[log_average + (1.0 - rate).ln(), log_sample + rate.ln()])
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use core::{
+        cell::{Cell, RefCell},
+        f64::consts::{FRAC_PI_2, PI, TAU},
+    };
+
+    use nalgebra::Vector2;
+    use uom::si::{f64::Velocity, velocity::meter_per_second};
+
+    use super::*;
+    use crate::{
+        localization::sensor::DummySensor,
+        sim::{block_on, drive_plant, odometry::SimOdometry},
+    };
+
+    struct ConstantSensor(Option<f64>);
+
+    impl Sensor for ConstantSensor {
+        fn log_p(&self, _: &StateRepresentation) -> Option<f64> {
+            self.0
+        }
+    }
+
+    struct MovingSensor(Rc<Cell<Vector2<f64>>>);
+
+    impl Sensor for MovingSensor {
+        fn log_p(&self, x: &StateRepresentation) -> Option<f64> {
+            DummySensor {
+                covariance: 0.2,
+                mean: self.0.get(),
+            }
+            .log_p(x)
+        }
+    }
+
+    fn filter() -> ParticleFilter<SimOdometry> {
+        let predictor = SimOdometry::new(drive_plant(), 0.0, 0.0);
+
+        let mut filter = ParticleFilter::new(predictor, Duration::ZERO, Length::new::<meter>(0.0))
+            .with_particle_limits(200, 200);
+        filter.init_uniform(
+            &StateRepresentation::new(-1.0, -1.0, 0.0),
+            &StateRepresentation::new(1.0, 1.0, TAU),
+        );
+        filter
+    }
+
+    #[test]
+    fn bad_sensors_do_not_collapse_weights() {
+        let mut filter = filter();
+        filter.add_sensor(DummySensor {
+            covariance: 0.1,
+            mean: Vector2::new(0.5, -0.3),
+        });
+        filter.add_sensor(ConstantSensor(None));
+        filter.add_sensor(ConstantSensor(Some(f64::NEG_INFINITY)));
+        filter.add_weighted_sensor(ConstantSensor(Some(f64::NAN)), 2.0);
+
+        block_on(filter.update());
+
+        let estimate = filter.pose_estimate();
+        assert!((estimate.x - 0.5).abs() < 0.1, "{estimate}");
+        assert!((estimate.y + 0.3).abs() < 0.1, "{estimate}");
+    }
+
+    #[test]
+    fn confident_sensor_rules_out_particles() {
+        let mut filter = filter();
+        // Agrees with every particle
+        filter.add_weighted_sensor(ConstantSensor(Some(0.0)), 10.0);
+        filter.add_sensor(DummySensor {
+            covariance: 0.02,
+            mean: Vector2::new(-0.4, 0.6),
+        });
+
+        block_on(filter.update());
+
+        assert!(filter.get_estimates().iter().all(|particle| (Vector2::new(
+            particle.x, particle.y
+        ) - Vector2::new(-0.4, 0.6))
+        .magnitude()
+            < 0.15));
+    }
+
+    #[test]
+    fn resample_handles_weight_on_last_particle() {
+        let mut filter = filter();
+        let last = filter.particles[199];
+        filter.weights = vec![0.0; 200];
+        filter.weights[199] = 1.0;
+
+        filter.resample(200, 0);
+
+        assert!(filter.particles.iter().all(|particle| *particle == last));
+        assert!((filter.effective_sample_size() - 200.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn uninformative_sensor_does_not_resample() {
+        let mut filter = filter();
+        filter.add_sensor(ConstantSensor(Some(0.0)));
+        let particles = filter.particles.clone();
+
+        block_on(filter.update());
+
+        assert!(filter
+            .particles
+            .iter()
+            .zip(particles.iter())
+            .all(|(new, old)| new.xy() == old.xy()));
+    }
+
+    #[test]
+    fn injects_particles_when_lost() {
+        let mean = Rc::new(Cell::new(Vector2::new(0.5, 0.5)));
+        let mut filter = filter();
+        filter.add_sensor(MovingSensor(mean.clone()));
+
+        for _ in 0..10 {
+            block_on(filter.update());
+        }
+
+        // Kidnap the robot somewhere no particle is
+        mean.set(Vector2::new(-1.5, -1.5));
+
+        for _ in 0..30 {
+            block_on(filter.update());
+        }
+
+        let estimate = filter.pose_estimate();
+        assert!((estimate.x + 1.5).abs() < 0.2, "{estimate}");
+        assert!((estimate.y + 1.5).abs() < 0.2, "{estimate}");
+    }
+
+    #[test]
+    fn injects_particles_when_likelihoods_underflow() {
+        let mean = Rc::new(Cell::new(Vector2::new(0.5, 0.5)));
+        let mut filter = filter();
+        filter.add_sensor(MovingSensor(mean.clone()));
+        // Same for every particle, but the average likelihood is too small for an f64
+        filter.add_sensor(ConstantSensor(Some(-800.0)));
+
+        for _ in 0..10 {
+            block_on(filter.update());
+        }
+
+        mean.set(Vector2::new(-1.5, -1.5));
+
+        for _ in 0..30 {
+            block_on(filter.update());
+        }
+
+        let estimate = filter.pose_estimate();
+        assert!((estimate.x + 1.5).abs() < 0.2, "{estimate}");
+        assert!((estimate.y + 1.5).abs() < 0.2, "{estimate}");
+    }
+
+    #[test]
+    fn particles_follow_motion_model() {
+        let plant = drive_plant();
+        let mut filter = ParticleFilter::new(
+            SimOdometry::new(plant.clone(), 0.0, 0.0),
+            Duration::ZERO,
+            Length::new::<meter>(0.0),
+        );
+        filter.add_sensor(ConstantSensor(Some(0.0)));
+        filter.init_norm(
+            &StateRepresentation::new(0.0, 0.0, 0.0),
+            &Matrix3::from_diagonal(&StateRepresentation::new(0.01, 0.01, 0.0)),
+        );
+
+        plant
+            .borrow_mut()
+            .set_pose(StateRepresentation::new(0.5, 0.2, 1.0));
+        block_on(filter.update());
+
+        let estimate = filter.pose_estimate();
+        assert!(
+            (estimate.xy() - Vector2::new(0.5, 0.2)).magnitude() < 0.05,
+            "{estimate}"
+        );
+        assert!((estimate.z - 1.0).abs() < 0.01, "{estimate}");
+    }
+
+    #[test]
+    fn particles_move_along_own_heading() {
+        let plant = drive_plant();
+        let mut filter = ParticleFilter::new(
+            SimOdometry::new(plant.clone(), 0.0, 0.0),
+            Duration::ZERO,
+            Length::new::<meter>(0.0),
+        )
+        .with_particle_limits(2, 2);
+        filter.add_sensor(ConstantSensor(Some(0.0)));
+        filter.particles = vec![
+            StateRepresentation::new(0.0, 0.0, 0.0),
+            StateRepresentation::new(0.0, 0.0, FRAC_PI_2),
+        ];
+        filter.weights = vec![0.5, 0.5];
+
+        // The IMU sees a quarter turn while driving straight
+        plant
+            .borrow_mut()
+            .set_pose(StateRepresentation::new(1.0, 0.0, FRAC_PI_2));
+        block_on(filter.update());
+
+        let particles = filter.get_estimates();
+        assert!((particles[0].xy() - Vector2::new(1.0, 0.0)).magnitude() < 1e-9);
+        assert!((particles[1].xy() - Vector2::new(0.0, 1.0)).magnitude() < 1e-9);
+        assert!((particles[0].z - FRAC_PI_2).abs() < 0.1);
+        assert!((particles[1].z - PI).abs() < 0.1);
+    }
+
+    #[test]
+    fn heading_estimate_wraps() {
+        let mut filter = filter();
+        for (i, particle) in filter.particles.iter_mut().enumerate() {
+            particle.z = if i % 2 == 0 { 0.05 } else { TAU - 0.05 };
+        }
+
+        let estimate = filter.pose_estimate();
+        assert!(angle_difference(estimate.z, 0.0).abs() < 1e-9, "{estimate}");
+        assert!((filter.covariance()[(2, 2)] - 0.05 * 0.05).abs() < 1e-9);
+    }
+
+    #[test]
+    fn converges_around_sensor() {
+        let mut filter = filter();
+        assert!(!filter.converged());
+
+        filter.add_sensor(DummySensor {
+            covariance: 0.01,
+            mean: Vector2::new(0.2, 0.3),
+        });
+        filter.init_norm(
+            &StateRepresentation::new(0.2, 0.3, 1.0),
+            &Matrix3::from_diagonal(&StateRepresentation::new(0.1, 0.1, 0.01)),
+        );
+
+        for _ in 0..5 {
+            block_on(filter.update());
+        }
+
+        assert!(filter.converged(), "{}", filter.covariance());
+    }
+
+    #[test]
+    fn particle_count_shrinks_once_converged() {
+        let mut filter = filter().with_particle_limits(20, 2000);
+        filter.add_sensor(DummySensor {
+            covariance: 0.02,
+            mean: Vector2::new(0.2, 0.3),
+        });
+        filter.init_uniform(
+            &StateRepresentation::new(-FIELD_MAX, -FIELD_MAX, 0.0),
+            &StateRepresentation::new(FIELD_MAX, FIELD_MAX, TAU),
+        );
+        assert_eq!(filter.particle_count(), 2000);
+
+        for _ in 0..5 {
+            block_on(filter.update());
+        }
+
+        assert!(filter.particle_count() < 200, "{}", filter.particle_count());
+        assert!(filter.particle_count() >= 20);
+    }
+
+    #[test]
+    fn update_budget_caps_particle_count() {
+        let mut filter = filter()
+            .with_particle_limits(20, 2000)
+            .with_update_budget(Duration::from_millis(1));
+        filter.add_sensor(ConstantSensor(Some(0.0)));
+        filter.init_uniform(
+            &StateRepresentation::new(-FIELD_MAX, -FIELD_MAX, 0.0),
+            &StateRepresentation::new(FIELD_MAX, FIELD_MAX, TAU),
+        );
+
+        // The simulated clock doesn't move while updating, so pretend particles are expensive
+        filter.particle_cost = Duration::from_micros(10);
+        filter.resample(filter.kld_particle_count(), 0);
+
+        assert_eq!(filter.particle_count(), 100);
+    }
+}
@@ -0,0 +1,180 @@
+//! Reflections of field coordinates, so a routine written for one side of the field runs on the
+//! other.
+
+use alloc::vec::Vec;
+use core::f64::consts::PI;
+
+use nalgebra::Vector2;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{field_map::Alliance, localization::StateRepresentation};
+use crate::utils::wrap_angle;
+
+/// Reflection of the field onto itself.
+///
+/// Reflecting across an axis turns counter-clockwise turns into clockwise ones. Driving direction
+/// is kept, a reversed path segment stays reversed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mirror {
+    /// Leaves coordinates as they are.
+    Identity,
+    /// Across the x axis, negating y.
+    XAxis,
+    /// Across the y axis, negating x. This swaps the red and blue sides of the field.
+    YAxis,
+    /// Through the field center, a half turn.
+    Center,
+}
+
+impl Mirror {
+    /// Takes coordinates written for the red alliance to `alliance`'s side of the field.
+    pub fn alliance(alliance: Alliance) -> Self {
+        match alliance {
+            Alliance::Red => Mirror::Identity,
+            Alliance::Blue => Mirror::YAxis,
+        }
+    }
+
+    pub fn point(self, point: Vector2<f64>) -> Vector2<f64> {
+        match self {
+            Mirror::Identity => point,
+            Mirror::XAxis => Vector2::new(point.x, -point.y),
+            Mirror::YAxis => Vector2::new(-point.x, point.y),
+            Mirror::Center => -point,
+        }
+    }
+
+    /// Heading in CCW radians, wrapped into [0, TAU).
+    pub fn heading(self, heading: f64) -> f64 {
+        wrap_angle(match self {
+            Mirror::Identity => heading,
+            Mirror::XAxis => -heading,
+            Mirror::YAxis => PI - heading,
+            Mirror::Center => heading + PI,
+        })
+    }
+
+    pub fn pose(self, pose: &StateRepresentation) -> StateRepresentation {
+        let point = self.point(pose.xy());
+
+        StateRepresentation::new(point.x, point.y, self.heading(pose.z))
+    }
+
+    pub fn waypoints(self, waypoints: &[Vector2<f64>]) -> Vec<Vector2<f64>> {
+        waypoints.iter().map(|point| self.point(*point)).collect()
+    }
+
+    /// Parses a path description in the motion profiling JSON format, e.g. for
+    /// [`motion_profiling::combined_mp::CombinedMP`], with its control points mirrored.
+    pub fn path<P: DeserializeOwned>(self, json: &str) -> serde_json::Result<P> {
+        let mut path: Value = serde_json::from_str(json)?;
+
+        let points = path
+            .get_mut("segments")
+            .and_then(Value::as_array_mut)
+            .into_iter()
+            .flatten()
+            .filter_map(|segment| segment.get_mut("path").and_then(Value::as_array_mut))
+            .flatten();
+
+        for point in points {
+            if let (Some(x), Some(y)) = (
+                point.get("x").and_then(Value::as_f64),
+                point.get("y").and_then(Value::as_f64),
+            ) {
+                let mirrored = self.point(Vector2::new(x, y));
+                point["x"] = mirrored.x.into();
+                point["y"] = mirrored.y.into();
+            }
+        }
+
+        serde_json::from_value(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    const MIRRORS: [Mirror; 4] = [
+        Mirror::Identity,
+        Mirror::XAxis,
+        Mirror::YAxis,
+        Mirror::Center,
+    ];
+
+    #[test]
+    fn mirrors_poses() {
+        let pose = StateRepresentation::new(1.0, 2.0, PI / 4.0);
+
+        let expected = [
+            StateRepresentation::new(1.0, 2.0, PI / 4.0),
+            StateRepresentation::new(1.0, -2.0, 7.0 * PI / 4.0),
+            StateRepresentation::new(-1.0, 2.0, 3.0 * PI / 4.0),
+            StateRepresentation::new(-1.0, -2.0, 5.0 * PI / 4.0),
+        ];
+
+        for (mirror, expected) in MIRRORS.into_iter().zip(expected) {
+            assert!((mirror.pose(&pose) - expected).abs().max() < 1e-12);
+
+            // Every mirror is its own inverse
+            assert!((mirror.pose(&mirror.pose(&pose)) - pose).abs().max() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn heading_follows_mirrored_motion() {
+        let start = Vector2::new(0.5, -1.0);
+        let heading: f64 = 1.0;
+
+        for mirror in MIRRORS {
+            // Moving along the heading before mirroring is moving along the mirrored heading after
+            let end = start + Vector2::new(heading.cos(), heading.sin());
+            let direction = mirror.point(end) - mirror.point(start);
+            let mirrored = mirror.heading(heading);
+
+            assert!((direction - Vector2::new(mirrored.cos(), mirrored.sin())).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn mirrors_path_control_points() {
+        #[derive(Deserialize)]
+        struct Point {
+            x: f64,
+            y: f64,
+        }
+        #[derive(Deserialize)]
+        struct Segment {
+            inverted: bool,
+            path: Vec<Point>,
+        }
+        #[derive(Deserialize)]
+        struct Path {
+            start_speed: f64,
+            segments: Vec<Segment>,
+        }
+
+        let json = r#"{
+            "start_speed": 0.5,
+            "segments": [
+                { "inverted": true, "path": [{ "x": 1.0, "y": 2.0 }, { "x": -0.5, "y": 0.25 }] }
+            ]
+        }"#;
+
+        let path: Path = Mirror::YAxis.path(json).unwrap();
+
+        assert_eq!(path.start_speed, 0.5);
+        assert!(path.segments[0].inverted);
+
+        let points: Vec<_> = path.segments[0]
+            .path
+            .iter()
+            .map(|point| (point.x, point.y))
+            .collect();
+        assert_eq!(points, [(-1.0, 2.0), (0.5, 0.25)]);
+    }
+}
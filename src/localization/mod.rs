@@ -1,3 +1,5 @@
+pub mod field_map;
 pub mod localization;
+pub mod mirror;
 pub mod predict;
 pub mod sensor;
@@ -1 +1,56 @@
+use nalgebra::{Rotation2, Vector2};
+use uom::num_traits::Float;
+
+use crate::{localization::localization::StateRepresentation, sensor::imu::HeadingHealth};
+
 pub mod tank_pose_tracking;
+pub mod tracking_wheel_odometry;
+
+/// Source of the robot's motion between localization updates, e.g. drive encoders or tracking
+/// wheels.
+pub trait MotionModel {
+    /// Reads the sensors, called once per localization update.
+    async fn update(&mut self);
+
+    /// Samples the change in pose over the last update with noise, in field coordinates. `z` is
+    /// the change in heading.
+    fn sample(&mut self) -> StateRepresentation;
+
+    /// Change in position over the last update without noise, in field coordinates.
+    fn mean_displacement(&self) -> Vector2<f64>;
+
+    /// Distance the robot moved forward over the last update without noise, negative when it
+    /// drove backwards. Sideways travel isn't included, see [`MotionModel::mean_displacement`].
+    fn displacement(&self) -> f64;
+
+    /// Heading of the robot as of the last update, counter-clockwise positive.
+    fn heading(&self) -> Rotation2<f64>;
+
+    /// Change in heading over the last update, counter-clockwise positive.
+    fn heading_delta(&self) -> f64;
+
+    /// Whether the heading is still being measured properly.
+    fn heading_health(&self) -> HeadingHealth {
+        HeadingHealth::Healthy
+    }
+
+    /// Standard deviation of the distance travelled, relative to the distance.
+    fn drive_noise(&self) -> f64;
+
+    /// Standard deviation of the direction of travel in radians.
+    fn angle_noise(&self) -> f64;
+}
+
+/// Displacement in field coordinates after moving by `local` in the robot's frame along an arc
+/// that starts at `heading` and turns by `heading_delta`.
+pub fn arc_displacement(local: Vector2<f64>, heading: f64, heading_delta: f64) -> Vector2<f64> {
+    // Ratio of the chord to the arc, which is 1 when driving straight
+    let chord_ratio = if heading_delta.abs() < 1e-9 {
+        1.0
+    } else {
+        2.0 / heading_delta * (heading_delta / 2.0).sin()
+    };
+
+    // The chord points halfway between the start and end headings
+    Rotation2::new(heading + heading_delta / 2.0) * local * chord_ratio
+}
@@ -1,92 +1,320 @@
+use alloc::vec::Vec;
+
 use nalgebra::{Rotation2, Vector2};
 use rand::{rngs::SmallRng, Rng, SeedableRng};
 use rand_distr::Normal;
-use uom::si::{
-    angle::{degree, radian},
-    f64::Angle,
-};
-use vexide::{core::println, prelude::InertialSensor};
+use uom::si::{f64::Length, length::meter};
 
 use crate::{
-    localization::localization::StateRepresentation,
-    sensor::rotary::{RotarySensor, TrackingWheel},
+    hal::Imu,
+    localization::{
+        localization::StateRepresentation,
+        predict::{arc_displacement, MotionModel},
+    },
+    println,
+    sensor::{
+        imu::{HeadingHealth, ImuFusion},
+        rotary::{RotarySensor, TrackingWheel},
+    },
 };
 
-pub struct TankPoseTracking<T: RotarySensor> {
+/// Odometry from the drive encoders, turning by the heading change of the IMUs.
+///
+/// When every IMU is lost the heading change comes from the difference between the sides of the
+/// drive instead, which drifts as the wheels slip.
+pub struct TankPoseTracking<T: RotarySensor, I: Imu> {
     left_side: TrackingWheel<T>,
     right_side: TrackingWheel<T>,
-    orientation: InertialSensor,
+    orientation: ImuFusion<I>,
+    track_width: Length,
     left_delta: f64,
     right_delta: f64,
     heading: Rotation2<f64>,
+    heading_delta: f64,
     rng: SmallRng,
     pub drive_noise: f64,
     pub angle_noise: f64,
 }
 
-impl<T: RotarySensor> TankPoseTracking<T> {
+impl<T: RotarySensor, I: Imu> TankPoseTracking<T, I> {
     pub async fn new(
-        left_side: TrackingWheel<T>,
-        right_side: TrackingWheel<T>,
-        mut orientation: InertialSensor,
+        mut left_side: TrackingWheel<T>,
+        mut right_side: TrackingWheel<T>,
+        imus: Vec<I>,
+        track_width: Length,
         drive_noise: f64,
         angle_noise: f64,
     ) -> Self {
-        let _ = orientation.calibrate().await;
+        let mut orientation = ImuFusion::new(imus);
+        orientation.calibrate().await;
+        left_side.reset().await;
+        right_side.reset().await;
+
+        // Start from the current heading so the first update doesn't see a jump
+        let heading = orientation.heading().unwrap_or(0.0);
+
         Self {
             left_side,
             right_side,
             orientation,
+            track_width,
             left_delta: 0.0,
             right_delta: 0.0,
             drive_noise,
             angle_noise,
-            heading: Rotation2::new(0.0),
+            heading: Rotation2::new(heading),
+            heading_delta: 0.0,
             rng: SmallRng::seed_from_u64(0),
         }
     }
 
-    pub async fn update(&mut self) {
+    /// Samples the distance a side of the drive moved, with noise proportional to the distance.
+    fn sample_drive(&mut self, delta: f64) -> f64 {
+        // The deltas are signed when driving backwards, but the spread isn't
+        self.rng
+            .sample(Normal::new(delta, self.drive_noise * delta.abs()).unwrap())
+    }
+
+    fn start_heading(&self) -> f64 {
+        self.heading.angle() - self.heading_delta
+    }
+}
+
+impl<T: RotarySensor, I: Imu> MotionModel for TankPoseTracking<T, I> {
+    async fn update(&mut self) {
         // Update the deltas for each side of the drive this frame
         self.left_delta = self.left_side.update().await;
         self.right_delta = self.right_side.update().await;
 
         // Update the heading once per frame
-        self.heading = self.orientation();
+        let wheel_delta = (self.right_delta - self.left_delta) / self.track_width.get::<meter>();
+        let was_healthy = self.orientation.health() != HeadingHealth::WheelOdometry;
+
+        self.heading_delta = self
+            .orientation
+            .update(Some(wheel_delta))
+            .unwrap_or_else(|| {
+                if was_healthy {
+                    println!("WARNING: No IMU, using wheel odometry for heading");
+                }
+
+                wheel_delta
+            });
+        self.heading = Rotation2::new(self.heading.angle() + self.heading_delta);
     }
 
-    pub fn predict(&mut self) -> StateRepresentation {
+    /// The robot is moved along the arc through the heading change the IMU saw, with the wheels
+    /// giving the length of the arc.
+    fn sample(&mut self) -> StateRepresentation {
         // Calculate noise for each side of the drive
-        let left_noisy = self
-            .rng
-            .sample(Normal::new(self.left_delta, self.drive_noise * self.left_delta).unwrap());
-        let right_noisy = self
-            .rng
-            .sample(Normal::new(self.right_delta, self.drive_noise * self.right_delta).unwrap());
+        let left_noisy = self.sample_drive(self.left_delta);
+        let right_noisy = self.sample_drive(self.right_delta);
 
         // Because we are calculating from the center of the drive the mean is the displacement of
         // the center of rotation
-        let mean = left_noisy + right_noisy / 2.0;
+        let mean = (left_noisy + right_noisy) / 2.0;
 
-        // Calculate a local displacement vector from the current position of the robot
-        let local = Rotation2::new(
-            self.heading.angle() + self.rng.sample(Normal::new(0.0, self.angle_noise).unwrap()),
-        ) * Vector2::new(mean, 0.0);
+        let direction_noise = self.rng.sample(Normal::new(0.0, self.angle_noise).unwrap());
+        let displacement = arc_displacement(
+            Vector2::new(mean, 0.0),
+            self.start_heading() + direction_noise,
+            self.heading_delta,
+        );
 
-        // Create a new state representation without a angle(z) change
-        // We do this because the IMU is more than accurate over the entire match
-        StateRepresentation::new(local.x, local.y, 0.0)
+        StateRepresentation::new(displacement.x, displacement.y, self.heading_delta)
     }
 
-    pub fn orientation(&self) -> Rotation2<f64> {
-        // Convert to a Rotation2 object to use for linear algebra
+    fn mean_displacement(&self) -> Vector2<f64> {
+        arc_displacement(
+            Vector2::new(self.displacement(), 0.0),
+            self.start_heading(),
+            self.heading_delta,
+        )
+    }
+
+    /// Distance the center of the drive moved last frame, without noise.
+    fn displacement(&self) -> f64 {
+        (self.left_delta + self.right_delta) / 2.0
+    }
+
+    fn heading(&self) -> Rotation2<f64> {
+        self.heading
+    }
+
+    fn heading_delta(&self) -> f64 {
+        self.heading_delta
+    }
 
-        if let Ok(orientation) = self.orientation.heading() {
-            Rotation2::new(Angle::new::<degree>(-orientation).get::<radian>())
-        } else {
-            println!("WARNING: No IMU");
-            Rotation2::new(Angle::new::<degree>(0.0).get::<radian>())
-            // TODO: Fix orientation source when it's not there
+    fn heading_health(&self) -> HeadingHealth {
+        self.orientation.health()
+    }
+
+    fn drive_noise(&self) -> f64 {
+        self.drive_noise
+    }
+
+    fn angle_noise(&self) -> f64 {
+        self.angle_noise
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{rc::Rc, vec};
+    use core::{
+        cell::Cell,
+        f64::consts::{FRAC_PI_2, PI, TAU},
+    };
+
+    use uom::si::{angle::radian, f64::Angle};
+
+    use super::*;
+    use crate::sim::block_on;
+
+    /// Wheel whose travel in meters is set by the test, rolling on a 2 m wheel.
+    struct FakeWheel(Rc<Cell<f64>>);
+
+    impl RotarySensor for FakeWheel {
+        async fn pos(&self) -> Option<f64> {
+            Some(self.0.get())
+        }
+    }
+
+    /// IMU whose heading in radians is set by the test.
+    struct FakeImu(Rc<Cell<Option<f64>>>);
+
+    impl Imu for FakeImu {
+        async fn calibrate(&mut self) {}
+
+        fn heading(&self) -> Option<Angle> {
+            self.0.get().map(Angle::new::<radian>)
         }
     }
+
+    struct Rig {
+        left: Rc<Cell<f64>>,
+        right: Rc<Cell<f64>>,
+        heading: Rc<Cell<Option<f64>>>,
+        tracking: TankPoseTracking<FakeWheel, FakeImu>,
+    }
+
+    impl Rig {
+        fn new(heading: f64, drive_noise: f64, angle_noise: f64) -> Self {
+            let left = Rc::new(Cell::new(0.0));
+            let right = Rc::new(Cell::new(0.0));
+            let imu = Rc::new(Cell::new(Some(heading)));
+
+            let wheel = |travel: &Rc<Cell<f64>>| {
+                TrackingWheel::new(FakeWheel(travel.clone()), Length::new::<meter>(2.0), None)
+            };
+            let tracking = block_on(TankPoseTracking::new(
+                wheel(&left),
+                wheel(&right),
+                vec![FakeImu(imu.clone())],
+                Length::new::<meter>(0.5),
+                drive_noise,
+                angle_noise,
+            ));
+
+            Self {
+                left,
+                right,
+                heading: imu,
+                tracking,
+            }
+        }
+
+        /// Moves the wheels and IMU by the given deltas and returns the predicted step.
+        fn step(&mut self, left: f64, right: f64, heading: f64) -> StateRepresentation {
+            self.left.set(self.left.get() + left);
+            self.right.set(self.right.get() + right);
+            self.heading
+                .set(self.heading.get().map(|current| current + heading));
+
+            block_on(self.tracking.update());
+            self.tracking.sample()
+        }
+    }
+
+    #[test]
+    fn drives_straight() {
+        let mut rig = Rig::new(FRAC_PI_2, 0.0, 0.0);
+
+        let step = rig.step(0.5, 0.5, 0.0);
+        assert!(step.x.abs() < 1e-9);
+        assert!((step.y - 0.5).abs() < 1e-9);
+        assert_eq!(step.z, 0.0);
+    }
+
+    #[test]
+    fn follows_arc() {
+        let mut rig = Rig::new(0.0, 0.0, 0.0);
+
+        // A quarter circle of radius 1 with a 0.5 track, in one step
+        let step = rig.step(0.75 * FRAC_PI_2, 1.25 * FRAC_PI_2, FRAC_PI_2);
+        assert!((step.x - 1.0).abs() < 1e-9);
+        assert!((step.y - 1.0).abs() < 1e-9);
+        assert!((step.z - FRAC_PI_2).abs() < 1e-9);
+        assert!((rig.tracking.mean_displacement() - step.xy()).magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn closes_circle_in_small_steps() {
+        let mut rig = Rig::new(0.0, 0.0, 0.0);
+        let mut position = Vector2::zeros();
+
+        let steps = 50;
+        let turn = TAU / steps as f64;
+        for _ in 0..steps {
+            position += rig.step(0.75 * turn, 1.25 * turn, turn).xy();
+        }
+
+        assert!(position.magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn heading_delta_wraps() {
+        let mut rig = Rig::new(TAU - 0.1, 0.0, 0.0);
+
+        // The IMU reads in [0, TAU), so crossing zero jumps back by almost a turn
+        rig.heading.set(Some(0.1));
+        block_on(rig.tracking.update());
+
+        assert!((rig.tracking.heading_delta() - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reverses_with_noise() {
+        let mut rig = Rig::new(PI, 0.1, 0.01);
+
+        let mut total = 0.0;
+        for _ in 0..100 {
+            total += rig.step(-0.1, -0.1, 0.0).x;
+        }
+
+        // Backwards while facing -x is towards +x
+        assert!((total - 10.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn falls_back_to_wheels_without_imu() {
+        let mut rig = Rig::new(FRAC_PI_2, 0.0, 0.0);
+        assert_eq!(rig.tracking.heading_health(), HeadingHealth::Healthy);
+
+        rig.heading.set(None);
+
+        // Turning in place by 0.2 on the 0.5 track
+        let step = rig.step(-0.05, 0.05, 0.0);
+        assert!(step.xy().magnitude() < 1e-9);
+        assert!((step.z - 0.2).abs() < 1e-9);
+        assert!((rig.tracking.heading().angle() - (FRAC_PI_2 + 0.2)).abs() < 1e-9);
+        assert_eq!(rig.tracking.heading_health(), HeadingHealth::WheelOdometry);
+
+        // Picks up from the IMU again once it's back, without a jump
+        rig.heading.set(Some(1.0));
+        rig.step(0.0, 0.0, 0.0);
+        rig.step(0.0, 0.0, 0.1);
+        assert!((rig.tracking.heading().angle() - (FRAC_PI_2 + 0.3)).abs() < 1e-9);
+        assert_eq!(rig.tracking.heading_health(), HeadingHealth::Healthy);
+    }
 }
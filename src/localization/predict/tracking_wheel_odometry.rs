@@ -0,0 +1,356 @@
+//! Odometry from unpowered tracking wheels, which keep rolling with the field when the drive
+//! wheels slip.
+
+use alloc::vec::Vec;
+
+use nalgebra::{Rotation2, Vector2};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rand_distr::Normal;
+use uom::si::{f64::Length, length::meter};
+
+use crate::{
+    hal::Imu,
+    localization::{
+        localization::StateRepresentation,
+        predict::{arc_displacement, MotionModel},
+    },
+    println,
+    sensor::{
+        imu::{HeadingHealth, ImuFusion},
+        rotary::{RotarySensor, TrackingWheel},
+    },
+};
+
+/// Where the tracking wheels sit relative to the center of rotation of the robot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackingWheelOffsets {
+    /// Sideways offset of the left parallel wheel, left positive. With a single parallel wheel
+    /// this is that wheel's offset.
+    pub left: Length,
+    /// Sideways offset of the right parallel wheel, left positive.
+    pub right: Length,
+    /// Forward offset of the perpendicular wheel, which reads positive rolling to the left.
+    pub perpendicular: Length,
+}
+
+/// Predictor over two parallel tracking wheels and one perpendicular one, which measures how far
+/// the robot slid sideways.
+///
+/// The heading change comes from the difference between the parallel wheels. With a single
+/// parallel wheel it comes from the IMUs instead, which are also read once at startup for the
+/// starting heading.
+pub struct TrackingWheelOdometry<T: RotarySensor, I: Imu> {
+    left: TrackingWheel<T>,
+    right: Option<TrackingWheel<T>>,
+    perpendicular: TrackingWheel<T>,
+    offsets: TrackingWheelOffsets,
+    orientation: ImuFusion<I>,
+    left_delta: f64,
+    right_delta: f64,
+    perpendicular_delta: f64,
+    heading: Rotation2<f64>,
+    heading_delta: f64,
+    rng: SmallRng,
+    pub drive_noise: f64,
+    pub angle_noise: f64,
+}
+
+impl<T: RotarySensor, I: Imu> TrackingWheelOdometry<T, I> {
+    /// Two wheel odometry, add the right wheel with [`TrackingWheelOdometry::with_right_wheel`].
+    pub async fn new(
+        mut left: TrackingWheel<T>,
+        mut perpendicular: TrackingWheel<T>,
+        offsets: TrackingWheelOffsets,
+        imus: Vec<I>,
+        drive_noise: f64,
+        angle_noise: f64,
+    ) -> Self {
+        let mut orientation = ImuFusion::new(imus);
+        orientation.calibrate().await;
+        left.reset().await;
+        perpendicular.reset().await;
+
+        let heading = orientation.heading().unwrap_or(0.0);
+
+        Self {
+            left,
+            right: None,
+            perpendicular,
+            offsets,
+            orientation,
+            left_delta: 0.0,
+            right_delta: 0.0,
+            perpendicular_delta: 0.0,
+            heading: Rotation2::new(heading),
+            heading_delta: 0.0,
+            rng: SmallRng::seed_from_u64(0),
+            drive_noise,
+            angle_noise,
+        }
+    }
+
+    pub async fn with_right_wheel(mut self, mut right: TrackingWheel<T>) -> Self {
+        right.reset().await;
+        self.right = Some(right);
+        self
+    }
+
+    /// Movement of the center of rotation in the robot's frame for the given wheel deltas.
+    fn local_displacement(&self, left: f64, right: f64, perpendicular: f64) -> Vector2<f64> {
+        // Turning moves each wheel by its offset times the heading change on top of the center
+        let forward = if self.right.is_some() {
+            (left + right) / 2.0
+                + (self.offsets.left + self.offsets.right).get::<meter>() / 2.0 * self.heading_delta
+        } else {
+            left + self.offsets.left.get::<meter>() * self.heading_delta
+        };
+        let sideways =
+            perpendicular - self.offsets.perpendicular.get::<meter>() * self.heading_delta;
+
+        Vector2::new(forward, sideways)
+    }
+
+    fn sample_drive(&mut self, delta: f64) -> f64 {
+        self.rng
+            .sample(Normal::new(delta, self.drive_noise * delta.abs()).unwrap())
+    }
+
+    fn start_heading(&self) -> f64 {
+        self.heading.angle() - self.heading_delta
+    }
+}
+
+impl<T: RotarySensor, I: Imu> MotionModel for TrackingWheelOdometry<T, I> {
+    async fn update(&mut self) {
+        self.left_delta = self.left.update().await;
+        self.perpendicular_delta = self.perpendicular.update().await;
+
+        if let Some(right) = self.right.as_mut() {
+            self.right_delta = right.update().await;
+
+            let spacing = (self.offsets.left - self.offsets.right).get::<meter>();
+            self.heading_delta = (self.right_delta - self.left_delta) / spacing;
+            self.heading = Rotation2::new(self.heading.angle() + self.heading_delta);
+        } else {
+            let was_healthy = self.orientation.health() != HeadingHealth::WheelOdometry;
+
+            self.heading_delta = self.orientation.update(None).unwrap_or_else(|| {
+                if was_healthy {
+                    println!("WARNING: No IMU, the heading is lost");
+                }
+
+                0.0
+            });
+            self.heading = Rotation2::new(self.heading.angle() + self.heading_delta);
+        }
+    }
+
+    fn sample(&mut self) -> StateRepresentation {
+        let left = self.sample_drive(self.left_delta);
+        let right = self.sample_drive(self.right_delta);
+        let perpendicular = self.sample_drive(self.perpendicular_delta);
+
+        let direction_noise = self.rng.sample(Normal::new(0.0, self.angle_noise).unwrap());
+        let displacement = arc_displacement(
+            self.local_displacement(left, right, perpendicular),
+            self.start_heading() + direction_noise,
+            self.heading_delta,
+        );
+
+        StateRepresentation::new(displacement.x, displacement.y, self.heading_delta)
+    }
+
+    fn mean_displacement(&self) -> Vector2<f64> {
+        arc_displacement(
+            self.local_displacement(self.left_delta, self.right_delta, self.perpendicular_delta),
+            self.start_heading(),
+            self.heading_delta,
+        )
+    }
+
+    fn displacement(&self) -> f64 {
+        self.local_displacement(self.left_delta, self.right_delta, self.perpendicular_delta)
+            .x
+    }
+
+    fn heading(&self) -> Rotation2<f64> {
+        self.heading
+    }
+
+    fn heading_delta(&self) -> f64 {
+        self.heading_delta
+    }
+
+    fn heading_health(&self) -> HeadingHealth {
+        if self.right.is_some() {
+            HeadingHealth::Healthy
+        } else {
+            // A single parallel wheel can't tell the heading on its own
+            match self.orientation.health() {
+                HeadingHealth::WheelOdometry => HeadingHealth::Lost,
+                health => health,
+            }
+        }
+    }
+
+    fn drive_noise(&self) -> f64 {
+        self.drive_noise
+    }
+
+    fn angle_noise(&self) -> f64 {
+        self.angle_noise
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{rc::Rc, vec};
+    use core::{
+        cell::Cell,
+        f64::consts::{FRAC_PI_2, PI},
+    };
+
+    use uom::si::{angle::radian, f64::Angle};
+
+    use super::*;
+    use crate::sim::block_on;
+
+    /// Wheel whose travel in meters is set by the test, rolling on a 2 m wheel.
+    struct FakeWheel(Rc<Cell<f64>>);
+
+    impl RotarySensor for FakeWheel {
+        async fn pos(&self) -> Option<f64> {
+            Some(self.0.get())
+        }
+    }
+
+    /// IMU whose heading in radians is set by the test.
+    struct FakeImu(Rc<Cell<f64>>);
+
+    impl Imu for FakeImu {
+        async fn calibrate(&mut self) {}
+
+        fn heading(&self) -> Option<Angle> {
+            Some(Angle::new::<radian>(self.0.get()))
+        }
+    }
+
+    fn offsets() -> TrackingWheelOffsets {
+        TrackingWheelOffsets {
+            left: Length::new::<meter>(0.1),
+            right: Length::new::<meter>(-0.1),
+            perpendicular: Length::new::<meter>(-0.05),
+        }
+    }
+
+    struct Rig {
+        left: Rc<Cell<f64>>,
+        right: Rc<Cell<f64>>,
+        perpendicular: Rc<Cell<f64>>,
+        heading: Rc<Cell<f64>>,
+        odometry: TrackingWheelOdometry<FakeWheel, FakeImu>,
+    }
+
+    impl Rig {
+        fn new(heading: f64, three_wheel: bool, drive_noise: f64) -> Self {
+            // Rotation sensors keep their position across restarts, so they don't start at zero
+            let left = Rc::new(Cell::new(1.0));
+            let right = Rc::new(Cell::new(-2.0));
+            let perpendicular = Rc::new(Cell::new(3.0));
+            let imu = Rc::new(Cell::new(heading));
+
+            let wheel = |travel: &Rc<Cell<f64>>| {
+                TrackingWheel::new(FakeWheel(travel.clone()), Length::new::<meter>(2.0), None)
+            };
+            let mut odometry = block_on(TrackingWheelOdometry::new(
+                wheel(&left),
+                wheel(&perpendicular),
+                offsets(),
+                vec![FakeImu(imu.clone())],
+                drive_noise,
+                0.0,
+            ));
+            if three_wheel {
+                odometry = block_on(odometry.with_right_wheel(wheel(&right)));
+            }
+
+            Self {
+                left,
+                right,
+                perpendicular,
+                heading: imu,
+                odometry,
+            }
+        }
+
+        fn step(
+            &mut self,
+            left: f64,
+            right: f64,
+            perpendicular: f64,
+            heading: f64,
+        ) -> StateRepresentation {
+            self.left.set(self.left.get() + left);
+            self.right.set(self.right.get() + right);
+            self.perpendicular
+                .set(self.perpendicular.get() + perpendicular);
+            self.heading.set(self.heading.get() + heading);
+
+            block_on(self.odometry.update());
+            self.odometry.sample()
+        }
+    }
+
+    #[test]
+    fn drives_straight() {
+        let mut rig = Rig::new(0.0, true, 0.0);
+
+        let step = rig.step(0.5, 0.5, 0.0, 0.0);
+        assert!((step.xy() - Vector2::new(0.5, 0.0)).magnitude() < 1e-9);
+        assert_eq!(step.z, 0.0);
+    }
+
+    #[test]
+    fn turns_in_place() {
+        let mut rig = Rig::new(0.0, true, 0.0);
+
+        // Every wheel rolls by its offset times the turn
+        let step = rig.step(-0.1 * 0.2, 0.1 * 0.2, -0.05 * 0.2, 0.0);
+        assert!(step.xy().magnitude() < 1e-9);
+        assert!((step.z - 0.2).abs() < 1e-9);
+        assert!((rig.odometry.heading().angle() - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn measures_sideways_slip() {
+        let mut rig = Rig::new(FRAC_PI_2, true, 0.0);
+
+        // Pushed to the robot's left, which is -x facing +y
+        let step = rig.step(0.0, 0.0, 0.3, 0.0);
+        assert!((step.xy() - Vector2::new(-0.3, 0.0)).magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn two_wheel_uses_imu() {
+        let mut rig = Rig::new(0.0, false, 0.0);
+
+        // Quarter circle of radius 1, with the parallel wheel 0.1 inside of it
+        let step = rig.step(0.9 * FRAC_PI_2, 0.0, -0.05 * FRAC_PI_2, FRAC_PI_2);
+        assert!((step.xy() - Vector2::new(1.0, 1.0)).magnitude() < 1e-9);
+        assert!((step.z - FRAC_PI_2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reverses_with_noise() {
+        let mut rig = Rig::new(PI, true, 0.1);
+
+        let mut total = Vector2::zeros();
+        for _ in 0..100 {
+            total += rig.step(-0.1, -0.1, -0.01, 0.0).xy();
+        }
+
+        // Backwards and to the right while facing -x is towards +x and +y
+        assert!((total - Vector2::new(10.0, 1.0)).magnitude() < 0.5);
+        assert!(rig.odometry.displacement() < 0.0);
+    }
+}
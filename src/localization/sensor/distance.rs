@@ -1,57 +1,316 @@
-use nalgebra::{Rotation2, Vector2, Vector3};
-use vexide::prelude::DistanceSensor;
+use alloc::sync::Arc;
+
+use nalgebra::{DMatrix, DVector, Rotation2, Vector2, Vector3};
+use uom::{
+    num_traits::Float,
+    si::{f64::Length, length::meter},
+};
 
 use crate::{
-    config::WALLS,
-    localization::{localization::StateRepresentation, sensor::Sensor},
+    hal::RangeFinder,
+    localization::{
+        field_map::FieldMap,
+        localization::StateRepresentation,
+        sensor::{MeasurementModel, Sensor},
+    },
     utils,
 };
 
-pub struct WallDistanceSensor {
-    distance: DistanceSensor,
+/// Smallest standard deviation of a reading in meters, so a wall right at the sensor doesn't
+/// make the hit distribution infinitely narrow.
+const MIN_STD: f64 = 0.015;
+
+/// Mixture weights of the beam model, see Probabilistic Robotics chapter 6.3.
+#[derive(Clone, Copy, Debug)]
+pub struct BeamModel {
+    /// Reading off the wall we expect, with gaussian noise.
+    pub hit: f64,
+    /// Reading off something in front of the wall, like another robot.
+    pub short: f64,
+    /// No return within the sensor's range.
+    pub max: f64,
+    /// Reading anywhere in range for no reason.
+    pub random: f64,
+    /// Rate of the exponential distribution of short readings, per meter.
+    pub short_rate: f64,
+}
+
+impl Default for BeamModel {
+    fn default() -> Self {
+        Self {
+            hit: 0.8,
+            short: 0.1,
+            max: 0.05,
+            random: 0.05,
+            short_rate: 1.0,
+        }
+    }
+}
+
+/// Reading of the sensor, in meters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Reading {
+    Range(f64),
+    /// Nothing within the sensor's range.
+    Max,
+}
+
+pub struct WallDistanceSensor<D: RangeFinder> {
+    distance: D,
     sensor_pose: Vector3<f64>,
+    max_range: Length,
+    beam_model: BeamModel,
+    field: Arc<FieldMap>,
 }
 
-impl WallDistanceSensor {
-    pub fn new(distance: DistanceSensor, sensor_pose: Vector3<f64>) -> Self {
+impl<D: RangeFinder> WallDistanceSensor<D> {
+    /// `sensor_pose` is the position and heading of the sensor relative to the center of the
+    /// robot.
+    pub fn new(
+        distance: D,
+        sensor_pose: Vector3<f64>,
+        max_range: Length,
+        field: Arc<FieldMap>,
+    ) -> Self {
         Self {
             distance,
             sensor_pose,
+            max_range,
+            beam_model: BeamModel::default(),
+            field,
         }
     }
+
+    pub fn with_beam_model(mut self, beam_model: BeamModel) -> Self {
+        self.beam_model = beam_model;
+        self
+    }
 }
 
-impl Sensor for WallDistanceSensor {
-    fn p(&self, x: &StateRepresentation) -> Option<f64> {
-        if let Some(Some(measured)) = self.distance.distance().ok()
-            && self.distance.relative_size().ok()?? > 50
-        {
-            let measured_meters = measured as f64 / 1000.0;
+impl<D: RangeFinder> WallDistanceSensor<D> {
+    /// Current reading, `None` if the sensor can't be read, has no confidence in the reading or
+    /// the object is too small to be a wall.
+    fn measured(&self) -> Option<Reading> {
+        // Confidence is only missing when the sensor itself can't be read
+        if self.distance.confidence()? <= 0.0 {
+            return None;
+        }
 
-            let v_1 = Rotation2::new(-x.z) * Vector2::new(self.sensor_pose.x, self.sensor_pose.y);
-            let v_2 = Rotation2::new(self.sensor_pose.z + x.z) * Vector2::new(1.0, 0.0);
+        let max_range = self.max_range.get::<meter>();
 
-            let predicted = WALLS
-                .iter()
-                .filter_map(|(v_3, v_4)| {
-                    let t = (((v_1.x - v_3.x) * (v_3.y - v_4.y))
-                        - ((v_1.y - v_3.y) * (v_3.x - v_4.x)))
-                        / (((v_1.x - v_2.x) * (v_3.y - v_4.y))
-                            - ((v_1.y - v_2.y) * (v_3.x - v_4.x)));
+        match self.distance.distance() {
+            Some(measured) if measured.get::<meter>() < max_range => {
+                if self.distance.object_size()? > 50 {
+                    Some(Reading::Range(measured.get::<meter>()))
+                } else {
+                    None
+                }
+            }
+            _ => Some(Reading::Max),
+        }
+    }
 
-                    if t > 0.0 || t.is_finite() {
-                        Some(t)
-                    } else {
-                        None
-                    }
-                })
-                .min_by(|a, b| a.partial_cmp(b).unwrap())?;
+    /// Distance the sensor would read if the robot were at `x`, `None` if the wall is out of
+    /// range.
+    fn predicted(&self, x: &StateRepresentation) -> Option<f64> {
+        let origin = Vector2::new(x.x, x.y)
+            + Rotation2::new(x.z) * Vector2::new(self.sensor_pose.x, self.sensor_pose.y);
+        let direction = Rotation2::new(x.z + self.sensor_pose.z) * Vector2::new(1.0, 0.0);
 
-            let std = 0.025 * predicted / self.distance.distance_confidence().ok()?;
+        self.field
+            .ray_cast(&origin, &direction)
+            .filter(|distance| *distance < self.max_range.get::<meter>())
+    }
 
-            Some(utils::normal_pdf(measured_meters, predicted, std))
-        } else {
-            None
+    fn std(&self, distance: f64) -> Option<f64> {
+        Some((0.025 * distance / self.distance.confidence()?).max(MIN_STD))
+    }
+}
+
+impl<D: RangeFinder> Sensor for WallDistanceSensor<D> {
+    fn log_p(&self, x: &StateRepresentation) -> Option<f64> {
+        let measured = self.measured()?;
+        let predicted = self.predicted(x);
+        let max_range = self.max_range.get::<meter>();
+        let model = &self.beam_model;
+
+        let p = match (measured, predicted) {
+            // Clipping the expected reading at the max range, a wall past it is a hit and a wall
+            // well within it only leaves a missed return
+            (Reading::Max, predicted) => {
+                let predicted = predicted.unwrap_or(max_range);
+
+                model.hit * utils::normal_pdf(max_range, predicted, self.std(predicted)?)
+                    + model.max
+                    + model.random / max_range
+            }
+            (Reading::Range(measured), Some(predicted)) => {
+                let hit = utils::normal_pdf(measured, predicted, self.std(predicted)?);
+
+                let short = if measured <= predicted {
+                    model.short_rate * (-model.short_rate * measured).exp()
+                        / (1.0 - (-model.short_rate * predicted).exp())
+                } else {
+                    0.0
+                };
+
+                model.hit * hit + model.short * short + model.random / max_range
+            }
+            // The wall is out of range, so an in range reading is off something else
+            (Reading::Range(_), None) => model.random / max_range,
+        };
+
+        Some(p.ln())
+    }
+}
+
+impl<D: RangeFinder> MeasurementModel for WallDistanceSensor<D> {
+    fn measure(&self) -> Option<(DVector<f64>, DMatrix<f64>)> {
+        let Reading::Range(measured) = self.measured()? else {
+            return None;
+        };
+        let std = self.std(measured)?;
+
+        Some((
+            DVector::from_element(1, measured),
+            DMatrix::from_element(1, 1, std * std),
+        ))
+    }
+
+    fn predict(&self, x: &StateRepresentation) -> Option<DVector<f64>> {
+        Some(DVector::from_element(1, self.predicted(x)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f64::consts::FRAC_PI_2;
+
+    use uom::si::length::millimeter;
+
+    use super::*;
+    use crate::{
+        config::{field_map, FIELD_MAX},
+        localization::field_map::Alliance,
+    };
+
+    /// Range finder with a fixed reading and confidence.
+    struct FixedRange(Option<f64>, f64);
+
+    impl RangeFinder for FixedRange {
+        fn distance(&self) -> Option<Length> {
+            self.0.map(Length::new::<meter>)
+        }
+
+        fn confidence(&self) -> Option<f64> {
+            Some(self.1)
         }
+
+        fn object_size(&self) -> Option<u32> {
+            Some(400)
+        }
+    }
+
+    fn sensor(distance: Option<f64>, sensor_pose: Vector3<f64>) -> WallDistanceSensor<FixedRange> {
+        WallDistanceSensor::new(
+            FixedRange(distance, 1.0),
+            sensor_pose,
+            Length::new::<millimeter>(2000.0),
+            Arc::new(field_map(Alliance::Red)),
+        )
+    }
+
+    #[test]
+    fn predicts_wall_distance() {
+        let sensor = sensor(None, Vector3::new(0.1, 0.0, 0.0));
+
+        let predicted = sensor
+            .predicted(&StateRepresentation::new(0.5, -1.5, 0.0))
+            .unwrap();
+        assert!((predicted - (FIELD_MAX - 0.6)).abs() < 1e-9);
+
+        // Out of range
+        assert_eq!(
+            sensor.predicted(&StateRepresentation::new(-1.5, -1.5, 0.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn offset_rotates_with_robot() {
+        // Sensor on the left side of the robot, pointing left
+        let sensor = sensor(None, Vector3::new(0.0, 0.1, FRAC_PI_2));
+
+        // Facing +y the sensor is at -x of the center and points towards -x
+        let predicted = sensor
+            .predicted(&StateRepresentation::new(-1.0, 1.5, FRAC_PI_2))
+            .unwrap();
+        assert!((predicted - (FIELD_MAX - 1.1)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn prefers_matching_pose() {
+        let sensor = sensor(Some(FIELD_MAX - 0.6), Vector3::new(0.1, 0.0, 0.0));
+
+        let matching = sensor
+            .log_p(&StateRepresentation::new(0.5, -1.5, 0.0))
+            .unwrap();
+        let off = sensor
+            .log_p(&StateRepresentation::new(0.3, -1.5, 0.0))
+            .unwrap();
+
+        assert!(matching > off);
+        assert!(off.is_finite());
+    }
+
+    #[test]
+    fn short_reading_is_not_ruled_out() {
+        // Something between the robot and the wall
+        let sensor = sensor(Some(0.3), Vector3::new(0.1, 0.0, 0.0));
+        let pose = StateRepresentation::new(0.5, -1.5, 0.0);
+
+        let short = sensor.log_p(&pose).unwrap();
+        let random = (BeamModel::default().random / 2.0).ln();
+
+        assert!(short > random);
+    }
+
+    #[test]
+    fn max_reading_expects_wall_out_of_range() {
+        let sensor = sensor(None, Vector3::new(0.1, 0.0, 0.0));
+
+        let out_of_range = sensor
+            .log_p(&StateRepresentation::new(-1.5, -1.5, 0.0))
+            .unwrap();
+        let in_range = sensor
+            .log_p(&StateRepresentation::new(0.5, -1.5, 0.0))
+            .unwrap();
+
+        // A wall 1.2m away should have been seen, but a missed return keeps it possible
+        assert!(out_of_range > in_range + 3.0, "{out_of_range} {in_range}");
+        assert!(in_range.is_finite());
+        assert!(sensor.measure().is_none());
+    }
+
+    #[test]
+    fn wall_at_sensor_is_finite() {
+        let sensor = sensor(Some(0.01), Vector3::new(0.1, 0.0, 0.0));
+        let pose = StateRepresentation::new(FIELD_MAX - 0.1, -1.5, 0.0);
+
+        assert_eq!(sensor.predicted(&pose), Some(0.0));
+        assert!(sensor.log_p(&pose).unwrap().is_finite());
+    }
+
+    #[test]
+    fn ignores_reading_without_confidence() {
+        let sensor = WallDistanceSensor::new(
+            FixedRange(Some(1.0), 0.0),
+            Vector3::new(0.1, 0.0, 0.0),
+            Length::new::<millimeter>(2000.0),
+            Arc::new(field_map(Alliance::Red)),
+        );
+
+        assert_eq!(sensor.measured(), None);
+        assert!(sensor.measure().is_none());
     }
 }
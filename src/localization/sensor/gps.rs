@@ -1,29 +1,75 @@
-use nalgebra::Vector2;
-use vexide::devices::smart::GpsSensor;
+use nalgebra::{DMatrix, DVector, Vector2};
 
 use crate::{
     config::GPS_ANGLE_DIFF_MAX,
-    localization::{localization::StateRepresentation, sensor::Sensor},
-    utils::{angle_difference, normal_pdf},
+    hal::Gps,
+    localization::{
+        localization::StateRepresentation,
+        sensor::{MeasurementModel, Sensor},
+    },
+    utils::{angle_difference, normal_log_pdf},
 };
 
-const GPS_BAD_BITFLAG: u32 = 0b0000_0100_0000_0000;
+pub struct GpsPoseSensor<G: Gps> {
+    gps: G,
+}
 
-impl Sensor for GpsSensor {
-    fn p(&self, x: &StateRepresentation) -> Option<f64> {
-        let pose = self.pose().ok()?;
+impl<G: Gps> GpsPoseSensor<G> {
+    pub fn new(gps: G) -> Self {
+        Self { gps }
+    }
+}
 
-        let std = self.error().ok()? * 2.0;
+impl<G: Gps> Sensor for GpsPoseSensor<G> {
+    fn log_p(&self, x: &StateRepresentation) -> Option<f64> {
+        let (position, heading) = self.gps.pose()?;
 
-        if angle_difference(pose.1, x.z) > GPS_ANGLE_DIFF_MAX
-            || self.status().ok()? & GPS_BAD_BITFLAG != 0
-        {
+        let std = self.gps.error()? * 2.0;
+
+        if angle_difference(heading, x.z).abs() > GPS_ANGLE_DIFF_MAX {
             None
         } else {
-            let position = Vector2::new(pose.0.x, pose.0.y);
             let predicted = Vector2::new(x.x, x.y);
 
-            Some(normal_pdf((position - predicted).magnitude(), 0.0, std))
+            Some(normal_log_pdf((position - predicted).magnitude(), 0.0, std))
         }
     }
 }
+
+impl<G: Gps> MeasurementModel for GpsPoseSensor<G> {
+    fn measure(&self) -> Option<(DVector<f64>, DMatrix<f64>)> {
+        let (position, _) = self.gps.pose()?;
+
+        let std = self.gps.error()? * 2.0;
+
+        Some((
+            DVector::from_column_slice(position.as_slice()),
+            DMatrix::from_diagonal_element(2, 2, std * std),
+        ))
+    }
+
+    fn predict(&self, x: &StateRepresentation) -> Option<DVector<f64>> {
+        Some(DVector::from_column_slice(&[x.x, x.y]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{devices::SimGps, drive_plant};
+
+    #[test]
+    fn gates_heading_in_both_directions() {
+        let plant = drive_plant();
+        let pose = StateRepresentation::new(0.5, -0.3, 1.0);
+        plant.borrow_mut().set_pose(pose);
+        let sensor = GpsPoseSensor::new(SimGps::new(plant, 0.0));
+
+        let turned = |offset: f64| StateRepresentation::new(pose.x, pose.y, pose.z + offset);
+
+        assert!(sensor.log_p(&pose).is_some());
+        assert!(sensor.log_p(&turned(2.0 * GPS_ANGLE_DIFF_MAX)).is_none());
+        // The difference is signed, particles behind the GPS heading are gated too
+        assert!(sensor.log_p(&turned(-2.0 * GPS_ANGLE_DIFF_MAX)).is_none());
+    }
+}
@@ -1,57 +1,221 @@
+use alloc::sync::Arc;
+
 use nalgebra::{Rotation2, Vector2};
-use uom::si::{f64::Length, length::meter};
-use vexide::prelude::AdiLineTracker;
+use uom::{
+    num_traits::Float,
+    si::{f64::Length, length::meter},
+};
 
 use crate::{
-    config::FIELD_TAPES,
-    localization::{localization::StateRepresentation, sensor::Sensor},
+    hal::Reflectance,
+    localization::{field_map::FieldMap, localization::StateRepresentation, sensor::Sensor},
+    utils::normal_log_pdf,
 };
 
-pub struct LineTrackerSensor {
-    line_tracker: AdiLineTracker,
+/// Reflectivity the line tracker reads over the field tiles and over the tape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineCalibration {
+    pub tile: f64,
+    pub tape: f64,
+}
+
+impl LineCalibration {
+    /// Readings above this are tape.
+    pub fn threshold(&self) -> f64 {
+        (self.tile + self.tape) / 2.0
+    }
+}
+
+pub struct LineTrackerSensor<L: Reflectance> {
+    line_tracker: L,
     position: Vector2<f64>,
-    line_sensor_threshold: f64,
-    distance_threshold: Length,
+    calibration: LineCalibration,
+    edge_std: Length,
+    field: Arc<FieldMap>,
+    on_tape: Option<bool>,
+    edge: bool,
 }
 
-impl LineTrackerSensor {
+impl<L: Reflectance> LineTrackerSensor<L> {
+    /// `position` is the position of the sensor relative to the center of the robot, and
+    /// `edge_std` how precisely a crossing puts the sensor on the edge of a tape.
     pub fn new(
-        line_tracker: AdiLineTracker,
+        line_tracker: L,
         position: Vector2<f64>,
-        line_sensor_threshold: f64,
-        distance_threshold: Length,
+        calibration: LineCalibration,
+        edge_std: Length,
+        field: Arc<FieldMap>,
     ) -> Self {
         Self {
             line_tracker,
             position,
-            line_sensor_threshold,
-            distance_threshold,
+            calibration,
+            edge_std,
+            field,
+            on_tape: None,
+            edge: false,
+        }
+    }
+
+    /// Takes the current reading as the tile reflectivity, for when the robot is known to be off
+    /// the tape, e.g. sitting in its starting tile.
+    pub fn calibrate_tile(&mut self) -> Option<()> {
+        self.calibration.tile = self.line_tracker.reflectivity()?;
+        Some(())
+    }
+
+    pub fn calibration(&self) -> LineCalibration {
+        self.calibration
+    }
+
+    /// Distance from the sensor to the nearest tape if the robot were at `x`.
+    fn tape_distance(&self, x: &StateRepresentation) -> Option<f64> {
+        let sensor_position = Vector2::new(x.x, x.y) + Rotation2::new(x.z) * self.position;
+
+        self.field.nearest_tape(&sensor_position)
+    }
+}
+
+impl<L: Reflectance> Sensor for LineTrackerSensor<L> {
+    fn log_p(&self, x: &StateRepresentation) -> Option<f64> {
+        let measured = self.on_tape?;
+        let distance = self.tape_distance(x)?;
+        let half_width = self.field.tape_width().get::<meter>() / 2.0;
+
+        // The sensor just crossed the edge of a tape, which pins it down across the tape but says
+        // nothing about where along it
+        if self.edge {
+            return Some(normal_log_pdf(
+                distance,
+                half_width,
+                self.edge_std.get::<meter>(),
+            ));
+        }
+
+        if measured == (distance < half_width) {
+            Some(0.9_f64.ln())
+        } else {
+            Some(0.1_f64.ln())
         }
     }
+
+    fn tick(&mut self) -> bool {
+        let on_tape = self
+            .line_tracker
+            .reflectivity()
+            .map(|reflectivity| reflectivity > self.calibration.threshold());
+
+        self.edge =
+            matches!((self.on_tape, on_tape), (Some(last), Some(current)) if last != current);
+        self.on_tape = on_tape;
+
+        self.edge
+    }
 }
 
-impl Sensor for LineTrackerSensor {
-    fn p(&self, x: &StateRepresentation) -> Option<f64> {
-        let measured = self.line_tracker.reflectivity().ok()? > self.line_sensor_threshold;
-        let sensor_position = Rotation2::new(-x.z) * self.position + Vector2::new(x.x, x.y);
-
-        let predicted = FIELD_TAPES
-            .into_iter()
-            .map(|tape| {
-                ((tape.0.y - tape.1.y) * sensor_position.y
-                    - (tape.0.x - tape.1.x) * sensor_position.x
-                    + tape.1.x * tape.0.y
-                    - tape.1.y * tape.0.x)
-                    / (tape.1 - tape.0).magnitude()
-            })
-            .min_by(|a, b| a.partial_cmp(b).unwrap())?
-            < self.distance_threshold.get::<meter>();
-
-        match (measured, predicted) {
-            (true, true) => Some(0.9),
-            (true, false) => Some(0.1),
-            (false, true) => Some(0.1),
-            (false, false) => Some(0.9),
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use core::{cell::Cell, f64::consts::FRAC_PI_2};
+
+    use uom::si::length::inch;
+
+    use super::*;
+    use crate::{config::field_map, localization::field_map::Alliance};
+
+    struct FixedReflectance(Rc<Cell<f64>>);
+
+    impl Reflectance for FixedReflectance {
+        fn reflectivity(&self) -> Option<f64> {
+            Some(self.0.get())
         }
     }
+
+    fn sensor(reflectivity: &Rc<Cell<f64>>) -> LineTrackerSensor<FixedReflectance> {
+        LineTrackerSensor::new(
+            FixedReflectance(reflectivity.clone()),
+            Vector2::new(0.1, 0.0),
+            LineCalibration {
+                tile: 0.1,
+                tape: 0.5,
+            },
+            Length::new::<inch>(0.5),
+            Arc::new(field_map(Alliance::Red)),
+        )
+    }
+
+    #[test]
+    fn offset_rotates_with_robot() {
+        let reflectivity = Rc::new(Cell::new(0.5));
+        let mut sensor = sensor(&reflectivity);
+        sensor.tick();
+
+        // Facing -y the sensor is 0.1 below the center, so it's on the center tape only when
+        // the center is at x = 0
+        let on = sensor
+            .log_p(&StateRepresentation::new(0.0, 1.0, -FRAC_PI_2))
+            .unwrap();
+        let off = sensor
+            .log_p(&StateRepresentation::new(0.1, 1.0, -FRAC_PI_2))
+            .unwrap();
+
+        assert!(on > off);
+    }
+
+    #[test]
+    fn tape_has_width() {
+        let reflectivity = Rc::new(Cell::new(0.5));
+        let mut sensor = sensor(&reflectivity);
+        sensor.tick();
+
+        // Sensor 0.02 off the center line, inside the 2 inch tape
+        let inside = sensor
+            .log_p(&StateRepresentation::new(-0.12, 1.0, 0.0))
+            .unwrap();
+        // Sensor 0.03 off the center line, past the edge
+        let outside = sensor
+            .log_p(&StateRepresentation::new(-0.13, 1.0, 0.0))
+            .unwrap();
+
+        assert!(inside > outside);
+    }
+
+    #[test]
+    fn detects_edges() {
+        let reflectivity = Rc::new(Cell::new(0.05));
+        let mut sensor = sensor(&reflectivity);
+
+        assert!(!sensor.tick());
+        assert!(!sensor.tick());
+
+        reflectivity.set(0.6);
+        assert!(sensor.tick());
+        assert!(!sensor.tick());
+
+        reflectivity.set(0.05);
+        assert!(sensor.tick());
+    }
+
+    #[test]
+    fn edge_corrects_across_tape_only() {
+        let reflectivity = Rc::new(Cell::new(0.05));
+        let mut sensor = sensor(&reflectivity);
+        sensor.tick();
+        reflectivity.set(0.6);
+        sensor.tick();
+
+        let half_width = Length::new::<inch>(1.0).get::<meter>();
+        let on_edge = sensor
+            .log_p(&StateRepresentation::new(-0.1 - half_width, 0.5, 0.0))
+            .unwrap();
+        let along_tape = sensor
+            .log_p(&StateRepresentation::new(-0.1 - half_width, -0.5, 0.0))
+            .unwrap();
+        let across_tape = sensor
+            .log_p(&StateRepresentation::new(-0.2 - half_width, 0.5, 0.0))
+            .unwrap();
+
+        assert!((on_edge - along_tape).abs() < 1e-9);
+        assert!(on_edge - across_tape > 10.0);
+    }
 }
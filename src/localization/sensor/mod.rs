@@ -1,4 +1,4 @@
-use nalgebra::Vector2;
+use nalgebra::{DMatrix, DVector, Vector2};
 
 use crate::{localization::localization::StateRepresentation, utils};
 
@@ -6,8 +6,27 @@ pub mod distance;
 pub mod gps;
 pub mod line_tracker;
 
+/// Measurement model used by the particle filter.
 pub trait Sensor {
-    fn p(&self, x: &StateRepresentation) -> Option<f64>;
+    /// Natural log of the likelihood of the current reading if the robot were at `x`, `None` if
+    /// the sensor has no usable reading.
+    fn log_p(&self, x: &StateRepresentation) -> Option<f64>;
+
+    /// Called every localization tick, even when there's no measurement update, for sensors that
+    /// track their readings over time. Returns whether the sensor saw something that should be
+    /// applied now rather than at the next update.
+    fn tick(&mut self) -> bool {
+        false
+    }
+}
+
+/// Measurement model used by filters that linearize around the estimate, like the EKF.
+pub trait MeasurementModel {
+    /// Reads the sensor, returning the measurement and its covariance.
+    fn measure(&self) -> Option<(DVector<f64>, DMatrix<f64>)>;
+
+    /// Measurement expected if the robot were at `x`.
+    fn predict(&self, x: &StateRepresentation) -> Option<DVector<f64>>;
 }
 
 pub struct DummySensor {
@@ -16,8 +35,8 @@ pub struct DummySensor {
 }
 
 impl Sensor for DummySensor {
-    fn p(&self, x: &StateRepresentation) -> Option<f64> {
-        Option::from(utils::normal_pdf(
+    fn log_p(&self, x: &StateRepresentation) -> Option<f64> {
+        Option::from(utils::normal_log_pdf(
             (Vector2::new(x.x, x.y) - self.mean).magnitude(),
             0.0,
             self.covariance,
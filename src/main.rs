@@ -2,63 +2,94 @@
 #![no_std]
 #![feature(future_join)]
 #![feature(async_closure)]
-#![feature(duration_millis_float)]
-#![feature(let_chains)]
 extern crate alloc;
-extern crate uom;
 
-use alloc::{boxed::Box, ffi::CString, format, sync::Arc, vec};
-use core::{future::join, panic::PanicInfo, time::Duration};
+use alloc::{boxed::Box, rc::Rc, sync::Arc, vec};
+use core::{cell::RefCell, f64::consts::TAU, future::join, panic::PanicInfo, time::Duration};
 
-use futures::{select_biased, FutureExt};
-use motion_profiling::combined_mp::CombinedMP;
-use nalgebra::Matrix3;
-use subsystems::drivetrain::VoltageDrive;
-use uom::si::{angle::revolution, f64::Angle, length::meter};
-use vexide::{
-    core::sync::Mutex,
-    devices::{
-        controller::ControllerId,
-        screen::{Text, TextSize},
-        smart::GpsSensor,
-    },
-    prelude::*,
-};
-
-use crate::{
+use echo::{
     actuator::{motor_group::MotorGroup, telemetry::Telemetry},
+    autonomous::{
+        selector::{run_selector, AutonSelector},
+        AutonRegistry,
+    },
+    command::{scheduler::Scheduler, StateCommand, SubsystemId},
     config::{
-        get_distance_1_offset, get_distance_2_offset, get_distance_3_offset, get_gps_offset,
-        get_line_1_offset, track_width, wheel_diameter, DRIVE_RATIO,
+        distance_sensor_max_range, field_map, get_distance_1_offset, get_distance_2_offset,
+        get_distance_3_offset, get_gps_offset, get_line_1_offset, line_edge_std,
+        localization_min_update_distance, start_position_std, track_width, wheel_diameter,
+        DRIVE_RATIO, FIELD_MAX, LINE_TRACKER_CALIBRATION, LOCALIZATION_MIN_UPDATE_INTERVAL,
+        START_HEADING_STD, START_TILES, TRACKING_WHEELS,
+    },
+    localization::{
+        field_map::Alliance,
+        localization::{particle_filter::ParticleFilter, StateRepresentation},
+        mirror::Mirror,
+        predict::{
+            tank_pose_tracking::TankPoseTracking, tracking_wheel_odometry::TrackingWheelOdometry,
+        },
+        sensor::{
+            distance::WallDistanceSensor, gps::GpsPoseSensor, line_tracker::LineTrackerSensor,
+        },
+    },
+    motion_control::ramsete::{Ramsete, RamseteConfig},
+    sensor::imu::HeadingHealth,
+    state_machine::{
+        runner::{SubsystemHandle, SubsystemRunner},
+        State,
     },
-    localization::localization::StateRepresentation,
-    motion_control::ramsete::Ramsete,
     subsystems::{
-        drivetrain::{Drivetrain, TankDrive},
+        drivetrain::{
+            drive_odometry, tracking_wheel_odometry, DriveOdometry, Drivetrain, TankDrive,
+            VoltageDrive,
+        },
         goal_clamp::{GoalClamp, GoalController},
         hook::{Hook, HookPosition},
-        intake::{Intake, IntakeManual, LoadGoal},
+        intake::{Intake, IntakeManual},
+        SharedController,
     },
 };
+use motion_profiling::combined_mp::CombinedMP;
+use nalgebra::Matrix3;
+use uom::si::{angle::revolution, f64::Angle, length::meter};
+use vexide::{
+    core::sync::Mutex,
+    devices::{controller::ControllerId, smart::GpsSensor},
+    prelude::*,
+};
+
+/// Subsystems commands can require.
+const DRIVETRAIN: SubsystemId = SubsystemId(0);
+const INTAKE: SubsystemId = SubsystemId(1);
+const GOAL_CLAMP: SubsystemId = SubsystemId(2);
+
+/// Autonomous routines the drivers can pick on the brain screen.
+#[derive(Clone, Copy)]
+enum Routine {
+    TestPath,
+    Nothing,
+}
 
-mod actuator;
-mod config;
-mod localization;
-mod motion_control;
-mod sensor;
-mod state_machine;
-mod subsystems;
-mod utils;
+/// Localization backend of the drivetrain. `ExtendedKalmanFilter` is a cheaper drop-in.
+type DriveLocalization = ParticleFilter<
+    DriveOdometry<
+        TankPoseTracking<Arc<Mutex<MotorGroup>>, InertialSensor>,
+        TrackingWheelOdometry<RotationSensor, InertialSensor>,
+    >,
+>;
 
 struct Robot {
-    drivetrain: Drivetrain,
+    drivetrain: Drivetrain<MotorGroup, DriveLocalization>,
+    _localization_task: Task<()>,
     intake: Intake,
-    hook: Hook,
-    controller_primary: Controller,
-    controller_partner: Controller,
+    hook: SubsystemHandle<(), f64>,
+    _hook_task: Task<()>,
+    controller_primary: SharedController,
+    controller_partner: SharedController,
     goal_clamp: GoalClamp,
     _telemetry: Telemetry,
-    _telemetry_task: Task<()>,
+    auton: Rc<RefCell<AutonSelector<Routine>>>,
+    _selector_task: Task<()>,
 }
 
 impl Robot {
@@ -70,124 +101,245 @@ impl Robot {
 
         // TODO Fix drivetrain encoders with 5.5W behavior
 
-        let drivetrain = Drivetrain::new(
-            Arc::new(Mutex::new(MotorGroup::new(vec![
-                Motor::new(peripherals.port_4, Gearset::Green, Direction::Forward),
-                Motor::new(peripherals.port_2, Gearset::Blue, Direction::Forward),
-                Motor::new(peripherals.port_3, Gearset::Blue, Direction::Reverse),
-            ]))),
-            Arc::new(Mutex::new(MotorGroup::new(vec![
-                Motor::new(peripherals.port_9, Gearset::Green, Direction::Reverse),
-                Motor::new(peripherals.port_6, Gearset::Blue, Direction::Forward),
-                Motor::new(peripherals.port_7, Gearset::Blue, Direction::Forward),
-            ]))),
-            InertialSensor::new(peripherals.port_19),
-            wheel_diameter(),
-            DRIVE_RATIO,
-            _telemetry.clone(),
-            vec![
-                (
-                    DistanceSensor::new(peripherals.port_12),
-                    get_distance_1_offset(),
-                ),
-                (
-                    DistanceSensor::new(peripherals.port_13),
-                    get_distance_2_offset(),
-                ),
-                (
-                    DistanceSensor::new(peripherals.port_14),
-                    get_distance_3_offset(),
-                ),
-            ],
-            vec![(AdiLineTracker::new(peripherals.adi_b), get_line_1_offset())],
-            GpsSensor::new(peripherals.port_11, get_gps_offset(), ((0.0, 0.0), 0.0)),
-        )
-        .await;
+        let left_drive = Arc::new(Mutex::new(MotorGroup::new(vec![
+            Motor::new(peripherals.port_4, Gearset::Green, Direction::Forward),
+            Motor::new(peripherals.port_2, Gearset::Blue, Direction::Forward),
+            Motor::new(peripherals.port_3, Gearset::Blue, Direction::Reverse),
+        ])));
+        let right_drive = Arc::new(Mutex::new(MotorGroup::new(vec![
+            Motor::new(peripherals.port_9, Gearset::Green, Direction::Reverse),
+            Motor::new(peripherals.port_6, Gearset::Blue, Direction::Forward),
+            Motor::new(peripherals.port_7, Gearset::Blue, Direction::Forward),
+        ])));
+
+        let mut auton = AutonSelector::new(
+            AutonRegistry::new()
+                .with_routine("Test path", Routine::TestPath)
+                .with_routine("Do nothing", Routine::Nothing),
+            &START_TILES,
+        );
+        if !auton.load() {
+            println!("No saved autonomous selection, starting from the first routine");
+        }
+        let auton = Rc::new(RefCell::new(auton));
+
+        // The field is always red's, only the routines get mirrored for blue
+        let field = Arc::new(field_map(Alliance::Red));
+
+        let odometry = if TRACKING_WHEELS {
+            DriveOdometry::TrackingWheels(
+                tracking_wheel_odometry(
+                    RotationSensor::new(peripherals.port_1, Direction::Forward),
+                    Some(RotationSensor::new(peripherals.port_15, Direction::Reverse)),
+                    RotationSensor::new(peripherals.port_16, Direction::Forward),
+                    vec![InertialSensor::new(peripherals.port_19)],
+                )
+                .await,
+            )
+        } else {
+            DriveOdometry::DriveWheels(
+                drive_odometry(
+                    &left_drive,
+                    &right_drive,
+                    vec![InertialSensor::new(peripherals.port_19)],
+                    wheel_diameter(),
+                    DRIVE_RATIO,
+                )
+                .await,
+            )
+        };
+
+        let mut localization = ParticleFilter::new(
+            odometry,
+            LOCALIZATION_MIN_UPDATE_INTERVAL,
+            localization_min_update_distance(),
+        );
+
+        for (sensor, offset) in [
+            (
+                DistanceSensor::new(peripherals.port_12),
+                get_distance_1_offset(),
+            ),
+            (
+                DistanceSensor::new(peripherals.port_13),
+                get_distance_2_offset(),
+            ),
+            (
+                DistanceSensor::new(peripherals.port_14),
+                get_distance_3_offset(),
+            ),
+        ] {
+            localization.add_sensor(WallDistanceSensor::new(
+                sensor,
+                offset,
+                distance_sensor_max_range(),
+                field.clone(),
+            ));
+        }
+
+        let mut line_tracker = LineTrackerSensor::new(
+            AdiLineTracker::new(peripherals.adi_b),
+            get_line_1_offset(),
+            LINE_TRACKER_CALIBRATION,
+            line_edge_std(),
+            field.clone(),
+        );
+        // The robot starts off the tape
+        if line_tracker.calibrate_tile().is_none() {
+            println!("WARNING: Can't calibrate line tracker");
+        }
+        localization.add_sensor(line_tracker);
+
+        if let Ok(gps) = GpsSensor::new(peripherals.port_11, get_gps_offset(), ((0.0, 0.0), 0.0)) {
+            localization.add_sensor(GpsPoseSensor::new(gps));
+        }
+
+        localization.init_uniform(
+            &StateRepresentation::new(-FIELD_MAX, -FIELD_MAX, 0.0),
+            &StateRepresentation::new(FIELD_MAX, FIELD_MAX, TAU),
+        );
+
+        let drivetrain = Drivetrain::new(left_drive, right_drive, localization);
+
+        let hook = SubsystemRunner::new(Hook::new(Motor::new(
+            peripherals.port_8,
+            Gearset::Green,
+            Direction::Reverse,
+        )));
 
         Self {
+            _localization_task: spawn(drivetrain.run_localization(_telemetry.clone())),
             drivetrain,
             intake: Intake::new(
                 Motor::new(peripherals.port_10, Gearset::Green, Direction::Reverse),
                 Motor::new(peripherals.port_5, Gearset::Blue, Direction::Reverse),
                 Motor::new(peripherals.port_17, Gearset::Red, Direction::Forward),
             ),
-            hook: Hook::new(Motor::new(
-                peripherals.port_8,
-                Gearset::Green,
-                Direction::Reverse,
-            )),
-            controller_primary: peripherals.primary_controller,
-            controller_partner: peripherals.partner_controller,
+            hook: hook.handle(),
+            _hook_task: spawn(hook.run()),
+            controller_primary: Rc::new(RefCell::new(peripherals.primary_controller)),
+            controller_partner: Rc::new(RefCell::new(peripherals.partner_controller)),
             goal_clamp: GoalClamp::new(AdiDigitalOut::new(peripherals.adi_a)),
             _telemetry: _telemetry.clone(),
-            _telemetry_task: spawn(async move {
-                let mut text = Text::new_aligned(
-                    "test-text",
-                    TextSize::Small,
-                    (0, 0),
-                    Default::default(),
-                    Default::default(),
-                );
-                loop {
-                    text.text = CString::new(format!("Current time: {}", 0.0)).unwrap();
-                    peripherals.screen.fill(&text, Rgb::WHITE);
-
-                    sleep(Duration::from_millis(50)).await;
-                }
-            }),
+            _selector_task: spawn(run_selector(auton.clone(), peripherals.screen)),
+            auton,
         }
     }
-}
-
-impl Compete for Robot {
-    async fn autonomous(&mut self) {
-        println!("auto");
-
-        self.drivetrain
-            .init_norm(
-                &StateRepresentation::new(0.0, 0.0, 0.0),
-                &Matrix3::new(0.1, 0.0, 0.0, 0.0, 0.1, 0.0, 0.0, 0.0, 0.0),
-            )
-            .await;
 
-        self.hook
-            .run(HookPosition(Angle::new::<revolution>(0.0)))
-            .await;
+    /// Written for red, `mirror` takes it to the selected alliance's side.
+    async fn test_path(&mut self, mirror: Mirror) {
+        // Holds the hook down for the rest of the routine
+        self.hook.run(HookPosition(Angle::new::<revolution>(0.0)));
 
-        let ramsete = Ramsete::try_new(
-            0.1,
-            0.5,
+        let ramsete = Ramsete::new(
+            RamseteConfig::try_new(0.1, 0.5).unwrap(),
             Box::new(
                 CombinedMP::try_new_2d(
-                    serde_json::from_str(include_str!("../bins/paths/test.json")).unwrap(),
+                    mirror
+                        .path(include_str!("../bins/paths/test.json"))
+                        .unwrap(),
                     track_width().get::<meter>(),
                 )
                 .unwrap(),
             ),
-        )
-        .unwrap();
+        );
 
         self.drivetrain.run_velocity(ramsete).await;
 
+        self.drivetrain
+            .run(VoltageDrive::new(-12.0, -12.0).timeout(Duration::from_secs(2)))
+            .await;
+
+        self.drivetrain.run(VoltageDrive::new(0.0, 0.0)).await;
+    }
+}
+
+impl Compete for Robot {
+    async fn autonomous(&mut self) {
+        println!("auto");
+
+        let (routine, alliance, start_pose) = {
+            let auton = self.auton.borrow();
+            (
+                *auton.routine(),
+                auton.selection().alliance,
+                auton.start_pose(),
+            )
+        };
+
+        let position_std = start_position_std().get::<meter>();
+        self.drivetrain
+            .init_norm(
+                &start_pose,
+                &Matrix3::from_diagonal(&StateRepresentation::new(
+                    position_std,
+                    position_std,
+                    START_HEADING_STD,
+                )),
+            )
+            .await;
+
+        if !self
+            .drivetrain
+            .wait_for_convergence(Duration::from_millis(500))
+            .await
         {
-            let drive_state = self.drivetrain.run(VoltageDrive::new(-12.0, -12.0));
+            println!("WARNING: Starting auto before localization converged");
+        }
 
-            let _ = select_biased! {
-                () = drive_state.fuse() => 1,
-                () = sleep(Duration::from_secs(2)).fuse() => 2,
-            };
+        let heading_health = self.drivetrain.heading_health().await;
+        if heading_health != HeadingHealth::Healthy {
+            println!("WARNING: Heading is {:?}", heading_health);
         }
 
-        self.drivetrain.run(VoltageDrive::new(0.0, 0.0)).await;
+        match routine {
+            Routine::TestPath => self.test_path(Mirror::alliance(alliance)).await,
+            Routine::Nothing => {}
+        }
     }
 
     async fn driver(&mut self) {
         println!("Drive");
-        // let drive_state = self.drivetrain.run(TankDrive::new(&self.controller));
 
-        join!(self.goal_clamp.run(GoalController {
-            controller: &mut self.controller_primary
-        }))
+        let drivetrain = SubsystemRunner::new(&mut self.drivetrain);
+        let intake = SubsystemRunner::new(&mut self.intake);
+        let goal_clamp = SubsystemRunner::new(&mut self.goal_clamp);
+
+        let mut scheduler = Scheduler::new();
+
+        let controller = self.controller_primary.clone();
+        scheduler.set_default_command(
+            DRIVETRAIN,
+            StateCommand::new(DRIVETRAIN, drivetrain.handle(), move || {
+                TankDrive::new(controller.clone())
+            }),
+        );
+
+        // The sticks of the primary controller are taken by the drivetrain
+        let controller = self.controller_partner.clone();
+        scheduler.set_default_command(
+            INTAKE,
+            StateCommand::new(INTAKE, intake.handle(), move || IntakeManual {
+                controller: controller.clone(),
+                lift_pos: 0.0,
+                top_pos: 0.0,
+            }),
+        );
+
+        let controller = self.controller_primary.clone();
+        scheduler.set_default_command(
+            GOAL_CLAMP,
+            StateCommand::new(GOAL_CLAMP, goal_clamp.handle(), move || GoalController {
+                controller: controller.clone(),
+            }),
+        );
+
+        join!(
+            drivetrain.run(),
+            intake.run(),
+            goal_clamp.run(),
+            scheduler.run()
+        )
         .await;
     }
 }
@@ -0,0 +1,151 @@
+use nalgebra::{Rotation2, Vector2};
+use uom::{
+    num_traits::Float,
+    si::{
+        f64::{AngularVelocity, Length, Velocity},
+        length::meter,
+        velocity::meter_per_second,
+    },
+};
+
+use crate::{
+    config::{
+        boomerang_close_distance, motion_settle_tolerance, BOOMERANG_ANGULAR_GAIN,
+        BOOMERANG_LINEAR_GAIN, MOTION_SETTLE_HEADING_TOLERANCE, MOTION_SETTLE_TIME, MOTION_TIMEOUT,
+    },
+    localization::localization::StateRepresentation,
+    motion_control::{exit::ExitConditions, wheel_velocities},
+    state_machine::State,
+    utils::angle_difference,
+};
+
+/// Drives to a pose without a path by chasing a carrot point set back from the target along its
+/// heading, so the robot curves in and arrives facing the right way.
+///
+/// `lead` is how far back the carrot is as a fraction of the distance to the target, 0 drives
+/// straight at the target.
+pub struct Boomerang {
+    target: StateRepresentation,
+    lead: f64,
+    max_speed: f64,
+    tolerance: f64,
+    heading_tolerance: f64,
+    exit: ExitConditions,
+}
+
+impl Boomerang {
+    pub fn new(target: StateRepresentation, lead: f64, max_speed: Velocity) -> Self {
+        assert!((0.0..1.0).contains(&lead), "Lead must be in [0, 1)");
+
+        Self {
+            target,
+            lead,
+            max_speed: max_speed.get::<meter_per_second>(),
+            tolerance: motion_settle_tolerance().get::<meter>(),
+            heading_tolerance: MOTION_SETTLE_HEADING_TOLERANCE,
+            exit: ExitConditions::new(MOTION_SETTLE_TIME, MOTION_TIMEOUT),
+        }
+    }
+
+    /// How close the robot has to be to the target position and heading to be done.
+    pub fn with_tolerance(mut self, tolerance: Length, heading_tolerance: f64) -> Self {
+        self.tolerance = tolerance.get::<meter>();
+        self.heading_tolerance = heading_tolerance;
+        self
+    }
+
+    pub fn with_exit_conditions(mut self, exit: ExitConditions) -> Self {
+        self.exit = exit;
+        self
+    }
+}
+
+impl State<StateRepresentation, (AngularVelocity, AngularVelocity)> for Boomerang {
+    fn init(&mut self) {
+        self.exit.reset();
+    }
+
+    fn update(&mut self, i: &StateRepresentation) -> Option<(AngularVelocity, AngularVelocity)> {
+        let to_target = self.target.xy() - i.xy();
+        let distance = to_target.magnitude();
+        let final_heading_error = angle_difference(self.target.z, i.z);
+
+        if self
+            .exit
+            .done(distance < self.tolerance && final_heading_error.abs() < self.heading_tolerance)
+        {
+            return None;
+        }
+
+        let (linear_error, heading_error) = if distance > boomerang_close_distance().get::<meter>()
+        {
+            let carrot = self.target.xy()
+                - Rotation2::new(self.target.z) * Vector2::new(self.lead * distance, 0.0);
+            let to_carrot = carrot - i.xy();
+            let heading_error = angle_difference(to_carrot.y.atan2(to_carrot.x), i.z);
+
+            // Only drive as fast as the robot is pointed at the carrot
+            (to_carrot.magnitude() * heading_error.cos(), heading_error)
+        } else {
+            // Close in, the heading to the target swings wildly, so turn to the final heading and
+            // only close the distance along it
+            let along = to_target.dot(&Vector2::new(i.z.cos(), i.z.sin()));
+
+            (along, final_heading_error)
+        };
+
+        let linear = (BOOMERANG_LINEAR_GAIN * linear_error).clamp(-self.max_speed, self.max_speed);
+
+        Some(wheel_velocities(
+            linear,
+            BOOMERANG_ANGULAR_GAIN * heading_error,
+            self.max_speed,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{f64::consts::FRAC_PI_2, time::Duration};
+
+    use super::*;
+    use crate::sim::{drive_plant, run_velocity, SharedPlant};
+
+    fn drive_to(plant: &SharedPlant, target: StateRepresentation) -> Duration {
+        let mut boomerang = Boomerang::new(target, 0.5, Velocity::new::<meter_per_second>(1.0));
+
+        run_velocity(
+            plant,
+            &mut boomerang,
+            Duration::from_millis(10),
+            Duration::from_secs(10),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn arrives_at_pose() {
+        let plant = drive_plant();
+        let target = StateRepresentation::new(1.0, 0.6, FRAC_PI_2);
+
+        let elapsed = drive_to(&plant, target);
+
+        let pose = plant.borrow().pose();
+        assert!(elapsed < MOTION_TIMEOUT, "{elapsed:?}");
+        assert!((pose.xy() - target.xy()).magnitude() < 0.05, "{pose}");
+        assert!(angle_difference(pose.z, target.z).abs() < 0.05, "{pose}");
+    }
+
+    #[test]
+    fn backs_into_pose() {
+        let plant = drive_plant();
+        // Behind the robot, facing the same way
+        let target = StateRepresentation::new(-0.8, 0.0, 0.0);
+
+        drive_to(&plant, target);
+
+        let pose = plant.borrow().pose();
+        assert!((pose.xy() - target.xy()).magnitude() < 0.05, "{pose}");
+        assert!(angle_difference(pose.z, target.z).abs() < 0.05, "{pose}");
+    }
+}
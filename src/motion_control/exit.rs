@@ -0,0 +1,87 @@
+use core::time::Duration;
+
+use crate::hal::time::Instant;
+
+/// When a closed loop move is done: once it has been within tolerance for the settle time, or
+/// once it runs out of time.
+#[derive(Clone, Copy, Debug)]
+pub struct ExitConditions {
+    settle_time: Duration,
+    timeout: Duration,
+    start: Instant,
+    settled_since: Option<Instant>,
+}
+
+impl ExitConditions {
+    pub fn new(settle_time: Duration, timeout: Duration) -> Self {
+        Self {
+            settle_time,
+            timeout,
+            start: Instant::now(),
+            settled_since: None,
+        }
+    }
+
+    /// Restarts the timers, call when the move starts.
+    pub fn reset(&mut self) {
+        self.start = Instant::now();
+        self.settled_since = None;
+    }
+
+    /// Whether the move should stop, given whether it's within tolerance right now.
+    pub fn done(&mut self, within_tolerance: bool) -> bool {
+        let now = Instant::now();
+
+        if within_tolerance {
+            let settled_since = *self.settled_since.get_or_insert(now);
+
+            if now - settled_since >= self.settle_time {
+                return true;
+            }
+        } else {
+            self.settled_since = None;
+        }
+
+        self.timed_out()
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.start.elapsed() >= self.timeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::clock;
+
+    #[test]
+    fn settles_after_settle_time() {
+        let mut exit = ExitConditions::new(Duration::from_millis(100), Duration::from_secs(5));
+
+        assert!(!exit.done(true));
+        clock::advance(Duration::from_millis(60));
+        assert!(!exit.done(true));
+
+        // Leaving the tolerance restarts the settle timer
+        assert!(!exit.done(false));
+        assert!(!exit.done(true));
+        clock::advance(Duration::from_millis(60));
+        assert!(!exit.done(true));
+        clock::advance(Duration::from_millis(60));
+        assert!(exit.done(true));
+    }
+
+    #[test]
+    fn times_out() {
+        let mut exit = ExitConditions::new(Duration::from_millis(100), Duration::from_secs(1));
+        clock::advance(Duration::from_millis(500));
+
+        // The timeout counts from the reset
+        exit.reset();
+        clock::advance(Duration::from_millis(600));
+        assert!(!exit.done(false));
+        clock::advance(Duration::from_millis(400));
+        assert!(exit.done(false));
+    }
+}
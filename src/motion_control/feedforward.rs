@@ -0,0 +1,222 @@
+use uom::si::{
+    angular_acceleration::radian_per_second_squared,
+    angular_velocity::radian_per_second,
+    f64::{AngularAcceleration, AngularVelocity},
+};
+
+use crate::hal::time::Instant;
+
+/// Gains of a [`VelocityController`], in volts per motor shaft rad/s.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeedforwardGains {
+    /// Voltage to overcome static friction.
+    pub ks: f64,
+    /// Voltage per rad/s.
+    pub kv: f64,
+    /// Voltage per rad/s².
+    pub ka: f64,
+    /// Voltage per rad/s the motor is slower than commanded.
+    pub kp: f64,
+}
+
+/// Motor velocity for a [`VelocityController`] to reach.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VelocityTarget {
+    pub velocity: AngularVelocity,
+    /// Acceleration planned by the state, e.g. by the profile it follows. Without it the
+    /// acceleration is taken from the change in `velocity`, which includes the state's feedback.
+    pub acceleration: Option<AngularAcceleration>,
+}
+
+impl From<AngularVelocity> for VelocityTarget {
+    fn from(velocity: AngularVelocity) -> Self {
+        Self {
+            velocity,
+            acceleration: None,
+        }
+    }
+}
+
+/// Turns a commanded motor velocity into a voltage with a kS/kV/kA feedforward and a proportional
+/// loop on the measured velocity.
+///
+/// The acceleration is the one planned with the target, or otherwise the change in the commanded
+/// velocity between updates. The feedback makes up for what the feedforward gets wrong, like the
+/// battery sagging over a match.
+pub struct VelocityController {
+    gains: FeedforwardGains,
+    last_target: Option<f64>,
+    last_time: Instant,
+}
+
+impl VelocityController {
+    pub const MAX_VOLTAGE: f64 = 12.0;
+
+    pub fn new(gains: FeedforwardGains) -> Self {
+        Self {
+            gains,
+            last_target: None,
+            last_time: Instant::now(),
+        }
+    }
+
+    pub fn gains(&self) -> FeedforwardGains {
+        self.gains
+    }
+
+    /// Forgets the last commanded velocity, call before tracking a new profile.
+    pub fn reset(&mut self) {
+        self.last_target = None;
+        self.last_time = Instant::now();
+    }
+
+    /// Voltage for the motor to reach `target`, without feedback if the velocity can't be read.
+    pub fn update(
+        &mut self,
+        target: impl Into<VelocityTarget>,
+        measured: Option<AngularVelocity>,
+    ) -> f64 {
+        let VelocityTarget {
+            velocity: target,
+            acceleration,
+        } = target.into();
+        let target = target.get::<radian_per_second>();
+
+        let now = Instant::now();
+        let dt = (now - self.last_time).as_secs_f64();
+        self.last_time = now;
+
+        let acceleration = match (acceleration, self.last_target) {
+            (Some(acceleration), _) => acceleration.get::<radian_per_second_squared>(),
+            (None, Some(last_target)) if dt > 0.0 => (target - last_target) / dt,
+            _ => 0.0,
+        };
+        self.last_target = Some(target);
+
+        let feedforward = if target == 0.0 {
+            0.0
+        } else {
+            self.gains.ks * target.signum()
+        } + self.gains.kv * target
+            + self.gains.ka * acceleration;
+
+        let feedback = measured.map_or(0.0, |measured| {
+            self.gains.kp * (target - measured.get::<radian_per_second>())
+        });
+
+        (feedforward + feedback).clamp(-Self::MAX_VOLTAGE, Self::MAX_VOLTAGE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use core::{cell::RefCell, time::Duration};
+
+    use uom::si::{f64::Velocity, length::meter, velocity::meter_per_second};
+
+    use super::*;
+    use crate::{
+        config::{wheel_diameter, DRIVE_RATIO},
+        hal::DriveMotor,
+        sim::{clock, devices::SimMotor, drive_plant, plant::Side},
+    };
+
+    const GAINS: FeedforwardGains = FeedforwardGains {
+        ks: 0.5,
+        kv: 0.2,
+        ka: 0.05,
+        kp: 0.1,
+    };
+
+    fn rad_per_second(value: f64) -> AngularVelocity {
+        AngularVelocity::new::<radian_per_second>(value)
+    }
+
+    #[test]
+    fn feeds_forward_velocity_and_acceleration() {
+        let mut controller = VelocityController::new(GAINS);
+
+        // No previous command, so no acceleration
+        assert!((controller.update(rad_per_second(10.0), None) - 2.5).abs() < 1e-9);
+
+        // 10 rad/s to 20 rad/s in 0.1s is 100 rad/s²
+        clock::advance(Duration::from_millis(100));
+        assert!((controller.update(rad_per_second(20.0), None) - 9.5).abs() < 1e-9);
+
+        // Static friction opposes the direction of travel, and stopping needs nothing
+        controller.reset();
+        assert!((controller.update(rad_per_second(-10.0), None) + 2.5).abs() < 1e-9);
+        controller.reset();
+        assert_eq!(controller.update(rad_per_second(0.0), None), 0.0);
+    }
+
+    #[test]
+    fn prefers_planned_acceleration() {
+        let mut controller = VelocityController::new(GAINS);
+        let target = |velocity, acceleration| VelocityTarget {
+            velocity: rad_per_second(velocity),
+            acceleration: Some(AngularAcceleration::new::<radian_per_second_squared>(
+                acceleration,
+            )),
+        };
+
+        assert!((controller.update(target(10.0, 20.0), None) - 3.5).abs() < 1e-9);
+
+        // The jump in velocity is feedback, not acceleration the profile asked for
+        clock::advance(Duration::from_millis(100));
+        assert!((controller.update(target(20.0, 20.0), None) - 5.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn feeds_back_velocity_error() {
+        let mut controller = VelocityController::new(GAINS);
+
+        let voltage = controller.update(rad_per_second(10.0), Some(rad_per_second(6.0)));
+        assert!((voltage - 2.9).abs() < 1e-9);
+        assert_eq!(
+            controller.update(rad_per_second(100.0), Some(rad_per_second(0.0))),
+            VelocityController::MAX_VOLTAGE
+        );
+    }
+
+    /// Steady state velocity error tracking `target` on the simulated drive, which reaches
+    /// 1.5 m/s at 12V.
+    fn tracking_error(gains: FeedforwardGains, target: f64) -> f64 {
+        let plant = drive_plant();
+        let mut motor = SimMotor::new(plant.clone(), Side::Left);
+        let mut controller = VelocityController::new(gains);
+
+        for _ in 0..200 {
+            let voltage = controller.update(rad_per_second(target), motor.velocity());
+            motor.set_voltage(voltage);
+            plant.borrow_mut().step(Duration::from_millis(10));
+            clock::advance(Duration::from_millis(10));
+        }
+
+        (motor.velocity().unwrap().get::<radian_per_second>() - target).abs()
+    }
+
+    #[test]
+    fn feedback_corrects_wrong_feedforward() {
+        // The plant turns 12V into 1.5 m/s at the wheels
+        let kv = 12.0 / (1.5 / (wheel_diameter().get::<meter>() / 2.0) / DRIVE_RATIO);
+        let tuned = FeedforwardGains {
+            ks: 0.0,
+            kv,
+            ka: 0.0,
+            kp: 0.0,
+        };
+        assert!(tracking_error(tuned, 5.0) < 1e-3);
+
+        // Like a sagging battery, the feedforward alone falls short
+        let sagging = FeedforwardGains {
+            kv: kv * 0.8,
+            ..tuned
+        };
+        let open_loop = tracking_error(sagging, 5.0);
+        let closed_loop = tracking_error(FeedforwardGains { kp: 2.0, ..sagging }, 5.0);
+        assert!(open_loop > 0.9, "{open_loop}");
+        assert!(closed_loop < open_loop / 2.0, "{closed_loop} {open_loop}");
+    }
+}
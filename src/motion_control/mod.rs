@@ -1 +1,38 @@
+use uom::si::{angular_velocity::radian_per_second, f64::AngularVelocity, length::meter};
+
+use crate::config::{track_width, wheel_diameter, DRIVE_RATIO};
+
+pub mod boomerang;
+pub mod exit;
+pub mod feedforward;
+pub mod moves;
+pub mod path;
+pub mod pid;
+pub mod pure_pursuit;
 pub mod ramsete;
+
+/// Motor speeds of a tank drive moving forward at `linear` m/s while turning counter-clockwise
+/// at `angular` rad/s.
+///
+/// Both are scaled down together so neither side goes faster than `max_speed` m/s, which keeps
+/// the curvature instead of clipping one side.
+pub fn wheel_velocities(
+    linear: f64,
+    angular: f64,
+    max_speed: f64,
+) -> (AngularVelocity, AngularVelocity) {
+    let turn = angular * track_width().get::<meter>() / 2.0;
+    let fastest = linear.abs() + turn.abs();
+    let scale = if fastest > max_speed {
+        max_speed / fastest
+    } else {
+        1.0
+    };
+    // Meters per radian of the motor shaft
+    let motor_radius = wheel_diameter().get::<meter>() / 2.0 * DRIVE_RATIO;
+
+    (
+        AngularVelocity::new::<radian_per_second>((linear - turn) * scale / motor_radius),
+        AngularVelocity::new::<radian_per_second>((linear + turn) * scale / motor_radius),
+    )
+}
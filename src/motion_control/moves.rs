@@ -0,0 +1,329 @@
+use core::f64::consts::PI;
+
+use nalgebra::Vector2;
+use uom::{
+    num_traits::Float,
+    si::{f64::Length, length::meter},
+};
+
+use crate::{
+    config::{
+        motion_settle_tolerance, DRIVE_PID, MOTION_SETTLE_HEADING_TOLERANCE, MOTION_SETTLE_TIME,
+        MOTION_TIMEOUT, PID_DERIVATIVE_FILTER, PID_INTEGRAL_LIMIT, TURN_PID,
+    },
+    localization::localization::StateRepresentation,
+    motion_control::{
+        exit::ExitConditions,
+        pid::{Pid, PidGains},
+    },
+    state_machine::State,
+    utils::angle_difference,
+};
+
+const MAX_VOLTAGE: f64 = 12.0;
+
+fn voltage_pid(gains: PidGains) -> Pid {
+    Pid::new(gains)
+        .with_integral_limit(PID_INTEGRAL_LIMIT)
+        .with_derivative_filter(PID_DERIVATIVE_FILTER)
+        .with_output_limit(MAX_VOLTAGE)
+}
+
+/// Turns in place to face `heading`, counter-clockwise radians, the short way around.
+pub struct TurnToHeading {
+    heading: f64,
+    pid: Pid,
+    tolerance: f64,
+    exit: ExitConditions,
+}
+
+impl TurnToHeading {
+    pub fn new(heading: f64) -> Self {
+        Self {
+            heading,
+            pid: voltage_pid(TURN_PID),
+            tolerance: MOTION_SETTLE_HEADING_TOLERANCE,
+            exit: ExitConditions::new(MOTION_SETTLE_TIME, MOTION_TIMEOUT),
+        }
+    }
+
+    pub fn with_pid(mut self, pid: Pid) -> Self {
+        self.pid = pid;
+        self
+    }
+
+    /// How close to the heading the robot has to be to be done, in radians.
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_exit_conditions(mut self, exit: ExitConditions) -> Self {
+        self.exit = exit;
+        self
+    }
+}
+
+impl State<StateRepresentation, (f64, f64)> for TurnToHeading {
+    fn init(&mut self) {
+        self.pid.reset();
+        self.exit.reset();
+    }
+
+    fn update(&mut self, i: &StateRepresentation) -> Option<(f64, f64)> {
+        let error = angle_difference(self.heading, i.z);
+
+        if self.exit.done(error.abs() <= self.tolerance) {
+            return None;
+        }
+
+        let turn = self.pid.update(error);
+
+        Some((-turn, turn))
+    }
+}
+
+/// Drives `distance` straight along the heading the robot starts at, holding that heading.
+/// Negative distances drive backwards.
+pub struct DriveDistance {
+    distance: f64,
+    origin: Option<StateRepresentation>,
+    pid: Pid,
+    heading_pid: Pid,
+    tolerance: f64,
+    exit: ExitConditions,
+}
+
+impl DriveDistance {
+    pub fn new(distance: Length) -> Self {
+        Self {
+            distance: distance.get::<meter>(),
+            origin: None,
+            pid: voltage_pid(DRIVE_PID),
+            heading_pid: voltage_pid(TURN_PID),
+            tolerance: motion_settle_tolerance().get::<meter>(),
+            exit: ExitConditions::new(MOTION_SETTLE_TIME, MOTION_TIMEOUT),
+        }
+    }
+
+    pub fn with_pid(mut self, pid: Pid, heading_pid: Pid) -> Self {
+        self.pid = pid;
+        self.heading_pid = heading_pid;
+        self
+    }
+
+    /// How close to the distance the robot has to be to be done.
+    pub fn with_tolerance(mut self, tolerance: Length) -> Self {
+        self.tolerance = tolerance.get::<meter>();
+        self
+    }
+
+    pub fn with_exit_conditions(mut self, exit: ExitConditions) -> Self {
+        self.exit = exit;
+        self
+    }
+}
+
+impl State<StateRepresentation, (f64, f64)> for DriveDistance {
+    fn init(&mut self) {
+        self.origin = None;
+        self.pid.reset();
+        self.heading_pid.reset();
+        self.exit.reset();
+    }
+
+    fn update(&mut self, i: &StateRepresentation) -> Option<(f64, f64)> {
+        // The pose is only known once the first update comes in
+        let origin = *self.origin.get_or_insert(*i);
+        let travelled = (i.xy() - origin.xy()).dot(&Vector2::new(origin.z.cos(), origin.z.sin()));
+        let error = self.distance - travelled;
+
+        if self.exit.done(error.abs() <= self.tolerance) {
+            return None;
+        }
+
+        let drive = self.pid.update(error);
+        let turn = self.heading_pid.update(angle_difference(origin.z, i.z));
+
+        Some((drive - turn, drive + turn))
+    }
+}
+
+/// Turns in place to face `point`, or to face away from it when reversed so the robot can back
+/// into it.
+pub struct TurnToPoint {
+    point: Vector2<f64>,
+    reversed: bool,
+    pid: Pid,
+    tolerance: f64,
+    exit: ExitConditions,
+}
+
+impl TurnToPoint {
+    pub fn new(point: Vector2<f64>) -> Self {
+        Self {
+            point,
+            reversed: false,
+            pid: voltage_pid(TURN_PID),
+            tolerance: MOTION_SETTLE_HEADING_TOLERANCE,
+            exit: ExitConditions::new(MOTION_SETTLE_TIME, MOTION_TIMEOUT),
+        }
+    }
+
+    pub fn reversed(mut self) -> Self {
+        self.reversed = true;
+        self
+    }
+
+    pub fn with_pid(mut self, pid: Pid) -> Self {
+        self.pid = pid;
+        self
+    }
+
+    /// How close to facing the point the robot has to be to be done, in radians.
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_exit_conditions(mut self, exit: ExitConditions) -> Self {
+        self.exit = exit;
+        self
+    }
+}
+
+impl State<StateRepresentation, (f64, f64)> for TurnToPoint {
+    fn init(&mut self) {
+        self.pid.reset();
+        self.exit.reset();
+    }
+
+    fn update(&mut self, i: &StateRepresentation) -> Option<(f64, f64)> {
+        let to_point = self.point - i.xy();
+        let mut heading = to_point.y.atan2(to_point.x);
+        if self.reversed {
+            heading += PI;
+        }
+        let error = angle_difference(heading, i.z);
+
+        if self.exit.done(error.abs() <= self.tolerance) {
+            return None;
+        }
+
+        let turn = self.pid.update(error);
+
+        Some((-turn, turn))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{f64::consts::FRAC_PI_2, time::Duration};
+
+    use super::*;
+    use crate::sim::{drive_plant, run_voltage, SharedPlant};
+
+    fn run(plant: &SharedPlant, state: &mut impl State<StateRepresentation, (f64, f64)>) {
+        run_voltage(
+            plant,
+            state,
+            Duration::from_millis(10),
+            Duration::from_secs(10),
+        )
+        .expect("Move didn't exit");
+    }
+
+    #[test]
+    fn turns_to_heading() {
+        let plant = drive_plant();
+        let mut turn = TurnToHeading::new(FRAC_PI_2);
+        run(&plant, &mut turn);
+
+        let pose = plant.borrow().pose();
+        assert!(
+            angle_difference(FRAC_PI_2, pose.z).abs() < 2.0 * MOTION_SETTLE_HEADING_TOLERANCE,
+            "{pose}"
+        );
+        assert!(pose.xy().magnitude() < 1e-6, "{pose}");
+    }
+
+    #[test]
+    fn turns_short_way_around() {
+        let plant = drive_plant();
+        plant
+            .borrow_mut()
+            .set_pose(StateRepresentation::new(0.0, 0.0, 0.1));
+
+        // 0.1 to -0.1 is a small clockwise turn, not most of a revolution
+        let mut turn = TurnToHeading::new(-0.1).with_exit_conditions(ExitConditions::new(
+            MOTION_SETTLE_TIME,
+            Duration::from_millis(500),
+        ));
+        run(&plant, &mut turn);
+
+        let heading = plant.borrow().pose().z;
+        assert!(angle_difference(-0.1, heading).abs() < 0.02, "{heading}");
+    }
+
+    #[test]
+    fn drives_distance() {
+        let plant = drive_plant();
+        plant
+            .borrow_mut()
+            .set_pose(StateRepresentation::new(0.0, 0.0, FRAC_PI_2));
+
+        let mut drive = DriveDistance::new(Length::new::<meter>(1.0));
+        run(&plant, &mut drive);
+
+        let pose = plant.borrow().pose();
+        assert!(
+            (pose.y - 1.0).abs() < 2.0 * motion_settle_tolerance().get::<meter>(),
+            "{pose}"
+        );
+        assert!(pose.x.abs() < 0.01, "{pose}");
+
+        let mut back = DriveDistance::new(Length::new::<meter>(-0.5));
+        run(&plant, &mut back);
+
+        let pose = plant.borrow().pose();
+        assert!(
+            (pose.y - 0.5).abs() < 2.0 * motion_settle_tolerance().get::<meter>(),
+            "{pose}"
+        );
+    }
+
+    #[test]
+    fn turns_to_point() {
+        let plant = drive_plant();
+        let mut turn = TurnToPoint::new(Vector2::new(-1.0, 1.0));
+        run(&plant, &mut turn);
+        assert!(
+            angle_difference(3.0 * PI / 4.0, plant.borrow().pose().z).abs()
+                < 2.0 * MOTION_SETTLE_HEADING_TOLERANCE
+        );
+
+        let mut turn = TurnToPoint::new(Vector2::new(-1.0, 1.0)).reversed();
+        run(&plant, &mut turn);
+        assert!(
+            angle_difference(-PI / 4.0, plant.borrow().pose().z).abs()
+                < 2.0 * MOTION_SETTLE_HEADING_TOLERANCE
+        );
+    }
+
+    #[test]
+    fn times_out() {
+        let plant = drive_plant();
+        let mut drive = DriveDistance::new(Length::new::<meter>(100.0)).with_exit_conditions(
+            ExitConditions::new(MOTION_SETTLE_TIME, Duration::from_millis(500)),
+        );
+
+        let elapsed = run_voltage(
+            &plant,
+            &mut drive,
+            Duration::from_millis(10),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+        assert!(elapsed >= Duration::from_millis(500) && elapsed < Duration::from_millis(600));
+    }
+}
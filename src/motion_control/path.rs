@@ -0,0 +1,638 @@
+//! Trajectories generated on the robot from poses and control points.
+//!
+//! A [`PathBuilder`] chains cubic Bézier segments, either given directly, fitted between poses or
+//! splined through waypoints, and [`PathBuilder::build`] times them into a [`Trajectory`] under
+//! the [`PathConstraints`]. The trajectory is a [`MotionProfile`], so [`super::ramsete::Ramsete`]
+//! can follow it like a profile loaded from JSON.
+
+use alloc::{vec, vec::Vec};
+use core::{f64::consts::PI, time::Duration};
+
+use motion_profiling::motion_profile::{MotionCommand, MotionProfile};
+use nalgebra::{Vector2, Vector3};
+use uom::{
+    num_traits::Float,
+    si::{
+        acceleration::meter_per_second_squared,
+        angular_velocity::radian_per_second,
+        f64::{Acceleration, AngularVelocity, Length, Velocity},
+        length::meter,
+        velocity::meter_per_second,
+    },
+};
+
+use crate::{
+    config::path_constraints,
+    localization::localization::StateRepresentation,
+    utils::{angle_difference, wrap_angle},
+};
+
+/// Distance between the samples a path is timed over.
+const RESOLUTION: f64 = 0.01;
+
+/// Derivatives shorter than this don't have a usable direction.
+const EPSILON: f64 = 1e-9;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CubicBezier {
+    pub points: [Vector2<f64>; 4],
+}
+
+impl CubicBezier {
+    pub fn new(
+        start: Vector2<f64>,
+        control_1: Vector2<f64>,
+        control_2: Vector2<f64>,
+        end: Vector2<f64>,
+    ) -> Self {
+        Self {
+            points: [start, control_1, control_2, end],
+        }
+    }
+
+    pub fn point(&self, t: f64) -> Vector2<f64> {
+        let [p0, p1, p2, p3] = self.points;
+        let u = 1.0 - t;
+
+        p0 * u.powi(3) + p1 * (3.0 * u * u * t) + p2 * (3.0 * u * t * t) + p3 * t.powi(3)
+    }
+
+    pub fn derivative(&self, t: f64) -> Vector2<f64> {
+        let [p0, p1, p2, p3] = self.points;
+        let u = 1.0 - t;
+
+        (p1 - p0) * (3.0 * u * u) + (p2 - p1) * (6.0 * u * t) + (p3 - p2) * (3.0 * t * t)
+    }
+
+    pub fn second_derivative(&self, t: f64) -> Vector2<f64> {
+        let [p0, p1, p2, p3] = self.points;
+
+        (p2 - p1 * 2.0 + p0) * (6.0 * (1.0 - t)) + (p3 - p2 * 2.0 + p1) * (6.0 * t)
+    }
+
+    /// Signed curvature in 1/m, positive when the curve bends counter-clockwise as `t` increases.
+    pub fn curvature(&self, t: f64) -> f64 {
+        let d = self.derivative(t);
+        let dd = self.second_derivative(t);
+        let speed = d.norm();
+
+        if speed < EPSILON {
+            0.0
+        } else {
+            (d.x * dd.y - d.y * dd.x) / speed.powi(3)
+        }
+    }
+
+    /// Upper bound on the length of the curve.
+    fn control_polygon_length(&self) -> f64 {
+        self.points.windows(2).map(|w| (w[1] - w[0]).norm()).sum()
+    }
+}
+
+/// Limits a path is timed with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathConstraints {
+    /// Fastest either side of the drivetrain may go, so the robot slows down in turns.
+    pub max_velocity: Velocity,
+    pub max_acceleration: Acceleration,
+    /// Most sideways acceleration in turns before the robot slides.
+    pub max_lateral_acceleration: Acceleration,
+    pub track_width: Length,
+}
+
+impl PathConstraints {
+    fn is_valid(&self) -> bool {
+        [
+            self.max_velocity.get::<meter_per_second>(),
+            self.max_acceleration.get::<meter_per_second_squared>(),
+            self.max_lateral_acceleration
+                .get::<meter_per_second_squared>(),
+            self.track_width.get::<meter>(),
+        ]
+        .iter()
+        .all(|value| *value > 0.0 && value.is_finite())
+    }
+
+    /// Fastest the center of the robot can go through a point with curvature `curvature`.
+    fn max_speed(&self, curvature: f64) -> f64 {
+        let curvature = curvature.abs();
+        // The outside wheel goes faster than the center
+        let wheel_limit = self.max_velocity.get::<meter_per_second>()
+            / (1.0 + curvature * self.track_width.get::<meter>() / 2.0);
+
+        if curvature < EPSILON {
+            wheel_limit
+        } else {
+            wheel_limit.min(
+                (self
+                    .max_lateral_acceleration
+                    .get::<meter_per_second_squared>()
+                    / curvature)
+                    .sqrt(),
+            )
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathError {
+    /// The path has no segments.
+    Empty,
+    /// A constraint is zero, negative or not finite.
+    InvalidConstraints,
+    /// A segment starts and ends at the same point, the robot can't turn in place on a path.
+    DegenerateSegment,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    curve: CubicBezier,
+    reversed: bool,
+}
+
+/// Direction the robot moves in with heading `heading`.
+fn travel_direction(heading: f64, reversed: bool) -> Vector2<f64> {
+    let heading = if reversed { heading + PI } else { heading };
+
+    Vector2::new(heading.cos(), heading.sin())
+}
+
+/// Chains path segments from a starting pose. Every segment starts where the last one ended, and
+/// reversed segments are driven backwards with the robot facing away from the direction of travel.
+pub struct PathBuilder {
+    pose: StateRepresentation,
+    segments: Vec<Segment>,
+    constraints: PathConstraints,
+}
+
+impl PathBuilder {
+    pub fn new(start: StateRepresentation) -> Self {
+        Self {
+            pose: start,
+            segments: Vec::new(),
+            constraints: path_constraints(),
+        }
+    }
+
+    pub fn with_constraints(mut self, constraints: PathConstraints) -> Self {
+        self.constraints = constraints;
+        self
+    }
+
+    /// Curves to `pose`, arriving facing its heading.
+    pub fn to_pose(self, pose: StateRepresentation) -> Self {
+        self.hermite(pose, false)
+    }
+
+    /// Backs up to `pose`, arriving facing its heading.
+    pub fn reversed_to_pose(self, pose: StateRepresentation) -> Self {
+        self.hermite(pose, true)
+    }
+
+    /// Follows a Bézier curve from the end of the path through two control points. The first
+    /// control point should be along the current heading for the robot to start smoothly.
+    pub fn bezier(
+        mut self,
+        control_1: Vector2<f64>,
+        control_2: Vector2<f64>,
+        end: Vector2<f64>,
+        reversed: bool,
+    ) -> Self {
+        let curve = CubicBezier::new(self.pose.xy(), control_1, control_2, end);
+        self.push(curve, reversed, None);
+        self
+    }
+
+    /// Splines through `waypoints` (Catmull-Rom), leaving along the current heading.
+    pub fn spline_through(mut self, waypoints: &[Vector2<f64>], reversed: bool) -> Self {
+        let mut points = vec![self.pose.xy()];
+        points.extend_from_slice(waypoints);
+
+        if points.len() < 2 {
+            return self;
+        }
+
+        let last = points.len() - 1;
+        let tangents: Vec<Vector2<f64>> = (0..points.len())
+            .map(|i| {
+                if i == 0 {
+                    travel_direction(self.pose.z, reversed) * (points[1] - points[0]).norm()
+                } else if i == last {
+                    points[last] - points[last - 1]
+                } else {
+                    (points[i + 1] - points[i - 1]) / 2.0
+                }
+            })
+            .collect();
+
+        for i in 0..last {
+            let curve = CubicBezier::new(
+                points[i],
+                points[i] + tangents[i] / 3.0,
+                points[i + 1] - tangents[i + 1] / 3.0,
+                points[i + 1],
+            );
+            self.push(curve, reversed, None);
+        }
+
+        self
+    }
+
+    /// Hermite curve with its tangents along the start and end headings, a third of the distance
+    /// between them long.
+    fn hermite(mut self, pose: StateRepresentation, reversed: bool) -> Self {
+        let start = self.pose.xy();
+        let end = pose.xy();
+        let handle = (end - start).norm() / 3.0;
+
+        let curve = CubicBezier::new(
+            start,
+            start + travel_direction(self.pose.z, reversed) * handle,
+            end - travel_direction(pose.z, reversed) * handle,
+            end,
+        );
+        self.push(curve, reversed, Some(pose.z));
+        self
+    }
+
+    /// Adds a segment, ending facing `heading` or along the end of the curve.
+    fn push(&mut self, curve: CubicBezier, reversed: bool, heading: Option<f64>) {
+        let end = curve.points[3];
+        let heading = heading.unwrap_or_else(|| {
+            let tangent = curve.derivative(1.0);
+
+            if tangent.norm() < EPSILON {
+                self.pose.z
+            } else if reversed {
+                tangent.y.atan2(tangent.x) + PI
+            } else {
+                tangent.y.atan2(tangent.x)
+            }
+        });
+
+        self.segments.push(Segment { curve, reversed });
+        self.pose = StateRepresentation::new(end.x, end.y, wrap_angle(heading));
+    }
+
+    /// Times the path, starting and ending at rest and stopping wherever it changes direction.
+    pub fn build(self) -> Result<Trajectory, PathError> {
+        if !self.constraints.is_valid() {
+            return Err(PathError::InvalidConstraints);
+        }
+        if self.segments.is_empty() {
+            return Err(PathError::Empty);
+        }
+
+        struct Sample {
+            position: Vector2<f64>,
+            heading: f64,
+            curvature: f64,
+            distance: f64,
+            reversed: bool,
+            max_speed: f64,
+        }
+
+        let mut samples: Vec<Sample> = Vec::new();
+        let mut heading = self.pose.z;
+
+        for (index, segment) in self.segments.iter().enumerate() {
+            let length = segment.curve.control_polygon_length();
+            if length < 1e-6 {
+                return Err(PathError::DegenerateSegment);
+            }
+
+            // Direction changes happen at a standstill
+            if let Some(last) = samples.last_mut()
+                && self.segments[index - 1].reversed != segment.reversed
+            {
+                last.max_speed = 0.0;
+            }
+
+            let steps = ((length / RESOLUTION).ceil() as usize).max(8);
+            // Every segment starts where the last one ended
+            let first_step = if index == 0 { 0 } else { 1 };
+
+            for step in first_step..=steps {
+                let t = step as f64 / steps as f64;
+                let position = segment.curve.point(t);
+                let derivative = segment.curve.derivative(t);
+                let curvature = segment.curve.curvature(t);
+
+                if derivative.norm() > EPSILON {
+                    heading = derivative.y.atan2(derivative.x);
+                    if segment.reversed {
+                        heading += PI;
+                    }
+                }
+
+                let distance = samples.last().map_or(0.0, |last| {
+                    last.distance + (position - last.position).norm()
+                });
+
+                samples.push(Sample {
+                    position,
+                    heading: wrap_angle(heading),
+                    curvature,
+                    distance,
+                    reversed: segment.reversed,
+                    max_speed: self.constraints.max_speed(curvature),
+                });
+            }
+        }
+
+        let acceleration = self
+            .constraints
+            .max_acceleration
+            .get::<meter_per_second_squared>();
+
+        // Accelerate as hard as allowed from the start and decelerate into the end, the speed is
+        // whichever is lower
+        let mut speeds = vec![0.0; samples.len()];
+        for i in 1..samples.len() {
+            let distance = samples[i].distance - samples[i - 1].distance;
+            speeds[i] = samples[i]
+                .max_speed
+                .min((speeds[i - 1].powi(2) + 2.0 * acceleration * distance).sqrt());
+        }
+        if let Some(last) = speeds.last_mut() {
+            *last = 0.0;
+        }
+        for i in (0..samples.len() - 1).rev() {
+            let distance = samples[i + 1].distance - samples[i].distance;
+            speeds[i] =
+                speeds[i].min((speeds[i + 1].powi(2) + 2.0 * acceleration * distance).sqrt());
+        }
+
+        let mut time = 0.0;
+        let points = samples
+            .iter()
+            .zip(speeds.iter())
+            .enumerate()
+            .map(|(i, (sample, speed))| {
+                if i > 0 {
+                    let distance = sample.distance - samples[i - 1].distance;
+                    let average_speed = (speeds[i - 1] + speed) / 2.0;
+
+                    if average_speed > EPSILON {
+                        time += distance / average_speed;
+                    }
+                }
+
+                TrajectoryPoint {
+                    time,
+                    pose: Vector3::new(sample.position.x, sample.position.y, sample.heading),
+                    velocity: if sample.reversed { -speed } else { *speed },
+                    angular: sample.curvature * speed,
+                }
+            })
+            .collect();
+
+        Ok(Trajectory { points })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrajectoryPoint {
+    /// Seconds since the start of the trajectory.
+    pub time: f64,
+    pub pose: Vector3<f64>,
+    /// Forward velocity in m/s, negative when driving backwards.
+    pub velocity: f64,
+    /// Counter-clockwise angular velocity in rad/s.
+    pub angular: f64,
+}
+
+/// A timed path from [`PathBuilder`], interpolated between its samples.
+#[derive(Clone, Debug)]
+pub struct Trajectory {
+    points: Vec<TrajectoryPoint>,
+}
+
+impl Trajectory {
+    pub fn points(&self) -> &[TrajectoryPoint] {
+        &self.points
+    }
+
+    pub fn sample(&self, t: f64) -> Option<TrajectoryPoint> {
+        let last = self.points.last()?;
+        if !(0.0..=last.time).contains(&t) {
+            return None;
+        }
+
+        let index = self.points.partition_point(|point| point.time <= t);
+        if index == self.points.len() {
+            return Some(*last);
+        }
+
+        let (a, b) = (self.points[index - 1], self.points[index]);
+        let fraction = if b.time > a.time {
+            (t - a.time) / (b.time - a.time)
+        } else {
+            0.0
+        };
+        let lerp = |a: f64, b: f64| a + (b - a) * fraction;
+
+        Some(TrajectoryPoint {
+            time: t,
+            pose: Vector3::new(
+                lerp(a.pose.x, b.pose.x),
+                lerp(a.pose.y, b.pose.y),
+                wrap_angle(a.pose.z + angle_difference(b.pose.z, a.pose.z) * fraction),
+            ),
+            velocity: lerp(a.velocity, b.velocity),
+            angular: lerp(a.angular, b.angular),
+        })
+    }
+}
+
+impl MotionProfile for Trajectory {
+    fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.points.last().map_or(0.0, |point| point.time))
+    }
+
+    fn get(&mut self, t: Duration) -> Option<MotionCommand> {
+        let point = self.sample(t.as_secs_f64())?;
+
+        Some(MotionCommand {
+            desired_velocity: Velocity::new::<meter_per_second>(point.velocity),
+            desired_angular: AngularVelocity::new::<radian_per_second>(point.angular),
+            desired_pose: point.pose,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc};
+    use core::{cell::RefCell, f64::consts::FRAC_PI_2};
+
+    use super::*;
+    use crate::{
+        config::{track_width, wheel_diameter, DRIVE_RATIO},
+        motion_control::ramsete::Ramsete,
+        sim::{plant::TankPlant, run_velocity},
+    };
+
+    fn constraints() -> PathConstraints {
+        PathConstraints {
+            max_velocity: Velocity::new::<meter_per_second>(1.0),
+            max_acceleration: Acceleration::new::<meter_per_second_squared>(1.0),
+            max_lateral_acceleration: Acceleration::new::<meter_per_second_squared>(1.0),
+            track_width: Length::new::<meter>(0.5),
+        }
+    }
+
+    #[test]
+    fn straight_line_is_trapezoidal() {
+        let trajectory = PathBuilder::new(StateRepresentation::new(0.0, 0.0, 0.0))
+            .with_constraints(constraints())
+            .to_pose(StateRepresentation::new(2.0, 0.0, 0.0))
+            .build()
+            .unwrap();
+
+        // 1s to speed up over 0.5m, 1s at 1 m/s, 1s to slow down
+        let duration = trajectory.duration().as_secs_f64();
+        assert!((duration - 3.0).abs() < 0.02, "{duration}");
+
+        let middle = trajectory.sample(1.5).unwrap();
+        assert!((middle.velocity - 1.0).abs() < 1e-6);
+        assert!((middle.pose.x - 1.0).abs() < 0.01);
+
+        let end = trajectory.sample(duration).unwrap();
+        assert!((end.pose.x - 2.0).abs() < 1e-9);
+        assert_eq!(end.velocity, 0.0);
+        assert!(trajectory.sample(duration + 0.1).is_none());
+    }
+
+    #[test]
+    fn slows_down_in_turns() {
+        let constraints = constraints();
+        let trajectory = PathBuilder::new(StateRepresentation::new(0.0, 0.0, 0.0))
+            .with_constraints(constraints)
+            .to_pose(StateRepresentation::new(1.0, 1.0, FRAC_PI_2))
+            .build()
+            .unwrap();
+
+        let mut slowest_in_turn = f64::INFINITY;
+        for point in trajectory.points() {
+            let curvature = point.angular.abs() / point.velocity.abs().max(EPSILON);
+            let outer_wheel = point.velocity.abs() + point.angular.abs() * 0.25;
+
+            assert!(outer_wheel <= 1.0 + 1e-6, "{point:?}");
+            assert!(
+                point.velocity.powi(2) * curvature <= 1.0 + 1e-6,
+                "{point:?}"
+            );
+
+            if point.time > 0.5 && point.time < trajectory.duration().as_secs_f64() - 0.5 {
+                slowest_in_turn = slowest_in_turn.min(point.velocity);
+            }
+        }
+        assert!(slowest_in_turn < 0.9, "{slowest_in_turn}");
+
+        let end = trajectory.points().last().unwrap();
+        assert!((end.pose.z - FRAC_PI_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reversed_segments_drive_backwards() {
+        let trajectory = PathBuilder::new(StateRepresentation::new(0.0, 0.0, 0.0))
+            .with_constraints(constraints())
+            .reversed_to_pose(StateRepresentation::new(-1.0, 0.0, 0.0))
+            .to_pose(StateRepresentation::new(0.0, 0.0, 0.0))
+            .build()
+            .unwrap();
+
+        let points = trajectory.points();
+        let cusp = points
+            .iter()
+            .position(|point| (point.pose.x + 1.0).abs() < 1e-9)
+            .unwrap();
+
+        // Backing up keeps facing forward, then stops before driving forward again
+        assert!(points[1..cusp].iter().all(|point| point.velocity < 0.0));
+        assert!(points[..cusp]
+            .iter()
+            .all(|point| angle_difference(point.pose.z, 0.0).abs() < 1e-6));
+        assert_eq!(points[cusp].velocity, 0.0);
+        assert!(points[cusp + 1..points.len() - 1]
+            .iter()
+            .all(|point| point.velocity > 0.0));
+    }
+
+    #[test]
+    fn splines_through_waypoints() {
+        let waypoints = [Vector2::new(1.0, 0.5), Vector2::new(2.0, 0.0)];
+        let trajectory = PathBuilder::new(StateRepresentation::new(0.0, 0.0, 0.0))
+            .with_constraints(constraints())
+            .spline_through(&waypoints, false)
+            .build()
+            .unwrap();
+
+        for waypoint in waypoints {
+            assert!(trajectory
+                .points()
+                .iter()
+                .any(|point| (point.pose.xy() - waypoint).norm() < 1e-9));
+        }
+        // Leaves along the starting heading
+        assert!(trajectory.points()[1].pose.z.abs() < 0.05);
+    }
+
+    #[test]
+    fn rejects_invalid_paths() {
+        let start = StateRepresentation::new(0.0, 0.0, 0.0);
+
+        assert_eq!(
+            PathBuilder::new(start).build().unwrap_err(),
+            PathError::Empty
+        );
+        assert_eq!(
+            PathBuilder::new(start)
+                .with_constraints(PathConstraints {
+                    max_acceleration: Acceleration::new::<meter_per_second_squared>(0.0),
+                    ..constraints()
+                })
+                .to_pose(StateRepresentation::new(1.0, 0.0, 0.0))
+                .build()
+                .unwrap_err(),
+            PathError::InvalidConstraints
+        );
+        assert_eq!(
+            PathBuilder::new(start).to_pose(start).build().unwrap_err(),
+            PathError::DegenerateSegment
+        );
+    }
+
+    #[test]
+    fn ramsete_follows_generated_path() {
+        // A tight velocity loop, like the drivetrain's feedforward gives
+        let plant = Rc::new(RefCell::new(
+            TankPlant::new(
+                track_width(),
+                wheel_diameter(),
+                DRIVE_RATIO,
+                Velocity::new::<meter_per_second>(1.5),
+            )
+            .with_time_constant(Duration::from_millis(20)),
+        ));
+        let target = StateRepresentation::new(1.0, 0.6, FRAC_PI_2);
+        let trajectory = PathBuilder::new(StateRepresentation::new(0.0, 0.0, 0.0))
+            .to_pose(target)
+            .build()
+            .unwrap();
+
+        let mut ramsete = Ramsete::try_new(0.7, 2.0, Box::new(trajectory)).unwrap();
+        run_velocity(
+            &plant,
+            &mut ramsete,
+            Duration::from_millis(10),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+
+        let pose = plant.borrow().pose();
+        assert!((pose.xy() - target.xy()).norm() < 0.05, "{pose}");
+        assert!(angle_difference(pose.z, target.z).abs() < 0.1, "{pose}");
+    }
+}
@@ -0,0 +1,169 @@
+use crate::hal::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PidGains {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+}
+
+/// PID controller on an error that's computed by the caller, so angles can be wrapped first.
+///
+/// The integral is limited so it can't wind up while the output is saturated and is cleared when
+/// the error changes sign, and the derivative is smoothed with a low pass filter so it doesn't
+/// amplify sensor noise. When a move is done is up to the move, see
+/// [`super::exit::ExitConditions`].
+pub struct Pid {
+    gains: PidGains,
+    integral: f64,
+    integral_limit: f64,
+    derivative: f64,
+    derivative_filter: f64,
+    output_limit: f64,
+    last_error: Option<f64>,
+    last_time: Instant,
+}
+
+impl Pid {
+    pub fn new(gains: PidGains) -> Self {
+        Self {
+            gains,
+            integral: 0.0,
+            integral_limit: f64::INFINITY,
+            derivative: 0.0,
+            derivative_filter: 1.0,
+            output_limit: f64::INFINITY,
+            last_error: None,
+            last_time: Instant::now(),
+        }
+    }
+
+    /// Largest output the integral term alone can contribute.
+    pub fn with_integral_limit(mut self, integral_limit: f64) -> Self {
+        self.integral_limit = integral_limit;
+        self
+    }
+
+    /// Weight of the newest derivative sample in the low pass filter, 1 disables the filter and
+    /// values closer to 0 smooth more.
+    pub fn with_derivative_filter(mut self, derivative_filter: f64) -> Self {
+        assert!(
+            derivative_filter > 0.0 && derivative_filter <= 1.0,
+            "Derivative filter must be in (0, 1]"
+        );
+
+        self.derivative_filter = derivative_filter;
+        self
+    }
+
+    /// Clamps the output to `[-output_limit, output_limit]`.
+    pub fn with_output_limit(mut self, output_limit: f64) -> Self {
+        self.output_limit = output_limit;
+        self
+    }
+
+    /// Clears the controller's history, call before starting a new move.
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.derivative = 0.0;
+        self.last_error = None;
+        self.last_time = Instant::now();
+    }
+
+    pub fn update(&mut self, error: f64) -> f64 {
+        let now = Instant::now();
+        let dt = (now - self.last_time).as_secs_f64();
+        self.last_time = now;
+
+        if let Some(last_error) = self.last_error
+            && dt > 0.0
+        {
+            let derivative = (error - last_error) / dt;
+            self.derivative += (derivative - self.derivative) * self.derivative_filter;
+
+            // The integral only slows down the approach once the error has crossed over
+            if error.signum() != last_error.signum() {
+                self.integral = 0.0;
+            } else {
+                self.integral += error * dt;
+            }
+        }
+        self.last_error = Some(error);
+
+        if self.gains.ki != 0.0 {
+            let integral_limit = self.integral_limit / self.gains.ki.abs();
+            self.integral = self.integral.clamp(-integral_limit, integral_limit);
+        }
+
+        (self.gains.kp * error + self.gains.ki * self.integral + self.gains.kd * self.derivative)
+            .clamp(-self.output_limit, self.output_limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+    use crate::sim::clock;
+
+    const GAINS: PidGains = PidGains {
+        kp: 2.0,
+        ki: 1.0,
+        kd: 0.5,
+    };
+
+    fn step(pid: &mut Pid, error: f64) -> f64 {
+        clock::advance(Duration::from_millis(100));
+        pid.update(error)
+    }
+
+    #[test]
+    fn combines_terms() {
+        let mut pid = Pid::new(GAINS);
+
+        // No history yet, so only the proportional term
+        assert_eq!(step(&mut pid, 1.0), 2.0);
+
+        // Integral of 0.05 and derivative of -5
+        let output = step(&mut pid, 0.5);
+        assert!((output - (1.0 + 0.05 - 2.5)).abs() < 1e-9, "{output}");
+    }
+
+    #[test]
+    fn limits_windup() {
+        let mut pid = Pid::new(GAINS).with_integral_limit(0.5);
+
+        for _ in 0..100 {
+            step(&mut pid, 1.0);
+        }
+        assert!((step(&mut pid, 1.0) - 2.5).abs() < 1e-9);
+
+        // Crossing zero drops the integral instead of overshooting on it
+        let output = step(&mut pid, -0.1);
+        assert!((output - (-0.2 + 0.5 * -11.0)).abs() < 1e-9, "{output}");
+    }
+
+    #[test]
+    fn filters_derivative() {
+        let mut pid = Pid::new(PidGains {
+            kp: 0.0,
+            ki: 0.0,
+            kd: 1.0,
+        })
+        .with_derivative_filter(0.25);
+
+        step(&mut pid, 0.0);
+        // A single jump of 1 in 0.1s is a derivative of 10, a quarter of which gets through
+        assert!((step(&mut pid, 1.0) - 2.5).abs() < 1e-9);
+        assert!((step(&mut pid, 1.0) - 1.875).abs() < 1e-9);
+    }
+
+    #[test]
+    fn clamps_output() {
+        let mut pid = Pid::new(GAINS).with_output_limit(12.0);
+
+        assert_eq!(step(&mut pid, 100.0), 12.0);
+        assert_eq!(step(&mut pid, -100.0), -12.0);
+    }
+}
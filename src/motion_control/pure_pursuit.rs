@@ -0,0 +1,223 @@
+use alloc::vec::Vec;
+
+use nalgebra::{Rotation2, Vector2};
+use uom::{
+    num_traits::Float,
+    si::{
+        f64::{AngularVelocity, Length, Velocity},
+        length::meter,
+        velocity::meter_per_second,
+    },
+};
+
+use crate::{
+    config::{motion_settle_tolerance, MOTION_SETTLE_TIME, MOTION_TIMEOUT},
+    localization::localization::StateRepresentation,
+    motion_control::{exit::ExitConditions, wheel_velocities},
+    state_machine::State,
+};
+
+/// Follows a polyline of waypoints by steering for the point where it leaves a circle of radius
+/// `lookahead` around the robot.
+///
+/// Slows down over the last lookahead distance and finishes once it has settled on the last
+/// waypoint.
+pub struct PurePursuit {
+    waypoints: Vec<Vector2<f64>>,
+    lookahead: f64,
+    max_speed: f64,
+    tolerance: f64,
+    segment: usize,
+    exit: ExitConditions,
+}
+
+impl PurePursuit {
+    pub fn new(waypoints: Vec<Vector2<f64>>, lookahead: Length, max_speed: Velocity) -> Self {
+        assert!(!waypoints.is_empty(), "Need at least one waypoint");
+
+        Self {
+            waypoints,
+            lookahead: lookahead.get::<meter>(),
+            max_speed: max_speed.get::<meter_per_second>(),
+            tolerance: motion_settle_tolerance().get::<meter>(),
+            segment: 0,
+            exit: ExitConditions::new(MOTION_SETTLE_TIME, MOTION_TIMEOUT),
+        }
+    }
+
+    /// Distance from the last waypoint at which the path counts as finished.
+    pub fn with_tolerance(mut self, tolerance: Length) -> Self {
+        self.tolerance = tolerance.get::<meter>();
+        self
+    }
+
+    pub fn with_exit_conditions(mut self, exit: ExitConditions) -> Self {
+        self.exit = exit;
+        self
+    }
+
+    /// Point on the path to steer for, never going back to an earlier segment.
+    fn goal(&mut self, position: &Vector2<f64>) -> Vector2<f64> {
+        let last = *self.waypoints.last().unwrap();
+
+        if (last - position).magnitude() < self.lookahead {
+            self.segment = self.waypoints.len().saturating_sub(2);
+            return last;
+        }
+
+        let mut goal = None;
+        for (i, segment) in self.waypoints.windows(2).enumerate().skip(self.segment) {
+            if let Some(point) = circle_exit(position, self.lookahead, &segment[0], &segment[1]) {
+                self.segment = i;
+                goal = Some(point);
+            }
+
+            // Nothing further along can cross the circle without the path looping back
+            if (segment[1] - position).magnitude() > self.lookahead {
+                break;
+            }
+        }
+
+        // Off the path, head for the end of the current segment to get back on it
+        goal.unwrap_or(self.waypoints[(self.segment + 1).min(self.waypoints.len() - 1)])
+    }
+}
+
+/// Where the segment from `a` to `b` leaves the circle around `center`, if it does.
+fn circle_exit(
+    center: &Vector2<f64>,
+    radius: f64,
+    a: &Vector2<f64>,
+    b: &Vector2<f64>,
+) -> Option<Vector2<f64>> {
+    let direction = b - a;
+    let offset = a - center;
+
+    let qa = direction.dot(&direction);
+    let qb = 2.0 * offset.dot(&direction);
+    let qc = offset.dot(&offset) - radius * radius;
+    let discriminant = qb * qb - 4.0 * qa * qc;
+
+    if qa == 0.0 || discriminant < 0.0 {
+        return None;
+    }
+
+    let t = (-qb + discriminant.sqrt()) / (2.0 * qa);
+
+    (0.0..=1.0).contains(&t).then(|| a + direction * t)
+}
+
+impl State<StateRepresentation, (AngularVelocity, AngularVelocity)> for PurePursuit {
+    fn init(&mut self) {
+        self.segment = 0;
+        self.exit.reset();
+    }
+
+    fn update(&mut self, i: &StateRepresentation) -> Option<(AngularVelocity, AngularVelocity)> {
+        let position = i.xy();
+        let remaining = (self.waypoints.last().unwrap() - position).magnitude();
+
+        if self.exit.done(remaining < self.tolerance) {
+            return None;
+        }
+
+        let goal = Rotation2::new(-i.z) * (self.goal(&position) - position);
+
+        // Curvature of the arc through the goal that's tangent to the robot's heading
+        let distance_squared = goal.magnitude_squared();
+        let curvature = if distance_squared > 0.0 {
+            2.0 * goal.y / distance_squared
+        } else {
+            0.0
+        };
+
+        let linear = self.max_speed * (remaining / self.lookahead).min(1.0);
+
+        Some(wheel_velocities(linear, linear * curvature, self.max_speed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use core::time::Duration;
+
+    use super::*;
+    use crate::sim::{drive_plant, run_velocity};
+
+    #[test]
+    fn finds_circle_exit() {
+        let exit = circle_exit(
+            &Vector2::new(0.0, 0.0),
+            1.0,
+            &Vector2::new(-2.0, 0.5),
+            &Vector2::new(2.0, 0.5),
+        )
+        .unwrap();
+        assert!((exit - Vector2::new(0.75_f64.sqrt(), 0.5)).magnitude() < 1e-9);
+
+        // Ends inside the circle
+        assert_eq!(
+            circle_exit(
+                &Vector2::new(0.0, 0.0),
+                1.0,
+                &Vector2::new(-2.0, 0.0),
+                &Vector2::new(0.5, 0.0),
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn follows_waypoints() {
+        let plant = drive_plant();
+        let mut pure_pursuit = PurePursuit::new(
+            vec![
+                Vector2::new(0.0, 0.0),
+                Vector2::new(1.0, 0.0),
+                Vector2::new(1.0, 1.0),
+            ],
+            Length::new::<meter>(0.3),
+            Velocity::new::<meter_per_second>(1.0),
+        );
+
+        let elapsed = run_velocity(
+            &plant,
+            &mut pure_pursuit,
+            Duration::from_millis(10),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+
+        let pose = plant.borrow().pose();
+        assert!(elapsed < MOTION_TIMEOUT, "{elapsed:?}");
+        assert!(
+            (pose.xy() - Vector2::new(1.0, 1.0)).magnitude() < 0.03,
+            "{pose}"
+        );
+    }
+
+    #[test]
+    fn times_out() {
+        let plant = drive_plant();
+        let mut pure_pursuit = PurePursuit::new(
+            vec![Vector2::new(0.0, 0.0), Vector2::new(3.0, 0.0)],
+            Length::new::<meter>(0.3),
+            Velocity::new::<meter_per_second>(0.1),
+        )
+        .with_exit_conditions(ExitConditions::new(
+            Duration::from_millis(250),
+            Duration::from_secs(2),
+        ));
+
+        let elapsed = run_velocity(
+            &plant,
+            &mut pure_pursuit,
+            Duration::from_millis(10),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+
+        assert_eq!(elapsed, Duration::from_secs(2));
+    }
+}
@@ -3,61 +3,153 @@ use alloc::boxed::Box;
 use motion_profiling::motion_profile::MotionProfile;
 use nalgebra::{Matrix3, SimdComplexField};
 use uom::{
-    num_traits::{real::Real, Pow},
+    num_traits::Float,
     si::{
-        angular_velocity::radian_per_second, f64::AngularVelocity, length::meter,
+        angular_acceleration::radian_per_second_squared,
+        angular_velocity::radian_per_second,
+        f64::{AngularAcceleration, AngularVelocity, Length},
+        length::meter,
         velocity::meter_per_second,
     },
 };
-use vexide::core::time::Instant;
 
 use crate::{
-    config::{track_width, wheel_diameter},
+    config::{track_width, wheel_diameter, DRIVE_RATIO},
+    hal::time::Instant,
     localization::localization::StateRepresentation,
+    motion_control::feedforward::VelocityTarget,
     state_machine::State,
     utils::angle_difference,
 };
 
-pub struct Ramsete {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RamseteError {
+    /// Zeta, the damping ratio, has to be in (0, 1].
+    InvalidZeta,
+    /// Beta, the aggressiveness, has to be positive.
+    InvalidBeta,
+    InvalidTrackWidth,
+    InvalidWheelDiameter,
+    InvalidGearRatio,
+}
+
+/// Gains and drivetrain geometry of a [`Ramsete`] controller, validated on construction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RamseteConfig {
     zeta: f64,
     beta: f64,
-    motion_profile: Box<dyn MotionProfile>,
-    start_time: Instant,
+    track_width: Length,
+    wheel_diameter: Length,
+    gear_ratio: f64,
 }
 
-#[derive(Debug)]
-pub enum RamseteError {
-    InvalidBeta,
+impl RamseteConfig {
+    /// Uses the geometry of the robot's drivetrain from [`crate::config`].
+    pub fn try_new(zeta: f64, beta: f64) -> Result<Self, RamseteError> {
+        // Written so NaN fails every check
+        if !(zeta > 0.0 && zeta <= 1.0) {
+            return Err(RamseteError::InvalidZeta);
+        }
+        if !(beta > 0.0 && beta.is_finite()) {
+            return Err(RamseteError::InvalidBeta);
+        }
+
+        Self {
+            zeta,
+            beta,
+            track_width: track_width(),
+            wheel_diameter: wheel_diameter(),
+            gear_ratio: DRIVE_RATIO,
+        }
+        .try_with_geometry(track_width(), wheel_diameter(), DRIVE_RATIO)
+    }
+
+    /// `gear_ratio` is wheel rotations per motor rotation, as in
+    /// [`TrackingWheel`](crate::sensor::rotary::TrackingWheel).
+    pub fn try_with_geometry(
+        mut self,
+        track_width: Length,
+        wheel_diameter: Length,
+        gear_ratio: f64,
+    ) -> Result<Self, RamseteError> {
+        let positive = |value: f64| value > 0.0 && value.is_finite();
+
+        if !positive(track_width.get::<meter>()) {
+            return Err(RamseteError::InvalidTrackWidth);
+        }
+        if !positive(wheel_diameter.get::<meter>()) {
+            return Err(RamseteError::InvalidWheelDiameter);
+        }
+        if !positive(gear_ratio) {
+            return Err(RamseteError::InvalidGearRatio);
+        }
+
+        self.track_width = track_width;
+        self.wheel_diameter = wheel_diameter;
+        self.gear_ratio = gear_ratio;
+        Ok(self)
+    }
+
+    pub fn zeta(&self) -> f64 {
+        self.zeta
+    }
+
+    pub fn beta(&self) -> f64 {
+        self.beta
+    }
+}
+
+/// Follows a motion profile with a RAMSETE controller, outputting the motor velocity of each side
+/// along with the acceleration the profile plans for it.
+pub struct Ramsete {
+    config: RamseteConfig,
+    motion_profile: Box<dyn MotionProfile>,
+    start_time: Instant,
+    /// Motor velocities the profile asked for last update, and when.
+    last_profile: Option<((f64, f64), Instant)>,
 }
 
 impl Ramsete {
+    pub fn new(config: RamseteConfig, motion_profile: Box<dyn MotionProfile>) -> Self {
+        Self {
+            config,
+            motion_profile,
+            start_time: Instant::now(),
+            last_profile: None,
+        }
+    }
+
+    /// Shorthand for [`RamseteConfig::try_new`] with the robot's drivetrain geometry.
     pub fn try_new(
         zeta: f64,
         beta: f64,
         motion_profile: Box<dyn MotionProfile>,
     ) -> Result<Self, RamseteError> {
-        if 0.0 < beta && 1.0 > beta {
-            Err(RamseteError::InvalidBeta)
-        } else {
-            Ok(Self {
-                zeta,
-                beta,
-                motion_profile,
-                start_time: Instant::now(),
-            })
-        }
+        Ok(Self::new(
+            RamseteConfig::try_new(zeta, beta)?,
+            motion_profile,
+        ))
     }
 }
 
-impl<'a> State<StateRepresentation, (AngularVelocity, AngularVelocity)> for Ramsete {
+impl State<StateRepresentation, (VelocityTarget, VelocityTarget)> for Ramsete {
     fn init(&mut self) {
         self.start_time = Instant::now();
+        self.last_profile = None;
     }
 
-    fn update(&mut self, i: &StateRepresentation) -> Option<(AngularVelocity, AngularVelocity)> {
-        let command = self.motion_profile.get(Instant::now() - self.start_time)?;
+    fn update(&mut self, i: &StateRepresentation) -> Option<(VelocityTarget, VelocityTarget)> {
+        let now = Instant::now();
+        let command = self.motion_profile.get(now - self.start_time)?;
+        let RamseteConfig {
+            zeta,
+            beta,
+            track_width,
+            wheel_diameter,
+            gear_ratio,
+        } = self.config;
 
-        let error = Matrix3::new(
+        let mut error = Matrix3::new(
             i.z.cos(),
             i.z.sin(),
             0.0,
@@ -68,32 +160,52 @@ impl<'a> State<StateRepresentation, (AngularVelocity, AngularVelocity)> for Rams
             0.0,
             1.0,
         ) * (command.desired_pose - i);
+        // sinc isn't periodic, so the heading error has to be the short way around
+        error.z = angle_difference(error.z, 0.0);
+
+        let velocity = command.desired_velocity.get::<meter_per_second>();
+        let angular = command.desired_angular.get::<radian_per_second>();
+
+        let k = 2.0 * zeta * (angular.powi(2) + beta * velocity.powi(2)).sqrt();
+
+        let velocity_commanded = velocity * error.z.cos() + k * error.x;
+        let angular_wheel_velocity_commanded =
+            (angular + k * error.z + beta * velocity * error.z.simd_sinc() * error.y)
+                * track_width.get::<meter>()
+                / 2.0;
+
+        let motor_radius = wheel_diameter.get::<meter>() / 2.0 * gear_ratio;
 
-        let k = 2.0
-            * self.zeta
-            * (command.desired_angular.get::<radian_per_second>().pow(2) as f64
-                + self.beta * command.desired_velocity.get::<meter_per_second>().pow(2) as f64)
-                .sqrt();
-
-        let velocity_commanded =
-            command.desired_velocity.get::<meter_per_second>() * error.z.cos() + k * error.x;
-        let angular_wheel_velocity_commanded = (command.desired_angular.get::<radian_per_second>()
-            + k * angle_difference(error.z, 0.0)
-            + self.beta
-                * command.desired_velocity.get::<meter_per_second>()
-                * error.z.simd_sinc()
-                * error.y)
-            * track_width().get::<meter>()
-            / 2.0;
+        // The acceleration comes from the profile alone, the correction above would add noise
+        let angular_wheel_velocity = angular * track_width.get::<meter>() / 2.0;
+        let profile = (
+            (velocity - angular_wheel_velocity) / motor_radius,
+            (velocity + angular_wheel_velocity) / motor_radius,
+        );
+        let acceleration = match self.last_profile {
+            Some((last, last_time)) if now > last_time => {
+                let dt = (now - last_time).as_secs_f64();
+                ((profile.0 - last.0) / dt, (profile.1 - last.1) / dt)
+            }
+            _ => (0.0, 0.0),
+        };
+        self.last_profile = Some((profile, now));
+
+        let target = |velocity: f64, acceleration: f64| VelocityTarget {
+            velocity: AngularVelocity::new::<radian_per_second>(velocity),
+            acceleration: Some(AngularAcceleration::new::<radian_per_second_squared>(
+                acceleration,
+            )),
+        };
 
         Some((
-            AngularVelocity::new::<radian_per_second>(
-                (velocity_commanded - angular_wheel_velocity_commanded)
-                    / (wheel_diameter().get::<meter>() / 2.0),
+            target(
+                (velocity_commanded - angular_wheel_velocity_commanded) / motor_radius,
+                acceleration.0,
             ),
-            AngularVelocity::new::<radian_per_second>(
-                (velocity_commanded + angular_wheel_velocity_commanded)
-                    / (wheel_diameter().get::<meter>() / 2.0),
+            target(
+                (velocity_commanded + angular_wheel_velocity_commanded) / motor_radius,
+                acceleration.1,
             ),
         ))
     }
@@ -101,11 +213,17 @@ impl<'a> State<StateRepresentation, (AngularVelocity, AngularVelocity)> for Rams
 
 #[cfg(test)]
 mod tests {
-    use core::time::Duration;
+    use core::{
+        f64::consts::{FRAC_PI_2, TAU},
+        time::Duration,
+    };
 
     use motion_profiling::motion_profile::MotionCommand;
+    use nalgebra::Vector3;
+    use uom::si::f64::Velocity;
 
     use super::*;
+    use crate::sim::clock;
 
     struct DummyMotionProfile;
 
@@ -114,15 +232,204 @@ mod tests {
             Duration::new(0, 0)
         }
 
-        fn get(&mut self, t: Duration) -> Option<MotionCommand> {
+        fn get(&mut self, _t: Duration) -> Option<MotionCommand> {
             None
         }
     }
 
+    /// Asks for the same pose and speeds forever.
+    struct ConstantMotionProfile(MotionCommand);
+
+    impl MotionProfile for ConstantMotionProfile {
+        fn duration(&self) -> Duration {
+            Duration::MAX
+        }
+
+        fn get(&mut self, _t: Duration) -> Option<MotionCommand> {
+            Some(self.0)
+        }
+    }
+
+    /// zeta 0.7 and beta 2 give k = 1.4 * sqrt(2) at 1 m/s without turning. The 0.5 m track and
+    /// 0.1 m wheels geared 2:1 turn 1 m/s of a side into 10 rad/s at the motor.
+    fn wheel_speeds(
+        pose: StateRepresentation,
+        desired_pose: StateRepresentation,
+        angular: f64,
+    ) -> (f64, f64) {
+        let config = RamseteConfig::try_new(0.7, 2.0)
+            .unwrap()
+            .try_with_geometry(Length::new::<meter>(0.5), Length::new::<meter>(0.1), 2.0)
+            .unwrap();
+        let mut ramsete = Ramsete::new(
+            config,
+            Box::new(ConstantMotionProfile(MotionCommand {
+                desired_velocity: Velocity::new::<meter_per_second>(1.0),
+                desired_angular: AngularVelocity::new::<radian_per_second>(angular),
+                desired_pose,
+            })),
+        );
+
+        ramsete.init();
+        let (left, right) = ramsete.update(&pose).unwrap();
+        (
+            left.velocity.get::<radian_per_second>(),
+            right.velocity.get::<radian_per_second>(),
+        )
+    }
+
+    /// Speeds up at 1 m/s² along the x axis.
+    struct RampMotionProfile;
+
+    impl MotionProfile for RampMotionProfile {
+        fn duration(&self) -> Duration {
+            Duration::MAX
+        }
+
+        fn get(&mut self, t: Duration) -> Option<MotionCommand> {
+            let t = t.as_secs_f64();
+
+            Some(MotionCommand {
+                desired_velocity: Velocity::new::<meter_per_second>(t),
+                desired_angular: AngularVelocity::new::<radian_per_second>(0.0),
+                desired_pose: Vector3::new(t * t / 2.0, 0.0, 0.0),
+            })
+        }
+    }
+
+    fn assert_speeds(actual: (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-4 && (actual.1 - expected.1).abs() < 1e-4,
+            "{actual:?} != {expected:?}"
+        );
+    }
+
     #[test]
     fn build_ramsete() {
-        let mut mp = DummyMotionProfile;
-        let ramsete = Ramsete::try_new(1.0, 0.5, &mut mp);
+        let ramsete = Ramsete::try_new(1.0, 0.5, Box::new(DummyMotionProfile));
         assert!(ramsete.is_ok());
     }
+
+    #[test]
+    fn rejects_invalid_gains() {
+        assert_eq!(
+            RamseteConfig::try_new(0.0, 2.0),
+            Err(RamseteError::InvalidZeta)
+        );
+        assert_eq!(
+            RamseteConfig::try_new(1.5, 2.0),
+            Err(RamseteError::InvalidZeta)
+        );
+        assert_eq!(
+            RamseteConfig::try_new(f64::NAN, 2.0),
+            Err(RamseteError::InvalidZeta)
+        );
+        assert_eq!(
+            RamseteConfig::try_new(0.7, 0.0),
+            Err(RamseteError::InvalidBeta)
+        );
+        assert_eq!(
+            RamseteConfig::try_new(0.7, f64::NAN),
+            Err(RamseteError::InvalidBeta)
+        );
+        assert!(RamseteConfig::try_new(1.0, 0.5).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_geometry() {
+        let config = RamseteConfig::try_new(0.7, 2.0).unwrap();
+        let length = Length::new::<meter>;
+
+        assert_eq!(
+            config.try_with_geometry(length(0.0), length(0.1), 1.0),
+            Err(RamseteError::InvalidTrackWidth)
+        );
+        assert_eq!(
+            config.try_with_geometry(length(0.5), length(-0.1), 1.0),
+            Err(RamseteError::InvalidWheelDiameter)
+        );
+        assert_eq!(
+            config.try_with_geometry(length(0.5), length(0.1), 0.0),
+            Err(RamseteError::InvalidGearRatio)
+        );
+    }
+
+    #[test]
+    fn follows_feedforward_on_path() {
+        let pose = StateRepresentation::new(0.0, 0.0, 0.0);
+
+        assert_speeds(wheel_speeds(pose, pose, 0.0), (10.0, 10.0));
+        // Turning at 0.5 rad/s moves each side 0.125 m/s
+        assert_speeds(wheel_speeds(pose, pose, 0.5), (8.75, 11.25));
+    }
+
+    #[test]
+    fn plans_acceleration_from_profile() {
+        let config = RamseteConfig::try_new(0.7, 2.0)
+            .unwrap()
+            .try_with_geometry(Length::new::<meter>(0.5), Length::new::<meter>(0.1), 2.0)
+            .unwrap();
+        let mut ramsete = Ramsete::new(config, Box::new(RampMotionProfile));
+        let acceleration = |target: VelocityTarget| {
+            target
+                .acceleration
+                .unwrap()
+                .get::<radian_per_second_squared>()
+        };
+
+        ramsete.init();
+        clock::advance(Duration::from_millis(100));
+        ramsete.update(&StateRepresentation::zeros()).unwrap();
+
+        // Well off the path, but the correction doesn't count as acceleration
+        clock::advance(Duration::from_millis(100));
+        let (left, right) = ramsete
+            .update(&StateRepresentation::new(-0.5, 0.2, 0.3))
+            .unwrap();
+        assert!((acceleration(left) - 10.0).abs() < 1e-6);
+        assert!((acceleration(right) - 10.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn corrects_error_in_robot_frame() {
+        // 0.1 m behind the path speeds up by k * 0.1 = 0.19799 m/s, whichever way the robot faces
+        assert_speeds(
+            wheel_speeds(
+                StateRepresentation::new(0.0, 0.0, 0.0),
+                StateRepresentation::new(0.1, 0.0, 0.0),
+                0.0,
+            ),
+            (11.9799, 11.9799),
+        );
+        assert_speeds(
+            wheel_speeds(
+                StateRepresentation::new(0.0, 0.0, FRAC_PI_2),
+                StateRepresentation::new(0.0, 0.1, FRAC_PI_2),
+                0.0,
+            ),
+            (11.9799, 11.9799),
+        );
+
+        // 0.1 m right of the path turns left at beta * 0.1 = 0.2 rad/s
+        assert_speeds(
+            wheel_speeds(
+                StateRepresentation::new(0.0, 0.0, 0.0),
+                StateRepresentation::new(0.0, 0.1, 0.0),
+                0.0,
+            ),
+            (9.5, 10.5),
+        );
+    }
+
+    #[test]
+    fn wraps_heading_error() {
+        // 0.1 rad clockwise of the robot across zero, so it slows to cos(0.1) = 0.995004 m/s and
+        // turns right at k * 0.1 = 0.19799 rad/s
+        let desired = Vector3::new(0.0, 0.0, TAU - 0.05);
+
+        assert_speeds(
+            wheel_speeds(StateRepresentation::new(0.0, 0.0, 0.05), desired, 0.0),
+            (10.4450, 9.4551),
+        );
+    }
 }
@@ -0,0 +1,262 @@
+//! Fusion of several IMUs into a single heading change, so one bad or unplugged sensor doesn't
+//! corrupt the heading.
+
+use alloc::{vec, vec::Vec};
+
+use uom::{num_traits::Float, si::angle::radian};
+
+use crate::{config::IMU_OUTLIER_THRESHOLD, hal::Imu, utils::angle_difference};
+
+/// Where the heading of a motion model is coming from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeadingHealth {
+    /// Every IMU is read and agrees with the others.
+    Healthy,
+    /// Some IMUs are unavailable or rejected as outliers, the rest are averaged.
+    Degraded { working: usize, total: usize },
+    /// No IMU can be used, the heading comes from the drive wheels and will drift.
+    WheelOdometry,
+    /// Nothing measures the heading.
+    Lost,
+}
+
+/// Averages the heading change of several IMUs, leaving out the ones that disagree.
+///
+/// Each IMU is only compared against its own previous reading, so they don't need to agree on
+/// where zero is.
+pub struct ImuFusion<I: Imu> {
+    imus: Vec<I>,
+    /// How far each IMU's zero is from the first one read at calibration.
+    offsets: Vec<Option<f64>>,
+    last_headings: Vec<Option<f64>>,
+    last_delta: f64,
+    working: usize,
+}
+
+impl<I: Imu> ImuFusion<I> {
+    pub fn new(imus: Vec<I>) -> Self {
+        let count = imus.len();
+
+        Self {
+            imus,
+            offsets: vec![None; count],
+            last_headings: vec![None; count],
+            last_delta: 0.0,
+            working: count,
+        }
+    }
+
+    pub async fn calibrate(&mut self) {
+        for imu in self.imus.iter_mut() {
+            imu.calibrate().await;
+        }
+
+        self.last_headings = self.read();
+        self.working = self.last_headings.iter().flatten().count();
+
+        let reference = self.last_headings.iter().flatten().next().copied();
+        self.offsets = self
+            .last_headings
+            .iter()
+            .map(|heading| Some(angle_difference((*heading)?, reference?)))
+            .collect();
+    }
+
+    fn read(&self) -> Vec<Option<f64>> {
+        self.imus
+            .iter()
+            .map(|imu| imu.heading().map(|heading| heading.get::<radian>()))
+            .collect()
+    }
+
+    /// Circular mean of the current readings, moved onto the zero of the first IMU read at
+    /// calibration. `None` if no calibrated IMU can be read.
+    pub fn heading(&self) -> Option<f64> {
+        let readings: Vec<f64> = self
+            .read()
+            .into_iter()
+            .zip(self.offsets.iter())
+            .filter_map(|(heading, offset)| Some(heading? - (*offset)?))
+            .collect();
+
+        if readings.is_empty() {
+            return None;
+        }
+
+        let (sin, cos) = readings.iter().fold((0.0, 0.0), |(sin, cos), heading| {
+            (sin + heading.sin(), cos + heading.cos())
+        });
+
+        Some(sin.atan2(cos))
+    }
+
+    /// Reads the IMUs and returns their fused heading change since the last update, `None` if
+    /// none of them can be used.
+    ///
+    /// `wheel_delta` is the heading change the drive wheels saw, if they can tell. It settles a
+    /// disagreement between two IMUs, falling back to the last fused change.
+    pub fn update(&mut self, wheel_delta: Option<f64>) -> Option<f64> {
+        let headings = self.read();
+
+        let mut deltas: Vec<f64> = self
+            .last_headings
+            .iter()
+            .zip(headings.iter())
+            .filter_map(|(last, current)| Some(angle_difference((*current)?, (*last)?)))
+            .collect();
+        self.last_headings = headings;
+
+        deltas.sort_by(f64::total_cmp);
+
+        let reference = match deltas.as_slice() {
+            [] => {
+                self.working = 0;
+                return None;
+            }
+            [delta] => *delta,
+            [a, b] if (a - b).abs() <= IMU_OUTLIER_THRESHOLD => (a + b) / 2.0,
+            // Can't tell which of the two is wrong from each other
+            [_, _] => wheel_delta.unwrap_or(self.last_delta),
+            _ => deltas[deltas.len() / 2],
+        };
+
+        let inliers: Vec<f64> = deltas
+            .into_iter()
+            .filter(|delta| (delta - reference).abs() <= IMU_OUTLIER_THRESHOLD)
+            .collect();
+
+        self.working = inliers.len();
+
+        if inliers.is_empty() {
+            return None;
+        }
+
+        self.last_delta = inliers.iter().sum::<f64>() / inliers.len() as f64;
+        Some(self.last_delta)
+    }
+
+    /// Health of the last update, assuming the wheels take over when every IMU is out.
+    pub fn health(&self) -> HeadingHealth {
+        match self.working {
+            0 => HeadingHealth::WheelOdometry,
+            working if working == self.imus.len() => HeadingHealth::Healthy,
+            working => HeadingHealth::Degraded {
+                working,
+                total: self.imus.len(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    use uom::si::f64::Angle;
+
+    use super::*;
+    use crate::sim::block_on;
+
+    type Heading = Rc<Cell<Option<f64>>>;
+
+    /// IMU whose heading in radians is set by the test.
+    struct FakeImu(Heading);
+
+    impl Imu for FakeImu {
+        async fn calibrate(&mut self) {}
+
+        fn heading(&self) -> Option<Angle> {
+            self.0.get().map(Angle::new::<radian>)
+        }
+    }
+
+    fn fusion(headings: &[f64]) -> (Vec<Heading>, ImuFusion<FakeImu>) {
+        let cells: Vec<_> = headings
+            .iter()
+            .map(|heading| Rc::new(Cell::new(Some(*heading))))
+            .collect();
+        let mut fusion = ImuFusion::new(cells.iter().map(|cell| FakeImu(cell.clone())).collect());
+        block_on(fusion.calibrate());
+
+        (cells, fusion)
+    }
+
+    fn turn(cells: &[Heading], deltas: &[f64]) {
+        for (cell, delta) in cells.iter().zip(deltas) {
+            cell.set(cell.get().map(|heading| heading + delta));
+        }
+    }
+
+    #[test]
+    fn averages_agreeing_imus() {
+        // Different zeros don't matter, only the change is compared
+        let (cells, mut fusion) = fusion(&[0.0, 1.0]);
+
+        turn(&cells, &[0.10, 0.12]);
+        assert!((fusion.update(Some(0.0)).unwrap() - 0.11).abs() < 1e-9);
+        assert_eq!(fusion.health(), HeadingHealth::Healthy);
+    }
+
+    #[test]
+    fn heading_uses_first_imu_zero() {
+        let (cells, mut fusion) = fusion(&[0.3, 1.0, -2.0]);
+        assert!((fusion.heading().unwrap() - 0.3).abs() < 1e-9);
+
+        turn(&cells, &[0.1, 0.1, 0.1]);
+        fusion.update(None);
+        assert!((fusion.heading().unwrap() - 0.4).abs() < 1e-9);
+
+        // The others stay on the first IMU's zero without it
+        cells[0].set(None);
+        assert!((fusion.heading().unwrap() - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_outlier_by_median() {
+        let (cells, mut fusion) = fusion(&[0.0, 0.0, 0.0]);
+
+        turn(&cells, &[0.1, 0.1, 1.0]);
+        assert!((fusion.update(None).unwrap() - 0.1).abs() < 1e-9);
+        assert_eq!(
+            fusion.health(),
+            HeadingHealth::Degraded {
+                working: 2,
+                total: 3
+            }
+        );
+    }
+
+    #[test]
+    fn wheels_settle_two_imus() {
+        let (cells, mut fusion) = fusion(&[0.0, 0.0]);
+
+        turn(&cells, &[0.1, 0.5]);
+        assert!((fusion.update(Some(0.12)).unwrap() - 0.1).abs() < 1e-9);
+
+        // Nothing agrees with the wheels
+        turn(&cells, &[0.1, 0.5]);
+        assert_eq!(fusion.update(Some(-0.3)), None);
+        assert_eq!(fusion.health(), HeadingHealth::WheelOdometry);
+    }
+
+    #[test]
+    fn skips_unplugged_imus() {
+        let (cells, mut fusion) = fusion(&[0.0, 0.0]);
+
+        cells[0].set(None);
+        turn(&cells, &[0.0, 0.2]);
+        assert!((fusion.update(None).unwrap() - 0.2).abs() < 1e-9);
+        assert_eq!(
+            fusion.health(),
+            HeadingHealth::Degraded {
+                working: 1,
+                total: 2
+            }
+        );
+
+        cells[1].set(None);
+        assert_eq!(fusion.update(None), None);
+        assert_eq!(fusion.health(), HeadingHealth::WheelOdometry);
+    }
+}
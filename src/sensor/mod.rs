@@ -1 +1,2 @@
+pub mod imu;
 pub mod rotary;
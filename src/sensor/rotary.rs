@@ -1,17 +1,33 @@
 use alloc::sync::Arc;
 
 use uom::si::{f64::Length, length::meter};
-use vexide::core::sync::Mutex;
+#[cfg(feature = "vexide")]
+use vexide::prelude::{AdiEncoder, RotationSensor};
 
-use crate::actuator::motor_group::MotorGroup;
+use crate::hal::{sync::Mutex, DriveMotor};
 
 pub trait RotarySensor {
-    async fn pos(&self) -> f64;
+    /// Angle the sensor turned since it started, in radians. `None` if it can't be read.
+    async fn pos(&self) -> Option<f64>;
 }
 
-impl RotarySensor for Arc<Mutex<MotorGroup>> {
-    async fn pos(&self) -> f64 {
-        self.lock().await.position()
+impl<M: DriveMotor> RotarySensor for Arc<Mutex<M>> {
+    async fn pos(&self) -> Option<f64> {
+        Some(self.lock().await.position())
+    }
+}
+
+#[cfg(feature = "vexide")]
+impl RotarySensor for RotationSensor {
+    async fn pos(&self) -> Option<f64> {
+        self.position().ok().map(|position| position.as_radians())
+    }
+}
+
+#[cfg(feature = "vexide")]
+impl RotarySensor for AdiEncoder {
+    async fn pos(&self) -> Option<f64> {
+        self.position().ok().map(|position| position.as_radians())
     }
 }
 
@@ -19,7 +35,7 @@ pub struct TrackingWheel<T: RotarySensor> {
     sensor: T,
     diameter: Length,
     gearing: Option<f64>,
-    last_length: f64,
+    last_length: Option<f64>,
 }
 
 impl<T: RotarySensor> TrackingWheel<T> {
@@ -28,20 +44,34 @@ impl<T: RotarySensor> TrackingWheel<T> {
             sensor,
             diameter,
             gearing,
-            last_length: 0.0,
+            last_length: None,
         }
     }
 
-    pub async fn travel(&self) -> f64 {
-        self.sensor.pos().await * self.diameter.get::<meter>() * self.gearing.unwrap_or(1.0)
+    /// Distance the wheel rolled since it started, in meters.
+    pub async fn travel(&self) -> Option<f64> {
+        Some(
+            self.sensor.pos().await? * self.diameter.get::<meter>() / 2.0
+                * self.gearing.unwrap_or(1.0),
+        )
+    }
+
+    /// Takes the current reading as where the wheel starts, since the sensor may not start at
+    /// zero. Otherwise the first successful reading is used.
+    pub async fn reset(&mut self) {
+        self.last_length = self.travel().await;
     }
 
+    /// Distance the wheel rolled since the last update. A wheel that can't be read counts as not
+    /// moving, and picks up from where it was once it can.
     pub async fn update(&mut self) -> f64 {
-        let current_length = self.travel().await;
+        let Some(current_length) = self.travel().await else {
+            return 0.0;
+        };
 
-        let delta = current_length - self.last_length;
+        let delta = current_length - self.last_length.unwrap_or(current_length);
 
-        self.last_length = current_length;
+        self.last_length = Some(current_length);
 
         delta
     }
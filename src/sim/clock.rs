@@ -0,0 +1,89 @@
+use core::{
+    ops::{Add, Sub},
+    time::Duration,
+};
+
+/// Simulated monotonic clock, only moves when [`advance`] is called.
+///
+/// Running off the brain, [`crate::hal::time::Instant`] is this type, so everything timed with
+/// it (RAMSETE profiles, localization intervals) follows simulated time instead of wall time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Self(now::get())
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now() - *self
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Duration::from_micros(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        Self(self.0 + rhs.as_micros() as u64)
+    }
+}
+
+/// Moves the simulated clock forward.
+pub fn advance(dt: Duration) {
+    now::add(dt.as_micros() as u64);
+}
+
+/// Sleeps on the simulated clock. Nothing else runs in the meantime, so this just moves the
+/// clock forward by `duration`.
+pub async fn sleep(duration: Duration) {
+    advance(duration);
+}
+
+/// Like [`sleep`], moves the clock to `deadline` if it hasn't passed yet.
+pub async fn sleep_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline > now {
+        advance(deadline - now);
+    }
+}
+
+#[cfg(not(test))]
+mod now {
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    static MICROS: AtomicU64 = AtomicU64::new(0);
+
+    pub fn get() -> u64 {
+        MICROS.load(Ordering::Relaxed)
+    }
+
+    pub fn add(micros: u64) {
+        MICROS.fetch_add(micros, Ordering::Relaxed);
+    }
+}
+
+// Tests run in parallel, so each gets its own clock
+#[cfg(test)]
+mod now {
+    use core::cell::Cell;
+
+    std::thread_local! {
+        static MICROS: Cell<u64> = const { Cell::new(0) };
+    }
+
+    pub fn get() -> u64 {
+        MICROS.with(|micros| micros.get())
+    }
+
+    pub fn add(amount: u64) {
+        MICROS.with(|micros| micros.set(micros.get() + amount));
+    }
+}
@@ -0,0 +1,298 @@
+use alloc::sync::Arc;
+use core::cell::RefCell;
+
+use nalgebra::{Rotation2, Vector2};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rand_distr::Normal;
+use uom::si::{
+    angle::radian,
+    f64::{Angle, AngularVelocity, Length},
+    length::meter,
+};
+
+use super::{plant::Side, SharedPlant};
+use crate::{
+    hal::{DriveMotor, Gps, Imu, RangeFinder, Reflectance},
+    localization::{field_map::FieldMap, localization::StateRepresentation},
+    sensor::rotary::RotarySensor,
+    utils::{angle_difference, wrap_angle},
+};
+
+/// Gaussian noise added to simulated readings.
+struct Noise {
+    std: f64,
+    rng: RefCell<SmallRng>,
+}
+
+impl Noise {
+    fn new(std: f64) -> Self {
+        Self {
+            std,
+            rng: RefCell::new(SmallRng::seed_from_u64(0)),
+        }
+    }
+
+    fn apply(&self, value: f64) -> f64 {
+        if self.std > 0.0 {
+            value
+                + self
+                    .rng
+                    .borrow_mut()
+                    .sample(Normal::new(0.0, self.std).unwrap())
+        } else {
+            value
+        }
+    }
+}
+
+/// One side of the simulated drivetrain.
+pub struct SimMotor {
+    plant: SharedPlant,
+    side: Side,
+}
+
+impl SimMotor {
+    pub fn new(plant: SharedPlant, side: Side) -> Self {
+        Self { plant, side }
+    }
+}
+
+impl DriveMotor for SimMotor {
+    fn set_voltage(&mut self, voltage: f64) {
+        self.plant.borrow_mut().set_voltage(self.side, voltage);
+    }
+
+    fn set_velocity(&mut self, velocity: AngularVelocity) {
+        self.plant.borrow_mut().set_velocity(self.side, velocity);
+    }
+
+    fn position(&self) -> f64 {
+        self.plant.borrow().motor_position(self.side)
+    }
+
+    fn velocity(&self) -> Option<AngularVelocity> {
+        Some(self.plant.borrow().motor_velocity(self.side))
+    }
+}
+
+impl RotarySensor for SimMotor {
+    async fn pos(&self) -> Option<f64> {
+        Some(self.position())
+    }
+}
+
+/// Unpowered wheel on the plant that rolls along `direction` in the robot's frame, e.g. a
+/// perpendicular tracking wheel that sees the robot being pushed sideways.
+pub struct SimTrackingWheel {
+    plant: SharedPlant,
+    position: Vector2<f64>,
+    direction: Vector2<f64>,
+    diameter: Length,
+    /// Pose at the last reading and the angle the wheel turned up to it.
+    last: RefCell<(StateRepresentation, f64)>,
+}
+
+impl SimTrackingWheel {
+    pub fn new(
+        plant: SharedPlant,
+        position: Vector2<f64>,
+        direction: Vector2<f64>,
+        diameter: Length,
+    ) -> Self {
+        let pose = plant.borrow().pose();
+
+        Self {
+            plant,
+            position,
+            direction,
+            diameter,
+            last: RefCell::new((pose, 0.0)),
+        }
+    }
+}
+
+impl RotarySensor for SimTrackingWheel {
+    async fn pos(&self) -> Option<f64> {
+        let pose = self.plant.borrow().pose();
+        let (last_pose, angle) = &mut *self.last.borrow_mut();
+
+        let wheel = |pose: &StateRepresentation| pose.xy() + Rotation2::new(pose.z) * self.position;
+        let moved = wheel(&pose) - wheel(last_pose);
+        // Roll along the wheel's direction halfway through the turn
+        let heading = last_pose.z + angle_difference(pose.z, last_pose.z) / 2.0;
+        let rolled = (Rotation2::new(heading).inverse() * moved).dot(&self.direction);
+
+        *angle += rolled / (self.diameter.get::<meter>() / 2.0);
+        *last_pose = pose;
+
+        Some(*angle)
+    }
+}
+
+/// IMU reading the true heading of the plant.
+pub struct SimImu {
+    plant: SharedPlant,
+    noise: Noise,
+    zero: f64,
+    connected: bool,
+}
+
+impl SimImu {
+    pub fn new(plant: SharedPlant, noise: f64) -> Self {
+        Self {
+            plant,
+            noise: Noise::new(noise),
+            zero: 0.0,
+            connected: true,
+        }
+    }
+
+    /// Heading of the plant the IMU reads as zero, like an IMU calibrated while facing it.
+    pub fn with_zero(mut self, zero: f64) -> Self {
+        self.zero = zero;
+        self
+    }
+
+    /// Simulates unplugging the sensor.
+    pub fn set_connected(&mut self, connected: bool) {
+        self.connected = connected;
+    }
+}
+
+impl Imu for SimImu {
+    async fn calibrate(&mut self) {}
+
+    fn heading(&self) -> Option<Angle> {
+        if !self.connected {
+            return None;
+        }
+
+        Some(Angle::new::<radian>(wrap_angle(
+            self.noise.apply(self.plant.borrow().pose().z - self.zero),
+        )))
+    }
+}
+
+/// Distance sensor ray-cast against the field walls and obstacles.
+pub struct SimDistanceSensor {
+    plant: SharedPlant,
+    field: Arc<FieldMap>,
+    sensor_pose: StateRepresentation,
+    max_range: Length,
+    noise: Noise,
+}
+
+impl SimDistanceSensor {
+    pub fn new(
+        plant: SharedPlant,
+        field: Arc<FieldMap>,
+        sensor_pose: StateRepresentation,
+        max_range: Length,
+        noise: f64,
+    ) -> Self {
+        Self {
+            plant,
+            field,
+            sensor_pose,
+            max_range,
+            noise: Noise::new(noise),
+        }
+    }
+}
+
+impl RangeFinder for SimDistanceSensor {
+    fn distance(&self) -> Option<Length> {
+        let pose = self.plant.borrow().pose();
+
+        let origin = Vector2::new(pose.x, pose.y)
+            + Rotation2::new(pose.z) * Vector2::new(self.sensor_pose.x, self.sensor_pose.y);
+        let direction = Rotation2::new(pose.z + self.sensor_pose.z) * Vector2::new(1.0, 0.0);
+
+        let distance = self.field.ray_cast(&origin, &direction)?;
+
+        if distance > self.max_range.get::<meter>() {
+            None
+        } else {
+            Some(Length::new::<meter>(self.noise.apply(distance)))
+        }
+    }
+
+    fn confidence(&self) -> Option<f64> {
+        Some(1.0)
+    }
+
+    fn object_size(&self) -> Option<u32> {
+        // Walls are always large objects
+        self.distance().map(|_| 400)
+    }
+}
+
+/// Line tracker that sees the field tapes.
+pub struct SimLineTracker {
+    plant: SharedPlant,
+    field: Arc<FieldMap>,
+    position: Vector2<f64>,
+    tape_reflectivity: f64,
+    tile_reflectivity: f64,
+}
+
+impl SimLineTracker {
+    pub fn new(plant: SharedPlant, field: Arc<FieldMap>, position: Vector2<f64>) -> Self {
+        Self {
+            plant,
+            field,
+            position,
+            tape_reflectivity: 0.9,
+            tile_reflectivity: 0.05,
+        }
+    }
+}
+
+impl Reflectance for SimLineTracker {
+    fn reflectivity(&self) -> Option<f64> {
+        let pose = self.plant.borrow().pose();
+
+        let sensor_position = Vector2::new(pose.x, pose.y) + Rotation2::new(pose.z) * self.position;
+
+        let on_tape = self
+            .field
+            .nearest_tape(&sensor_position)
+            .is_some_and(|distance| distance < self.field.tape_width().get::<meter>() / 2.0);
+
+        Some(if on_tape {
+            self.tape_reflectivity
+        } else {
+            self.tile_reflectivity
+        })
+    }
+}
+
+/// GPS reporting the true position of the plant.
+pub struct SimGps {
+    plant: SharedPlant,
+    noise: Noise,
+}
+
+impl SimGps {
+    pub fn new(plant: SharedPlant, noise: f64) -> Self {
+        Self {
+            plant,
+            noise: Noise::new(noise),
+        }
+    }
+}
+
+impl Gps for SimGps {
+    fn pose(&self) -> Option<(Vector2<f64>, f64)> {
+        let pose = self.plant.borrow().pose();
+
+        Some((
+            Vector2::new(self.noise.apply(pose.x), self.noise.apply(pose.y)),
+            pose.z,
+        ))
+    }
+
+    fn error(&self) -> Option<f64> {
+        Some(self.noise.std.max(0.01))
+    }
+}
@@ -0,0 +1,126 @@
+//! Simulated backend for the [`crate::hal`] traits.
+//!
+//! A [`plant::TankPlant`] holds the true pose of the robot, and the simulated devices read from
+//! and write to it. Step the plant with the same period as the control loop to run the drivetrain,
+//! localization and motion control off the brain.
+
+use alloc::rc::Rc;
+use core::{
+    cell::RefCell,
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    time::Duration,
+};
+
+use uom::si::{f64::Velocity, velocity::meter_per_second};
+
+use crate::{
+    config::{track_width, wheel_diameter, DRIVE_RATIO},
+    localization::localization::StateRepresentation,
+    motion_control::feedforward::VelocityTarget,
+    state_machine::State,
+};
+
+pub mod clock;
+pub mod devices;
+pub mod odometry;
+pub mod plant;
+pub mod sync;
+
+pub type SharedPlant = Rc<RefCell<plant::TankPlant>>;
+
+/// Plant with the robot's drive geometry and gearing from [`crate::config`].
+pub fn drive_plant() -> SharedPlant {
+    Rc::new(RefCell::new(plant::TankPlant::new(
+        track_width(),
+        wheel_diameter(),
+        DRIVE_RATIO,
+        Velocity::new::<meter_per_second>(1.5),
+    )))
+}
+
+/// Runs a future to completion by polling it in a loop.
+///
+/// Simulated devices never block, so this is enough to drive the async parts of localization
+/// without an executor.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+/// Runs a state against the plant's true pose like
+/// [`Drivetrain::run_velocity`](crate::subsystems::drivetrain::Drivetrain::run_velocity), stepping
+/// the plant and the simulated clock by `period` in between.
+///
+/// Returns how long the state ran for, `None` if it was still running after `limit`.
+pub fn run_velocity<T: Into<VelocityTarget>>(
+    plant: &SharedPlant,
+    state: &mut impl State<StateRepresentation, (T, T)>,
+    period: Duration,
+    limit: Duration,
+) -> Option<Duration> {
+    state.init();
+
+    let mut elapsed = Duration::ZERO;
+    while elapsed <= limit {
+        let pose = plant.borrow().pose();
+        let Some((left, right)) = state.update(&pose) else {
+            return Some(elapsed);
+        };
+
+        let mut plant = plant.borrow_mut();
+        plant.set_velocity(plant::Side::Left, left.into().velocity);
+        plant.set_velocity(plant::Side::Right, right.into().velocity);
+        plant.step(period);
+        clock::advance(period);
+        elapsed += period;
+    }
+
+    None
+}
+
+/// Like [`run_velocity`], for states that output voltages like
+/// [`Drivetrain::run`](crate::subsystems::drivetrain::Drivetrain::run).
+pub fn run_voltage(
+    plant: &SharedPlant,
+    state: &mut impl State<StateRepresentation, (f64, f64)>,
+    period: Duration,
+    limit: Duration,
+) -> Option<Duration> {
+    state.init();
+
+    let mut elapsed = Duration::ZERO;
+    while elapsed <= limit {
+        let pose = plant.borrow().pose();
+        let Some((left, right)) = state.update(&pose) else {
+            return Some(elapsed);
+        };
+
+        let mut plant = plant.borrow_mut();
+        plant.set_voltage(plant::Side::Left, left);
+        plant.set_voltage(plant::Side::Right, right);
+        plant.step(period);
+        clock::advance(period);
+        elapsed += period;
+    }
+
+    None
+}
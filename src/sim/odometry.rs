@@ -0,0 +1,82 @@
+use nalgebra::{Rotation2, Vector2};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rand_distr::Normal;
+
+use super::SharedPlant;
+use crate::{
+    localization::{localization::StateRepresentation, predict::MotionModel},
+    utils::angle_difference,
+};
+
+/// Motion model reading the true motion of the plant, with optional noise on the samples.
+///
+/// Lets the localization filters be tested without modelling any wheels or IMU.
+pub struct SimOdometry {
+    plant: SharedPlant,
+    last_pose: StateRepresentation,
+    delta: StateRepresentation,
+    rng: SmallRng,
+    drive_noise: f64,
+    angle_noise: f64,
+}
+
+impl SimOdometry {
+    pub fn new(plant: SharedPlant, drive_noise: f64, angle_noise: f64) -> Self {
+        let last_pose = plant.borrow().pose();
+
+        Self {
+            plant,
+            last_pose,
+            delta: StateRepresentation::zeros(),
+            rng: SmallRng::seed_from_u64(0),
+            drive_noise,
+            angle_noise,
+        }
+    }
+}
+
+impl MotionModel for SimOdometry {
+    async fn update(&mut self) {
+        let pose = self.plant.borrow().pose();
+
+        self.delta = StateRepresentation::new(
+            pose.x - self.last_pose.x,
+            pose.y - self.last_pose.y,
+            angle_difference(pose.z, self.last_pose.z),
+        );
+        self.last_pose = pose;
+    }
+
+    fn sample(&mut self) -> StateRepresentation {
+        let scale = self.rng.sample(Normal::new(1.0, self.drive_noise).unwrap());
+        let direction = self.rng.sample(Normal::new(0.0, self.angle_noise).unwrap());
+        let displacement = Rotation2::new(direction) * self.mean_displacement() * scale;
+
+        StateRepresentation::new(displacement.x, displacement.y, self.delta.z)
+    }
+
+    fn mean_displacement(&self) -> Vector2<f64> {
+        self.delta.xy()
+    }
+
+    fn displacement(&self) -> f64 {
+        // Along the heading halfway through the turn
+        (Rotation2::new(self.last_pose.z - self.delta.z / 2.0).inverse() * self.delta.xy()).x
+    }
+
+    fn heading(&self) -> Rotation2<f64> {
+        Rotation2::new(self.last_pose.z)
+    }
+
+    fn heading_delta(&self) -> f64 {
+        self.delta.z
+    }
+
+    fn drive_noise(&self) -> f64 {
+        self.drive_noise
+    }
+
+    fn angle_noise(&self) -> f64 {
+        self.angle_noise
+    }
+}
@@ -0,0 +1,153 @@
+use core::time::Duration;
+
+use uom::{
+    num_traits::Float,
+    si::{
+        angular_velocity::radian_per_second,
+        f64::{AngularVelocity, Length, Velocity},
+        length::meter,
+        velocity::meter_per_second,
+    },
+};
+
+use crate::{localization::localization::StateRepresentation, utils::wrap_angle};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    fn index(self) -> usize {
+        match self {
+            Side::Left => 0,
+            Side::Right => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum WheelCommand {
+    Voltage(f64),
+    /// Wheel surface speed in m/s
+    Velocity(f64),
+}
+
+/// Kinematic model of a tank drive.
+///
+/// Each side tracks its commanded speed through a first order lag, and the chassis integrates the
+/// resulting wheel speeds along an arc. There is no wheel slip, so the simulated odometry is
+/// exact and any error in the estimate comes from the estimator itself.
+pub struct TankPlant {
+    pose: StateRepresentation,
+    wheel_velocity: [f64; 2],
+    wheel_travel: [f64; 2],
+    command: [WheelCommand; 2],
+    track_width: f64,
+    wheel_radius: f64,
+    drive_ratio: f64,
+    max_speed: f64,
+    time_constant: f64,
+}
+
+impl TankPlant {
+    pub fn new(
+        track_width: Length,
+        wheel_diameter: Length,
+        drive_ratio: f64,
+        max_speed: Velocity,
+    ) -> Self {
+        Self {
+            pose: StateRepresentation::new(0.0, 0.0, 0.0),
+            wheel_velocity: [0.0; 2],
+            wheel_travel: [0.0; 2],
+            command: [WheelCommand::Voltage(0.0); 2],
+            track_width: track_width.get::<meter>(),
+            wheel_radius: wheel_diameter.get::<meter>() / 2.0,
+            drive_ratio,
+            max_speed: max_speed.get::<meter_per_second>(),
+            time_constant: 0.1,
+        }
+    }
+
+    /// Time for a side to reach ~63% of a new commanded speed. Zero makes the drive respond
+    /// instantly.
+    pub fn with_time_constant(mut self, time_constant: Duration) -> Self {
+        self.time_constant = time_constant.as_secs_f64();
+        self
+    }
+
+    pub fn pose(&self) -> StateRepresentation {
+        self.pose
+    }
+
+    pub fn set_pose(&mut self, pose: StateRepresentation) {
+        self.pose = pose;
+    }
+
+    pub fn set_voltage(&mut self, side: Side, voltage: f64) {
+        self.command[side.index()] = WheelCommand::Voltage(voltage);
+    }
+
+    /// Commands the motor shaft velocity of one side, like [`crate::hal::DriveMotor`].
+    pub fn set_velocity(&mut self, side: Side, velocity: AngularVelocity) {
+        self.command[side.index()] = WheelCommand::Velocity(
+            velocity.get::<radian_per_second>() * self.drive_ratio * self.wheel_radius,
+        );
+    }
+
+    /// Motor shaft position of one side in radians.
+    pub fn motor_position(&self, side: Side) -> f64 {
+        self.wheel_travel[side.index()] / self.wheel_radius / self.drive_ratio
+    }
+
+    /// Motor shaft velocity of one side.
+    pub fn motor_velocity(&self, side: Side) -> AngularVelocity {
+        AngularVelocity::new::<radian_per_second>(
+            self.wheel_velocity[side.index()] / self.wheel_radius / self.drive_ratio,
+        )
+    }
+
+    pub fn step(&mut self, dt: Duration) {
+        let dt = dt.as_secs_f64();
+
+        let response = if self.time_constant > 0.0 {
+            1.0 - (-dt / self.time_constant).exp()
+        } else {
+            1.0
+        };
+
+        for (velocity, command) in self.wheel_velocity.iter_mut().zip(self.command.iter()) {
+            let target = match *command {
+                WheelCommand::Voltage(voltage) => {
+                    voltage.clamp(-12.0, 12.0) / 12.0 * self.max_speed
+                }
+                WheelCommand::Velocity(speed) => speed.clamp(-self.max_speed, self.max_speed),
+            };
+
+            *velocity += (target - *velocity) * response;
+        }
+
+        let left = self.wheel_velocity[0] * dt;
+        let right = self.wheel_velocity[1] * dt;
+
+        self.wheel_travel[0] += left;
+        self.wheel_travel[1] += right;
+
+        let distance = (left + right) / 2.0;
+        let delta_heading = (right - left) / self.track_width;
+
+        // Move along the chord of the arc driven this step
+        let chord = if delta_heading.abs() < 1e-9 {
+            distance
+        } else {
+            2.0 * distance / delta_heading * (delta_heading / 2.0).sin()
+        };
+        let chord_heading = self.pose.z + delta_heading / 2.0;
+
+        self.pose.x += chord * chord_heading.cos();
+        self.pose.y += chord * chord_heading.sin();
+        self.pose.z = wrap_angle(self.pose.z + delta_heading);
+    }
+}
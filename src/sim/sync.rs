@@ -0,0 +1,36 @@
+use core::{
+    cell::{RefCell, RefMut},
+    ops::{Deref, DerefMut},
+};
+
+/// Stands in for vexide's async mutex off the brain.
+///
+/// Simulated code runs on one thread under [`super::block_on`], so a lock is never contended and
+/// is just a borrow of the value.
+pub struct Mutex<T>(RefCell<T>);
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self(RefCell::new(value))
+    }
+
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        MutexGuard(self.0.borrow_mut())
+    }
+}
+
+pub struct MutexGuard<'a, T>(RefMut<'a, T>);
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
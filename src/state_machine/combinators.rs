@@ -0,0 +1,367 @@
+//! Adapters returned by the combinator methods of [`State`].
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{marker::PhantomData, time::Duration};
+
+use super::State;
+use crate::hal::time::Instant;
+
+pub struct Timeout<S> {
+    state: S,
+    timeout: Duration,
+    start: Instant,
+}
+
+impl<S> Timeout<S> {
+    pub fn new(state: S, timeout: Duration) -> Self {
+        Self {
+            state,
+            timeout,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl<I, O, S: State<I, O>> State<I, O> for Timeout<S> {
+    fn init(&mut self) {
+        self.start = Instant::now();
+        self.state.init();
+    }
+
+    fn update(&mut self, i: &I) -> Option<O> {
+        if self.start.elapsed() >= self.timeout {
+            return None;
+        }
+
+        self.state.update(i)
+    }
+
+    fn name(&self) -> &'static str {
+        self.state.name()
+    }
+}
+
+pub struct Until<S, F> {
+    state: S,
+    predicate: F,
+}
+
+impl<S, F> Until<S, F> {
+    pub fn new(state: S, predicate: F) -> Self {
+        Self { state, predicate }
+    }
+}
+
+impl<I, O, S: State<I, O>, F: FnMut(&I) -> bool> State<I, O> for Until<S, F> {
+    fn init(&mut self) {
+        self.state.init();
+    }
+
+    fn update(&mut self, i: &I) -> Option<O> {
+        if (self.predicate)(i) {
+            return None;
+        }
+
+        self.state.update(i)
+    }
+
+    fn name(&self) -> &'static str {
+        self.state.name()
+    }
+}
+
+/// Runs `first`, then `second` from the update `first` ends on.
+pub struct Then<A, B> {
+    first: A,
+    second: B,
+    on_second: bool,
+}
+
+impl<A, B> Then<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self {
+            first,
+            second,
+            on_second: false,
+        }
+    }
+}
+
+impl<I, O, A: State<I, O>, B: State<I, O>> State<I, O> for Then<A, B> {
+    fn init(&mut self) {
+        self.on_second = false;
+        self.first.init();
+    }
+
+    fn update(&mut self, i: &I) -> Option<O> {
+        if !self.on_second {
+            if let Some(output) = self.first.update(i) {
+                return Some(output);
+            }
+
+            self.on_second = true;
+            self.second.init();
+        }
+
+        self.second.update(i)
+    }
+
+    fn name(&self) -> &'static str {
+        if self.on_second {
+            self.second.name()
+        } else {
+            self.first.name()
+        }
+    }
+}
+
+/// Runs states one after the other, for when the list is only known at runtime.
+pub struct Sequence<I, O> {
+    states: Vec<Box<dyn State<I, O>>>,
+    current: usize,
+}
+
+impl<I, O> Sequence<I, O> {
+    pub fn new(states: Vec<Box<dyn State<I, O>>>) -> Self {
+        Self { states, current: 0 }
+    }
+}
+
+impl<I, O> State<I, O> for Sequence<I, O> {
+    fn init(&mut self) {
+        self.current = 0;
+
+        if let Some(state) = self.states.first_mut() {
+            state.init();
+        }
+    }
+
+    fn update(&mut self, i: &I) -> Option<O> {
+        loop {
+            let state = self.states.get_mut(self.current)?;
+
+            if let Some(output) = state.update(i) {
+                return Some(output);
+            }
+
+            self.current += 1;
+            if let Some(next) = self.states.get_mut(self.current) {
+                next.init();
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        self.states
+            .get(self.current)
+            .map_or("Sequence", |state| state.name())
+    }
+}
+
+pub struct Race<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> Race<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<I, O, P, A: State<I, O>, B: State<I, P>> State<I, (O, P)> for Race<A, B> {
+    fn init(&mut self) {
+        self.first.init();
+        self.second.init();
+    }
+
+    fn update(&mut self, i: &I) -> Option<(O, P)> {
+        // Both always update, so neither falls behind the other
+        let first = self.first.update(i);
+        let second = self.second.update(i);
+
+        Some((first?, second?))
+    }
+
+    fn name(&self) -> &'static str {
+        self.first.name()
+    }
+}
+
+pub struct MapOutput<S, F, O> {
+    state: S,
+    f: F,
+    _output: PhantomData<fn() -> O>,
+}
+
+impl<S, F, O> MapOutput<S, F, O> {
+    pub fn new(state: S, f: F) -> Self {
+        Self {
+            state,
+            f,
+            _output: PhantomData,
+        }
+    }
+}
+
+impl<I, O, P, S: State<I, O>, F: FnMut(O) -> P> State<I, P> for MapOutput<S, F, O> {
+    fn init(&mut self) {
+        self.state.init();
+    }
+
+    fn update(&mut self, i: &I) -> Option<P> {
+        self.state.update(i).map(&mut self.f)
+    }
+
+    fn name(&self) -> &'static str {
+        self.state.name()
+    }
+}
+
+pub struct WithInitHook<S, F> {
+    state: S,
+    hook: F,
+}
+
+impl<S, F> WithInitHook<S, F> {
+    pub fn new(state: S, hook: F) -> Self {
+        Self { state, hook }
+    }
+}
+
+impl<I, O, S: State<I, O>, F: FnMut()> State<I, O> for WithInitHook<S, F> {
+    fn init(&mut self) {
+        (self.hook)();
+        self.state.init();
+    }
+
+    fn update(&mut self, i: &I) -> Option<O> {
+        self.state.update(i)
+    }
+
+    fn name(&self) -> &'static str {
+        self.state.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::sim::clock;
+
+    /// Outputs `value` for `ticks` updates.
+    struct Constant {
+        value: i32,
+        ticks: usize,
+        remaining: usize,
+    }
+
+    fn constant(value: i32, ticks: usize) -> Constant {
+        Constant {
+            value,
+            ticks,
+            remaining: ticks,
+        }
+    }
+
+    impl State<i32, i32> for Constant {
+        fn init(&mut self) {
+            self.remaining = self.ticks;
+        }
+
+        fn update(&mut self, _: &i32) -> Option<i32> {
+            self.remaining = self.remaining.checked_sub(1)?;
+            Some(self.value)
+        }
+    }
+
+    fn run<O>(mut state: impl State<i32, O>, inputs: impl IntoIterator<Item = i32>) -> Vec<O> {
+        state.init();
+        inputs.into_iter().map_while(|i| state.update(&i)).collect()
+    }
+
+    #[test]
+    fn times_out() {
+        let mut state = constant(1, 100).timeout(Duration::from_millis(30));
+        state.init();
+
+        let mut outputs = 0;
+        while state.update(&0).is_some() {
+            outputs += 1;
+            clock::advance(Duration::from_millis(10));
+        }
+        assert_eq!(outputs, 3);
+
+        // Restarting restarts the timer
+        state.init();
+        assert!(state.update(&0).is_some());
+    }
+
+    #[test]
+    fn ends_on_predicate() {
+        let outputs = run(constant(1, 100).until(|i| *i >= 3), 0..10);
+        assert_eq!(outputs, vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn chains_states() {
+        assert_eq!(
+            run(constant(1, 2).then(constant(2, 2)), 0..10),
+            vec![1, 1, 2, 2]
+        );
+
+        let sequence = Sequence::new(vec![
+            Box::new(constant(1, 1)) as Box<dyn State<i32, i32>>,
+            Box::new(constant(2, 0)),
+            Box::new(constant(3, 2)),
+        ]);
+        assert_eq!(run(sequence, 0..10), vec![1, 3, 3]);
+    }
+
+    #[test]
+    fn races_states() {
+        let race = constant(1, 2).race(constant(2, 5));
+        assert_eq!(run(race, 0..10), vec![(1, 2), (1, 2)]);
+
+        let race = constant(1, 5).race(constant(2, 3)).map_output(|(a, _)| a);
+        assert_eq!(run(race, 0..10), vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn maps_output() {
+        let outputs = run(constant(2, 2).map_output(|value| value as f64 * 1.5), 0..10);
+        assert_eq!(outputs, vec![3.0, 3.0]);
+    }
+
+    #[test]
+    fn names_current_state() {
+        let mut state = constant(1, 1)
+            .timeout(Duration::from_secs(1))
+            .then(constant(2, 1).map_output(|value| value * 2));
+        assert_eq!(state.name(), "Constant");
+
+        state.init();
+        state.update(&0);
+        state.update(&0);
+        assert_eq!(state.name(), "Constant");
+        assert_eq!(Sequence::<i32, i32>::new(Vec::new()).name(), "Sequence");
+    }
+
+    #[test]
+    fn calls_init_hook() {
+        let calls = Rc::new(Cell::new(0));
+        let hook_calls = calls.clone();
+        let mut state = constant(1, 1)
+            .with_init_hook(move || hook_calls.set(hook_calls.get() + 1))
+            .then(constant(2, 1));
+
+        state.init();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(state.update(&0), Some(1));
+        assert_eq!(state.update(&0), Some(2));
+        assert_eq!(calls.get(), 1);
+    }
+}
@@ -1,4 +1,81 @@
+use alloc::boxed::Box;
+use core::time::Duration;
+
+use combinators::{MapOutput, Race, Then, Timeout, Until, WithInitHook};
+
+pub mod combinators;
+pub mod runner;
+
 pub trait State<I, O> {
     fn init(&mut self) {}
     fn update(&mut self, i: &I) -> Option<O>;
+
+    /// Name of the state for logging and status, the type's name by default.
+    fn name(&self) -> &'static str {
+        let name = core::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    /// Ends the state once `timeout` has passed since it started.
+    fn timeout(self, timeout: Duration) -> Timeout<Self>
+    where
+        Self: Sized,
+    {
+        Timeout::new(self, timeout)
+    }
+
+    /// Ends the state as soon as `predicate` holds for the input.
+    fn until<F: FnMut(&I) -> bool>(self, predicate: F) -> Until<Self, F>
+    where
+        Self: Sized,
+    {
+        Until::new(self, predicate)
+    }
+
+    /// Runs `next` once this state ends.
+    fn then<S: State<I, O>>(self, next: S) -> Then<Self, S>
+    where
+        Self: Sized,
+    {
+        Then::new(self, next)
+    }
+
+    /// Runs both states together, outputting both outputs, until either ends.
+    fn race<P, S: State<I, P>>(self, other: S) -> Race<Self, S>
+    where
+        Self: Sized,
+    {
+        Race::new(self, other)
+    }
+
+    fn map_output<P, F: FnMut(O) -> P>(self, f: F) -> MapOutput<Self, F, O>
+    where
+        Self: Sized,
+    {
+        MapOutput::new(self, f)
+    }
+
+    /// Calls `hook` every time the state starts, before its own `init`.
+    fn with_init_hook<F: FnMut()>(self, hook: F) -> WithInitHook<Self, F>
+    where
+        Self: Sized,
+    {
+        WithInitHook::new(self, hook)
+    }
+}
+
+impl<I, O, S: State<I, O> + ?Sized> State<I, O> for Box<S> {
+    fn init(&mut self) {
+        (**self).init();
+    }
+
+    fn update(&mut self, i: &I) -> Option<O> {
+        (**self).update(i)
+    }
+
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
 }
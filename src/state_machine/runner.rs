@@ -0,0 +1,379 @@
+//! Runs the states of one subsystem, taking requests from any number of tasks.
+//!
+//! A [`SubsystemRunner`] owns a [`Subsystem`] and updates whichever state is current every tick.
+//! [`SubsystemHandle`]s queue new states or cancel the current one, so driver control and
+//! autonomous code can command a subsystem without holding on to it for the whole match. When no
+//! requested state is running, the runner falls back to its default state, if it has one.
+
+use alloc::{boxed::Box, collections::VecDeque, rc::Rc};
+use core::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
+use super::State;
+use crate::hal::time::{sleep, sleep_until, Instant};
+
+/// How often the runner updates the current state.
+pub const PERIOD: Duration = Duration::from_millis(10);
+
+/// Hardware a [`SubsystemRunner`] runs states on.
+pub trait Subsystem {
+    /// What the states see each update.
+    type Input;
+    /// What the states command each update.
+    type Output;
+
+    async fn read(&mut self) -> Self::Input;
+    async fn write(&mut self, output: Self::Output);
+
+    /// Called when no state is running, e.g. to stop the motors.
+    async fn idle(&mut self) {}
+}
+
+impl<S: Subsystem> Subsystem for &mut S {
+    type Input = S::Input;
+    type Output = S::Output;
+
+    async fn read(&mut self) -> S::Input {
+        (**self).read().await
+    }
+
+    async fn write(&mut self, output: S::Output) {
+        (**self).write(output).await;
+    }
+
+    async fn idle(&mut self) {
+        (**self).idle().await;
+    }
+}
+
+/// Identifies a state passed to [`SubsystemHandle::run`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RequestId(u32);
+
+enum Command<I, O> {
+    Run(RequestId, Box<dyn State<I, O>>),
+    Cancel,
+}
+
+struct Shared<I, O> {
+    commands: RefCell<VecDeque<Command<I, O>>>,
+    next_id: Cell<u32>,
+    /// Requests end in the order they were made, so every request up to this one is done.
+    ended_through: Cell<u32>,
+    current_name: Cell<Option<&'static str>>,
+}
+
+/// Sends requests to a [`SubsystemRunner`]. Cheap to clone, every clone talks to the same runner.
+pub struct SubsystemHandle<I, O> {
+    shared: Rc<Shared<I, O>>,
+}
+
+impl<I, O> Clone for SubsystemHandle<I, O> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<I, O> SubsystemHandle<I, O> {
+    /// Replaces the current state with `state` on the runner's next tick.
+    pub fn run(&self, state: impl State<I, O> + 'static) -> RequestId {
+        let id = self.shared.next_id.get();
+        self.shared.next_id.set(id + 1);
+
+        let id = RequestId(id);
+        self.shared
+            .commands
+            .borrow_mut()
+            .push_back(Command::Run(id, Box::new(state)));
+        id
+    }
+
+    /// Stops the current state on the runner's next tick, the default state takes over.
+    pub fn cancel(&self) {
+        self.shared.commands.borrow_mut().push_back(Command::Cancel);
+    }
+
+    /// Whether the request has finished, been cancelled or been replaced.
+    pub fn is_done(&self, request: RequestId) -> bool {
+        request.0 <= self.shared.ended_through.get()
+    }
+
+    /// Name of the running state, `None` if the subsystem is idle.
+    pub fn current_state(&self) -> Option<&'static str> {
+        self.shared.current_name.get()
+    }
+
+    pub async fn wait(&self, request: RequestId) {
+        while !self.is_done(request) {
+            sleep(PERIOD).await;
+        }
+    }
+}
+
+enum Running<I, O> {
+    Idle,
+    Default,
+    Request(RequestId, Box<dyn State<I, O>>),
+}
+
+pub struct SubsystemRunner<S: Subsystem> {
+    subsystem: S,
+    running: Running<S::Input, S::Output>,
+    default: Option<Box<dyn State<S::Input, S::Output>>>,
+    shared: Rc<Shared<S::Input, S::Output>>,
+}
+
+impl<S: Subsystem> SubsystemRunner<S> {
+    pub fn new(subsystem: S) -> Self {
+        Self {
+            subsystem,
+            running: Running::Idle,
+            default: None,
+            shared: Rc::new(Shared {
+                commands: RefCell::new(VecDeque::new()),
+                next_id: Cell::new(1),
+                ended_through: Cell::new(0),
+                current_name: Cell::new(None),
+            }),
+        }
+    }
+
+    /// State to run whenever nothing else is, restarted every time it takes over.
+    pub fn with_default(mut self, state: impl State<S::Input, S::Output> + 'static) -> Self {
+        self.default = Some(Box::new(state));
+        self
+    }
+
+    pub fn handle(&self) -> SubsystemHandle<S::Input, S::Output> {
+        SubsystemHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Marks the current request as done and makes `next` current.
+    fn replace(&mut self, next: Running<S::Input, S::Output>) {
+        if let Running::Request(id, _) = self.running {
+            self.shared.ended_through.set(id.0);
+        }
+
+        self.running = next;
+
+        match &mut self.running {
+            Running::Request(_, state) => state.init(),
+            Running::Default => {
+                if let Some(state) = &mut self.default {
+                    state.init();
+                }
+            }
+            Running::Idle => {}
+        }
+    }
+
+    /// Handles the queued requests, then updates the current state once.
+    pub async fn tick(&mut self) {
+        loop {
+            let Some(command) = self.shared.commands.borrow_mut().pop_front() else {
+                break;
+            };
+
+            match command {
+                Command::Run(id, state) => self.replace(Running::Request(id, state)),
+                Command::Cancel => {
+                    if let Running::Request(..) = self.running {
+                        self.replace(Running::Idle);
+                    }
+                }
+            }
+        }
+
+        let input = self.subsystem.read().await;
+
+        // A request that ends hands over to the default state within the same tick, so the
+        // subsystem doesn't stop for a tick in between
+        loop {
+            if let Running::Idle = self.running
+                && self.default.is_some()
+            {
+                self.replace(Running::Default);
+            }
+
+            let state = match &mut self.running {
+                Running::Request(_, state) => state,
+                Running::Default => match &mut self.default {
+                    Some(state) => state,
+                    None => break,
+                },
+                Running::Idle => break,
+            };
+
+            if let Some(output) = state.update(&input) {
+                self.shared.current_name.set(Some(state.name()));
+                self.subsystem.write(output).await;
+                return;
+            }
+
+            // A default state that ends is gone for good
+            if let Running::Default = self.running {
+                self.default = None;
+            }
+            self.replace(Running::Idle);
+        }
+
+        self.shared.current_name.set(None);
+        self.subsystem.idle().await;
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            let start = Instant::now();
+            self.tick().await;
+            sleep_until(start + PERIOD).await;
+        }
+    }
+}
+
+/// Runs `state` on `subsystem` every [`PERIOD`] until it ends.
+pub async fn run_state<S: Subsystem>(
+    subsystem: &mut S,
+    mut state: impl State<S::Input, S::Output>,
+) {
+    state.init();
+
+    loop {
+        let start = Instant::now();
+
+        let input = subsystem.read().await;
+        let Some(output) = state.update(&input) else {
+            return;
+        };
+        subsystem.write(output).await;
+
+        sleep_until(start + PERIOD).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+    use crate::sim::block_on;
+
+    /// Outputs written by the recorder, `None` where it was told to idle.
+    type Outputs = Rc<RefCell<Vec<Option<i32>>>>;
+
+    /// Records what it was told to do.
+    #[derive(Default)]
+    struct Recorder {
+        outputs: Outputs,
+    }
+
+    impl Subsystem for Recorder {
+        type Input = ();
+        type Output = i32;
+
+        async fn read(&mut self) {}
+
+        async fn write(&mut self, output: i32) {
+            self.outputs.borrow_mut().push(Some(output));
+        }
+
+        async fn idle(&mut self) {
+            self.outputs.borrow_mut().push(None);
+        }
+    }
+
+    /// Outputs `value` for `ticks` updates.
+    struct Hold {
+        value: i32,
+        ticks: usize,
+        remaining: usize,
+    }
+
+    fn hold(value: i32, ticks: usize) -> Hold {
+        Hold {
+            value,
+            ticks,
+            remaining: ticks,
+        }
+    }
+
+    impl State<(), i32> for Hold {
+        fn init(&mut self) {
+            self.remaining = self.ticks;
+        }
+
+        fn update(&mut self, _: &()) -> Option<i32> {
+            self.remaining = self.remaining.checked_sub(1)?;
+            Some(self.value)
+        }
+    }
+
+    fn runner() -> (SubsystemRunner<Recorder>, Outputs) {
+        let recorder = Recorder::default();
+        let outputs = recorder.outputs.clone();
+        (SubsystemRunner::new(recorder), outputs)
+    }
+
+    #[test]
+    fn runs_requests_until_done() {
+        let (mut runner, outputs) = runner();
+        let handle = runner.handle();
+
+        let request = handle.run(hold(1, 2));
+        assert_eq!(handle.current_state(), None);
+
+        block_on(runner.tick());
+        assert_eq!(handle.current_state(), Some("Hold"));
+        assert!(!handle.is_done(request));
+
+        block_on(runner.tick());
+        block_on(runner.tick());
+        assert!(handle.is_done(request));
+        assert_eq!(handle.current_state(), None);
+        assert_eq!(*outputs.borrow(), vec![Some(1), Some(1), None]);
+    }
+
+    #[test]
+    fn new_requests_preempt() {
+        let (mut runner, outputs) = runner();
+        let handle = runner.handle();
+
+        let first = handle.run(hold(1, 10));
+        block_on(runner.tick());
+
+        // Another task takes over the subsystem
+        let second = handle.clone().run(hold(2, 10));
+        block_on(runner.tick());
+        assert!(handle.is_done(first));
+        assert!(!handle.is_done(second));
+
+        handle.cancel();
+        block_on(runner.tick());
+        assert!(handle.is_done(second));
+        assert_eq!(*outputs.borrow(), vec![Some(1), Some(2), None]);
+    }
+
+    #[test]
+    fn falls_back_to_default() {
+        let (runner, outputs) = runner();
+        let mut runner = runner.with_default(hold(0, usize::MAX));
+        let handle = runner.handle();
+
+        block_on(runner.tick());
+        handle.run(hold(1, 1));
+        block_on(runner.tick());
+        block_on(runner.tick());
+        assert_eq!(handle.current_state(), Some("Hold"));
+
+        // Cancelling the default state does nothing, it's what runs when nothing else does
+        handle.cancel();
+        block_on(runner.tick());
+
+        assert_eq!(*outputs.borrow(), vec![Some(0), Some(1), Some(0), Some(0)]);
+    }
+}
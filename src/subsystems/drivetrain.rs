@@ -1,160 +1,271 @@
 use alloc::{sync::Arc, vec::Vec};
-use core::{f64::consts::TAU, ops::Add, time::Duration};
-
-use nalgebra::{Matrix3, Vector2};
-use uom::si::f64::{AngularVelocity, Length};
-use vexide::{
-    core::{sync::Mutex, time::Instant},
-    devices::{smart::GpsSensor, PortError},
-    prelude::*,
-};
+use core::{ops::Add, time::Duration};
+
+use nalgebra::{Matrix3, Rotation2, Vector2};
+use uom::si::f64::Length;
 
+#[cfg(feature = "vexide")]
+use crate::actuator::telemetry::Telemetry;
+#[cfg(feature = "vexide")]
+use crate::config::TELEMETRY_ENABLED;
+#[cfg(feature = "vexide")]
+use crate::subsystems::SharedController;
 use crate::{
-    actuator::{motor_group::MotorGroup, telemetry::Telemetry},
     config::{
-        distance_threshold, localization_min_update_distance, ANGLE_NOISE, DRIVE_NOISE, FIELD_MAX,
-        LINE_SENSOR_THRESHOLD, LOCALIZATION_MIN_UPDATE_INTERVAL, NUM_PARTICLES, TELEMETRY_ENABLED,
+        track_width, tracking_wheel_diameter, tracking_wheel_offsets, ANGLE_NOISE, DRIVE_NOISE,
+        LEFT_DRIVE_FEEDFORWARD, RIGHT_DRIVE_FEEDFORWARD,
+    },
+    hal::{
+        sync::Mutex,
+        time::{sleep, sleep_until, Instant},
+        DriveMotor, Imu,
     },
     localization::{
-        localization::{particle_filter::ParticleFilter, Localization, StateRepresentation},
-        predict::tank_pose_tracking::TankPoseTracking,
-        sensor::{distance::WallDistanceSensor, line_tracker::LineTrackerSensor},
+        localization::{Localization, StateRepresentation},
+        predict::{
+            tank_pose_tracking::TankPoseTracking, tracking_wheel_odometry::TrackingWheelOdometry,
+            MotionModel,
+        },
+    },
+    motion_control::feedforward::{FeedforwardGains, VelocityController, VelocityTarget},
+    sensor::{
+        imu::HeadingHealth,
+        rotary::{RotarySensor, TrackingWheel},
+    },
+    state_machine::{
+        runner::{run_state, Subsystem},
+        *,
     },
-    sensor::rotary::TrackingWheel,
-    state_machine::*,
 };
 
-/// Example implementation of a drivetrain subsystem.
-#[allow(dead_code)]
-pub struct Drivetrain {
-    left_motor: Arc<Mutex<MotorGroup>>,
-    right_motor: Arc<Mutex<MotorGroup>>,
-    localization: Arc<Mutex<ParticleFilter<NUM_PARTICLES>>>,
-    _localization_task: Task<()>,
-    telemetry: Telemetry,
+/// Tank drivetrain driven from the pose estimate of its localization.
+///
+/// Only talks to the motors through [`DriveMotor`], so it runs the same against the simulated
+/// backend. Localization is updated by [`Self::run_localization`] on the brain, or by calling
+/// [`Self::update_localization`] in between steps of the plant.
+pub struct Drivetrain<M: DriveMotor + 'static, L: Localization + 'static> {
+    left_motor: Arc<Mutex<M>>,
+    right_motor: Arc<Mutex<M>>,
+    localization: Arc<Mutex<L>>,
+    left_velocity: VelocityController,
+    right_velocity: VelocityController,
 }
 
-impl Drivetrain {
-    pub async fn new(
-        left_motor: Arc<Mutex<MotorGroup>>,
-        right_motor: Arc<Mutex<MotorGroup>>,
-        imu: InertialSensor,
-        tracking_wheel_diameter: Length,
-        drive_ratio: f64,
-        telemetry: Telemetry,
-        distance_sensors: Vec<(DistanceSensor, StateRepresentation)>,
-        line_sensors: Vec<(AdiLineTracker, Vector2<f64>)>,
-        gps: Result<GpsSensor, PortError>,
-    ) -> Self {
-        let localization = Arc::new(Mutex::new(ParticleFilter::new(
-            TankPoseTracking::new(
-                TrackingWheel::new(
-                    left_motor.clone(),
-                    tracking_wheel_diameter,
-                    Option::from(drive_ratio),
-                ),
-                TrackingWheel::new(
-                    right_motor.clone(),
-                    tracking_wheel_diameter,
-                    Option::from(drive_ratio),
-                ),
-                imu,
-                DRIVE_NOISE,
-                ANGLE_NOISE,
-            )
-            .await,
-            LOCALIZATION_MIN_UPDATE_INTERVAL,
-            localization_min_update_distance(),
-        )));
-
-        {
-            let mut loc_lock = localization.lock().await;
-
-            for (sensor, pose) in distance_sensors {
-                loc_lock.add_sensor(WallDistanceSensor::new(sensor, pose));
-            }
+/// Odometry from the drive motor encoders and the IMUs, used as the predictor for every
+/// localization backend.
+pub async fn drive_odometry<M: DriveMotor, I: Imu>(
+    left_motor: &Arc<Mutex<M>>,
+    right_motor: &Arc<Mutex<M>>,
+    imus: Vec<I>,
+    tracking_wheel_diameter: Length,
+    drive_ratio: f64,
+) -> TankPoseTracking<Arc<Mutex<M>>, I> {
+    TankPoseTracking::new(
+        TrackingWheel::new(
+            left_motor.clone(),
+            tracking_wheel_diameter,
+            Option::from(drive_ratio),
+        ),
+        TrackingWheel::new(
+            right_motor.clone(),
+            tracking_wheel_diameter,
+            Option::from(drive_ratio),
+        ),
+        imus,
+        track_width(),
+        DRIVE_NOISE,
+        ANGLE_NOISE,
+    )
+    .await
+}
 
-            for (sensor, pose) in line_sensors {
-                loc_lock.add_sensor(LineTrackerSensor::new(
-                    sensor,
-                    pose,
-                    LINE_SENSOR_THRESHOLD,
-                    distance_threshold(),
-                ));
-            }
+/// Odometry from the dead wheels in [`tracking_wheel_offsets`], with the right parallel wheel
+/// optional.
+pub async fn tracking_wheel_odometry<T: RotarySensor, I: Imu>(
+    left: T,
+    right: Option<T>,
+    perpendicular: T,
+    imus: Vec<I>,
+) -> TrackingWheelOdometry<T, I> {
+    let wheel = |sensor| TrackingWheel::new(sensor, tracking_wheel_diameter(), None);
+
+    let odometry = TrackingWheelOdometry::new(
+        wheel(left),
+        wheel(perpendicular),
+        tracking_wheel_offsets(),
+        imus,
+        DRIVE_NOISE,
+        ANGLE_NOISE,
+    )
+    .await;
+
+    match right {
+        Some(right) => odometry.with_right_wheel(wheel(right)).await,
+        None => odometry,
+    }
+}
 
-            if let Ok(gps_ok) = gps {
-                loc_lock.add_sensor(gps_ok);
-            }
+/// Predictor of the drivetrain, picked at startup by
+/// [`TRACKING_WHEELS`](crate::config::TRACKING_WHEELS).
+pub enum DriveOdometry<D: MotionModel, T: MotionModel> {
+    DriveWheels(D),
+    TrackingWheels(T),
+}
 
-            // loc_lock.add_sensor(DummySensor {
-            //     covariance: 0.5,
-            //     mean: Default::default(),
-            // });
+impl<D: MotionModel, T: MotionModel> MotionModel for DriveOdometry<D, T> {
+    async fn update(&mut self) {
+        match self {
+            Self::DriveWheels(odometry) => odometry.update().await,
+            Self::TrackingWheels(odometry) => odometry.update().await,
+        }
+    }
 
-            loc_lock.init_uniform(
-                &StateRepresentation::new(-FIELD_MAX, -FIELD_MAX, 0.0),
-                &StateRepresentation::new(FIELD_MAX, FIELD_MAX, TAU),
-            );
+    fn sample(&mut self) -> StateRepresentation {
+        match self {
+            Self::DriveWheels(odometry) => odometry.sample(),
+            Self::TrackingWheels(odometry) => odometry.sample(),
         }
+    }
 
-        Self {
-            localization: localization.clone(),
-            telemetry: telemetry.clone(),
-            _localization_task: spawn(async move {
-                loop {
-                    let now = Instant::now();
+    fn mean_displacement(&self) -> Vector2<f64> {
+        match self {
+            Self::DriveWheels(odometry) => odometry.mean_displacement(),
+            Self::TrackingWheels(odometry) => odometry.mean_displacement(),
+        }
+    }
 
-                    {
-                        let mut loc = localization.lock().await;
+    fn displacement(&self) -> f64 {
+        match self {
+            Self::DriveWheels(odometry) => odometry.displacement(),
+            Self::TrackingWheels(odometry) => odometry.displacement(),
+        }
+    }
 
-                        loc.update().await;
+    fn heading(&self) -> Rotation2<f64> {
+        match self {
+            Self::DriveWheels(odometry) => odometry.heading(),
+            Self::TrackingWheels(odometry) => odometry.heading(),
+        }
+    }
 
-                        if TELEMETRY_ENABLED {
-                            telemetry.send_json(loc.get_estimates().to_vec()).await;
-                            telemetry.send("\n".as_bytes()).await;
-                        }
-                    }
+    fn heading_delta(&self) -> f64 {
+        match self {
+            Self::DriveWheels(odometry) => odometry.heading_delta(),
+            Self::TrackingWheels(odometry) => odometry.heading_delta(),
+        }
+    }
 
-                    sleep_until(now.add(Duration::from_millis(10))).await;
-                }
-            }),
+    fn heading_health(&self) -> HeadingHealth {
+        match self {
+            Self::DriveWheels(odometry) => odometry.heading_health(),
+            Self::TrackingWheels(odometry) => odometry.heading_health(),
+        }
+    }
+
+    fn drive_noise(&self) -> f64 {
+        match self {
+            Self::DriveWheels(odometry) => odometry.drive_noise(),
+            Self::TrackingWheels(odometry) => odometry.drive_noise(),
+        }
+    }
+
+    fn angle_noise(&self) -> f64 {
+        match self {
+            Self::DriveWheels(odometry) => odometry.angle_noise(),
+            Self::TrackingWheels(odometry) => odometry.angle_noise(),
+        }
+    }
+}
+
+impl<M: DriveMotor + 'static, L: Localization + 'static> Drivetrain<M, L> {
+    pub fn new(left_motor: Arc<Mutex<M>>, right_motor: Arc<Mutex<M>>, localization: L) -> Self {
+        Self {
             left_motor,
             right_motor,
+            localization: Arc::new(Mutex::new(localization)),
+            left_velocity: VelocityController::new(LEFT_DRIVE_FEEDFORWARD),
+            right_velocity: VelocityController::new(RIGHT_DRIVE_FEEDFORWARD),
         }
     }
 
-    pub async fn init_norm(&mut self, mean: &StateRepresentation, covariance: &Matrix3<f64>) {
-        self.localization.lock().await.init_norm(mean, covariance);
+    /// Runs one localization update.
+    pub async fn update_localization(&self) {
+        self.localization.lock().await.update().await;
     }
 
-    pub async fn run_velocity(
-        &mut self,
-        mut state: impl State<StateRepresentation, (AngularVelocity, AngularVelocity)>,
-    ) {
-        state.init();
-        loop {
-            let position;
-
-            {
-                position = self.localization.lock().await.pose_estimate();
-            }
+    /// Updates the localization every 10ms forever, sending the estimates over `telemetry`. Spawn
+    /// it next to the drivetrain.
+    #[cfg(feature = "vexide")]
+    pub fn run_localization(
+        &self,
+        telemetry: Telemetry,
+    ) -> impl core::future::Future<Output = ()> + use<M, L> {
+        let localization = self.localization.clone();
 
-            if let Some(output) = state.update(&position) {
+        async move {
+            loop {
                 let now = Instant::now();
 
-                self.left_motor.lock().await.set_velocity(output.0);
-                self.right_motor.lock().await.set_velocity(output.1);
+                {
+                    let mut loc = localization.lock().await;
+
+                    loc.update().await;
+
+                    if TELEMETRY_ENABLED {
+                        telemetry.send_json(loc.get_estimates()).await;
+                        telemetry.send("\n".as_bytes()).await;
+                    }
+                }
 
                 sleep_until(now.add(Duration::from_millis(10))).await;
-            } else {
-                return;
             }
         }
     }
 
-    pub async fn run(&mut self, mut state: impl State<StateRepresentation, (f64, f64)>) {
+    /// Tunes the velocity control of each side for [`Self::run_velocity`].
+    pub fn with_feedforward(mut self, left: FeedforwardGains, right: FeedforwardGains) -> Self {
+        self.left_velocity = VelocityController::new(left);
+        self.right_velocity = VelocityController::new(right);
+        self
+    }
+
+    /// See [`Localization::init_norm`], `std_dev` is a square root of the covariance.
+    pub async fn init_norm(&mut self, mean: &StateRepresentation, std_dev: &Matrix3<f64>) {
+        self.localization.lock().await.init_norm(mean, std_dev);
+    }
+
+    /// Waits for the localization to converge, giving up after `timeout`. Returns whether it
+    /// converged.
+    pub async fn wait_for_convergence(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+
+        loop {
+            if self.localization.lock().await.converged() {
+                return true;
+            }
+
+            if start.elapsed() > timeout {
+                return false;
+            }
+
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Whether the localization is still measuring the heading properly.
+    pub async fn heading_health(&self) -> HeadingHealth {
+        self.localization.lock().await.heading_health()
+    }
+
+    /// Runs a state that outputs the motor velocity of each side, turned into voltages by the
+    /// drivetrain's feedforward and velocity feedback instead of the motors' internal PID.
+    pub async fn run_velocity<T: Into<VelocityTarget>>(
+        &mut self,
+        mut state: impl State<StateRepresentation, (T, T)>,
+    ) {
         state.init();
+        self.left_velocity.reset();
+        self.right_velocity.reset();
         loop {
             let position;
 
@@ -165,10 +276,16 @@ impl Drivetrain {
             if let Some(output) = state.update(&position) {
                 let now = Instant::now();
 
-                // println!("updateD, {:?}", now);
-
-                self.left_motor.lock().await.set_voltage(output.0);
-                self.right_motor.lock().await.set_voltage(output.1);
+                {
+                    let mut left_motor = self.left_motor.lock().await;
+                    let voltage = self.left_velocity.update(output.0, left_motor.velocity());
+                    left_motor.set_voltage(voltage);
+                }
+                {
+                    let mut right_motor = self.right_motor.lock().await;
+                    let voltage = self.right_velocity.update(output.1, right_motor.velocity());
+                    right_motor.set_voltage(voltage);
+                }
 
                 sleep_until(now.add(Duration::from_millis(10))).await;
             } else {
@@ -176,23 +293,51 @@ impl Drivetrain {
             }
         }
     }
+
+    pub async fn run(&mut self, state: impl State<StateRepresentation, (f64, f64)>) {
+        run_state(self, state).await;
+    }
+}
+
+/// Voltage control of each side from the pose estimate, see [`Drivetrain::run`].
+impl<M: DriveMotor + 'static, L: Localization + 'static> Subsystem for Drivetrain<M, L> {
+    type Input = StateRepresentation;
+    type Output = (f64, f64);
+
+    async fn read(&mut self) -> StateRepresentation {
+        self.localization.lock().await.pose_estimate()
+    }
+
+    async fn write(&mut self, (left, right): (f64, f64)) {
+        self.left_motor.lock().await.set_voltage(left);
+        self.right_motor.lock().await.set_voltage(right);
+    }
+
+    async fn idle(&mut self) {
+        self.write((0.0, 0.0)).await;
+    }
 }
 
-pub struct TankDrive<'a> {
-    controller: &'a Controller,
+#[cfg(feature = "vexide")]
+pub struct TankDrive {
+    controller: SharedController,
 }
 
-impl<'a> TankDrive<'a> {
-    pub fn new(controller: &'a Controller) -> Self {
+#[cfg(feature = "vexide")]
+impl TankDrive {
+    pub fn new(controller: SharedController) -> Self {
         TankDrive { controller }
     }
 }
 
-impl<'a> State<StateRepresentation, (f64, f64)> for TankDrive<'a> {
+#[cfg(feature = "vexide")]
+impl State<StateRepresentation, (f64, f64)> for TankDrive {
     fn update(&mut self, _: &StateRepresentation) -> Option<(f64, f64)> {
+        let controller = self.controller.borrow();
+
         Some((
-            self.controller.left_stick.y().ok()? as f64 * 12.0,
-            self.controller.right_stick.y().ok()? as f64 * 12.0,
+            controller.left_stick.y().ok()? as f64 * 12.0,
+            controller.right_stick.y().ok()? as f64 * 12.0,
         ))
     }
 }
@@ -213,6 +358,58 @@ impl VoltageDrive {
 
 impl State<StateRepresentation, (f64, f64)> for VoltageDrive {
     fn update(&mut self, _: &StateRepresentation) -> Option<(f64, f64)> {
-        Some((self.left_voltage as f64, self.right_voltage as f64))
+        Some((self.left_voltage, self.right_voltage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        config::{wheel_diameter, DRIVE_RATIO},
+        localization::localization::extended_kalman_filter::ExtendedKalmanFilter,
+        sim::{
+            block_on,
+            devices::{SimImu, SimMotor},
+            drive_plant,
+            plant::Side,
+        },
+    };
+
+    #[test]
+    // Shared through `Arc` like on the brain, the simulated devices just aren't `Send`
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn drives_simulated_plant() {
+        let plant = drive_plant();
+        let left = Arc::new(Mutex::new(SimMotor::new(plant.clone(), Side::Left)));
+        let right = Arc::new(Mutex::new(SimMotor::new(plant.clone(), Side::Right)));
+
+        let odometry = block_on(drive_odometry(
+            &left,
+            &right,
+            vec![SimImu::new(plant.clone(), 0.0)],
+            wheel_diameter(),
+            DRIVE_RATIO,
+        ));
+        let mut drivetrain = Drivetrain::new(left, right, ExtendedKalmanFilter::new(odometry));
+        block_on(drivetrain.init_norm(
+            &StateRepresentation::zeros(),
+            &Matrix3::from_diagonal_element(0.01),
+        ));
+
+        // Sleeping moves the simulated clock, so the timeout ends the state
+        let start = Instant::now();
+        block_on(drivetrain.run(VoltageDrive::new(6.0, 6.0).timeout(Duration::from_millis(100))));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        plant.borrow_mut().step(Duration::from_millis(500));
+        block_on(drivetrain.update_localization());
+
+        let pose = plant.borrow().pose();
+        let estimate = block_on(drivetrain.read());
+        assert!(pose.x > 0.1, "{pose}");
+        assert!((estimate - pose).magnitude() < 1e-3, "{estimate} {pose}");
     }
 }
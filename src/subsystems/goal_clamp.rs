@@ -1,13 +1,13 @@
-use core::time::Duration;
-
-use vexide::{
-    core::println,
-    devices::adi::digital::LogicLevel,
-    prelude::{sleep, AdiDigitalOut, Controller},
+use vexide::{core::println, devices::adi::digital::LogicLevel, prelude::AdiDigitalOut};
+
+use crate::{
+    state_machine::{
+        runner::{run_state, Subsystem},
+        State,
+    },
+    subsystems::SharedController,
 };
 
-use crate::state_machine::State;
-
 pub struct GoalClamp {
     adi_solenoid: AdiDigitalOut,
 }
@@ -17,28 +17,35 @@ impl GoalClamp {
         Self { adi_solenoid }
     }
 
-    pub async fn run(&mut self, mut state: impl State<(), LogicLevel>) {
-        state.init();
+    pub async fn run(&mut self, state: impl State<(), LogicLevel>) {
+        run_state(self, state).await;
+    }
+}
+
+impl Subsystem for GoalClamp {
+    type Input = ();
+    type Output = LogicLevel;
 
-        loop {
-            if let Some(command) = state.update(&()) {
-                println!("{:?}", command);
-                self.adi_solenoid.set_level(command).unwrap();
-            } else {
-                return;
-            }
+    async fn read(&mut self) {}
 
-            sleep(Duration::from_millis(10)).await;
-        }
+    async fn write(&mut self, command: LogicLevel) {
+        println!("{:?}", command);
+        self.adi_solenoid.set_level(command).unwrap();
     }
 }
 
-pub struct GoalController<'a> {
-    pub controller: &'a Controller,
+pub struct GoalController {
+    pub controller: SharedController,
 }
 
-impl<'a> State<(), LogicLevel> for GoalController<'a> {
+impl State<(), LogicLevel> for GoalController {
     fn update(&mut self, _: &()) -> Option<LogicLevel> {
-        Some(self.controller.button_a.level().unwrap_or(LogicLevel::High))
+        Some(
+            self.controller
+                .borrow()
+                .button_a
+                .level()
+                .unwrap_or(LogicLevel::High),
+        )
     }
 }
@@ -1,9 +1,10 @@
-use core::time::Duration;
-
 use uom::si::{angle::revolution, f64::Angle};
-use vexide::prelude::{sleep, Motor, Position};
+use vexide::prelude::{Motor, Position};
 
-use crate::state_machine::State;
+use crate::state_machine::{
+    runner::{run_state, Subsystem},
+    State,
+};
 
 pub struct Hook {
     motor: Motor,
@@ -14,20 +15,22 @@ impl Hook {
         Self { motor }
     }
 
-    pub async fn run(&mut self, mut state: impl State<(), f64>) {
-        state.init();
+    pub async fn run(&mut self, state: impl State<(), f64>) {
+        run_state(self, state).await;
+    }
+}
+
+impl Subsystem for Hook {
+    type Input = ();
+    /// Hook position in revolutions.
+    type Output = f64;
 
-        loop {
-            if let Some(position) = state.update(&()) {
-                let _ = self
-                    .motor
-                    .set_position_target(Position::from_revolutions(position), 200);
-            } else {
-                return;
-            }
+    async fn read(&mut self) {}
 
-            sleep(Duration::from_millis(10)).await;
-        }
+    async fn write(&mut self, position: f64) {
+        let _ = self
+            .motor
+            .set_position_target(Position::from_revolutions(position), 200);
     }
 }
 
@@ -1,11 +1,13 @@
-use core::time::Duration;
-
 use uom::si::{angular_velocity::revolution_per_minute, f64::AngularVelocity};
-use vexide::prelude::{sleep, Controller, Motor, Position};
+use vexide::prelude::{Motor, Position};
 
 use crate::{
     config::{INTAKE_RATIO, LIFT_RATIO},
-    state_machine::State,
+    state_machine::{
+        runner::{run_state, Subsystem},
+        State,
+    },
+    subsystems::SharedController,
 };
 
 pub struct Intake {
@@ -36,39 +38,40 @@ impl Intake {
         Self { bottom, top, lift }
     }
 
-    pub async fn run(&mut self, mut state: impl State<f64, IntakeCommand>) {
-        state.init();
-
-        loop {
-            if let Some(command) = state.update(
-                &(self
-                    .top
-                    .position()
-                    .unwrap_or(Position::from_revolutions(0.0))
-                    .as_revolutions()
-                    / INTAKE_RATIO),
-            ) {
-                let _ = self.lift.set_position_target(
-                    Position::from_revolutions(command.lift_position.0 * LIFT_RATIO),
-                    command.lift_position.1,
-                );
-
-                let _ = match command.top_command {
-                    TopCommand::Position(pos) => self
-                        .top
-                        .set_position_target(Position::from_revolutions(pos * INTAKE_RATIO), 600),
-                    TopCommand::Velocity(vel) => self.top.set_velocity(vel),
-                };
-
-                let _ = self
-                    .bottom
-                    .set_velocity(command.bottom_speed.get::<revolution_per_minute>() as i32);
-            } else {
-                return;
-            }
-
-            sleep(Duration::from_millis(10)).await;
-        }
+    pub async fn run(&mut self, state: impl State<f64, IntakeCommand>) {
+        run_state(self, state).await;
+    }
+}
+
+impl Subsystem for Intake {
+    /// Position of the top roller in revolutions.
+    type Input = f64;
+    type Output = IntakeCommand;
+
+    async fn read(&mut self) -> f64 {
+        self.top
+            .position()
+            .unwrap_or(Position::from_revolutions(0.0))
+            .as_revolutions()
+            / INTAKE_RATIO
+    }
+
+    async fn write(&mut self, command: IntakeCommand) {
+        let _ = self.lift.set_position_target(
+            Position::from_revolutions(command.lift_position.0 * LIFT_RATIO),
+            command.lift_position.1,
+        );
+
+        let _ = match command.top_command {
+            TopCommand::Position(pos) => self
+                .top
+                .set_position_target(Position::from_revolutions(pos * INTAKE_RATIO), 600),
+            TopCommand::Velocity(vel) => self.top.set_velocity(vel),
+        };
+
+        let _ = self
+            .bottom
+            .set_velocity(command.bottom_speed.get::<revolution_per_minute>() as i32);
     }
 }
 
@@ -90,49 +93,31 @@ impl State<f64, IntakeCommand> for LoadGoal {
     }
 }
 
-pub struct IntakeManual<'a> {
-    pub controller: &'a mut Controller,
+pub struct IntakeManual {
+    pub controller: SharedController,
     pub lift_pos: f64,
     pub top_pos: f64,
 }
 
-impl<'a> State<f64, IntakeCommand> for IntakeManual<'a> {
+impl State<f64, IntakeCommand> for IntakeManual {
     fn update(&mut self, _: &f64) -> Option<IntakeCommand> {
-        if self
-            .controller
-            .right_trigger_1
-            .was_pressed()
-            .unwrap_or(false)
-        {
+        let mut controller = self.controller.borrow_mut();
+
+        if controller.right_trigger_1.was_pressed().unwrap_or(false) {
             self.top_pos += 1.0;
-        } else if self
-            .controller
-            .right_trigger_2
-            .was_pressed()
-            .unwrap_or(false)
-        {
+        } else if controller.right_trigger_2.was_pressed().unwrap_or(false) {
             self.top_pos -= 1.0;
         }
 
-        if self
-            .controller
-            .left_trigger_1
-            .was_pressed()
-            .unwrap_or(false)
-        {
+        if controller.left_trigger_1.was_pressed().unwrap_or(false) {
             self.lift_pos += 1.0;
-        } else if self
-            .controller
-            .left_trigger_2
-            .was_pressed()
-            .unwrap_or(false)
-        {
+        } else if controller.left_trigger_2.was_pressed().unwrap_or(false) {
             self.lift_pos -= 1.0;
         }
 
         Some(IntakeCommand {
             bottom_speed: AngularVelocity::new::<revolution_per_minute>(
-                (self.controller.right_stick.y().unwrap_or(0.0) * 200.0).into(),
+                (controller.right_stick.y().unwrap_or(0.0) * 200.0).into(),
             ),
             top_command: TopCommand::Position(self.top_pos),
             lift_position: (self.lift_pos, 600),
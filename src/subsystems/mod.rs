@@ -1,4 +1,20 @@
+#[cfg(feature = "vexide")]
+use alloc::rc::Rc;
+#[cfg(feature = "vexide")]
+use core::cell::RefCell;
+
+#[cfg(feature = "vexide")]
+use vexide::prelude::Controller;
+
 pub mod drivetrain;
+#[cfg(feature = "vexide")]
 pub mod goal_clamp;
+#[cfg(feature = "vexide")]
 pub mod hook;
+#[cfg(feature = "vexide")]
 pub mod intake;
+
+/// Controller shared between the states that read it, which outlive any one borrow of the robot
+/// once they're handed to a subsystem runner.
+#[cfg(feature = "vexide")]
+pub type SharedController = Rc<RefCell<Controller>>;
@@ -1,12 +1,35 @@
 use core::f64::consts::{PI, TAU};
 
-use vexide::core::float::Float;
+use nalgebra::Vector2;
+use uom::num_traits::Float;
 
 pub fn normal_pdf(x: f64, mu: f64, sigma: f64) -> f64 {
     let exponent = -(x - mu) * (x - mu) / (2.0 * sigma * sigma);
     (1.0 / (sigma * (2.0 * PI).sqrt())) * exponent.exp()
 }
 
+/// Natural log of [`normal_pdf`], without underflowing far out in the tails.
+pub fn normal_log_pdf(x: f64, mu: f64, sigma: f64) -> f64 {
+    -(x - mu) * (x - mu) / (2.0 * sigma * sigma) - (sigma * (2.0 * PI).sqrt()).ln()
+}
+
+/// `ln(sum(exp(x)))` computed relative to the largest value so it doesn't overflow or underflow.
+///
+/// Returns negative infinity for an empty slice or if every value is negative infinity.
+pub fn log_sum_exp(values: &[f64]) -> f64 {
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+    if !max.is_finite() {
+        return max;
+    }
+
+    max + values
+        .iter()
+        .map(|value| (value - max).exp())
+        .sum::<f64>()
+        .ln()
+}
+
 /// Finds the minimum signed distance between 2 angles
 ///
 /// # Examples
@@ -18,7 +41,68 @@ pub fn normal_pdf(x: f64, mu: f64, sigma: f64) -> f64 {
 /// let difference = angle_difference(angle1, angle2);
 /// ```
 pub fn angle_difference(x: f64, y: f64) -> f64 {
-    (x - y + PI) % TAU - PI
+    // `%` keeps the sign of the dividend, so wrap negative remainders back into [0, TAU)
+    let wrapped = (x - y + PI) % TAU;
+
+    if wrapped < 0.0 {
+        wrapped + TAU - PI
+    } else {
+        wrapped - PI
+    }
+}
+
+/// Wraps an angle into [0, TAU).
+pub fn wrap_angle(angle: f64) -> f64 {
+    let wrapped = angle % TAU;
+
+    if wrapped < 0.0 {
+        wrapped + TAU
+    } else {
+        wrapped
+    }
+}
+
+/// Distance along a ray from `origin` towards `direction` to the segment between `a` and `b`,
+/// `None` if the ray misses the segment.
+pub fn ray_segment_intersection(
+    origin: &Vector2<f64>,
+    direction: &Vector2<f64>,
+    a: &Vector2<f64>,
+    b: &Vector2<f64>,
+) -> Option<f64> {
+    let segment = b - a;
+    let denominator = direction.perp(&segment);
+
+    // Parallel lines never intersect
+    if denominator.abs() < f64::EPSILON {
+        return None;
+    }
+
+    let offset = a - origin;
+
+    // origin + t * direction = a + u * segment
+    let t = offset.perp(&segment) / denominator;
+    let u = offset.perp(direction) / denominator;
+
+    if t >= 0.0 && (0.0..=1.0).contains(&u) {
+        Some(t * direction.magnitude())
+    } else {
+        None
+    }
+}
+
+/// Shortest distance from `point` to the segment between `a` and `b`.
+pub fn point_segment_distance(point: &Vector2<f64>, a: &Vector2<f64>, b: &Vector2<f64>) -> f64 {
+    let segment = b - a;
+    let length_squared = segment.magnitude_squared();
+
+    if length_squared == 0.0 {
+        return (point - a).magnitude();
+    }
+
+    let t = ((point - a).dot(&segment) / length_squared).clamp(0.0, 1.0);
+
+    (point - (a + segment * t)).magnitude()
 }
 
 // pub fn normal_pdf_vec2(x: &Vector2<f64>, mean: &Vector2<f64>, covariance: &Matrix2<f64>) -> f64 {